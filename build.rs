@@ -1,7 +1,15 @@
 use winres;
 
 fn main() {
-    if cfg!(target_os = "windows") {
+    // 存在锁文件时才将其编入程序，见resources.rs
+    println!("cargo:rerun-if-changed=requirements/tgba.lock");
+    println!("cargo:rustc-check-cfg=cfg(tgba_lock)");
+    if std::path::Path::new("requirements/tgba.lock").is_file() {
+        println!("cargo:rustc-cfg=tgba_lock");
+    }
+
+    // 构建脚本在宿主平台上运行，需按编译目标平台判断
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("windows") {
         let mut res = winres::WindowsResource::new();
         res.set_icon(r#"resources\tgba-jupyterlab-48x48.ico"#);
         res.compile().unwrap();
@@ -1,6 +1,9 @@
+use pep508_rs::Requirement;
+use serde_derive::Deserialize;
 use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DownloadingErrorKind {
     ServerError,
     NotFound,
@@ -43,6 +46,10 @@ impl DownloadingError {
             message,
         }
     }
+
+    pub fn kind(&self) -> DownloadingErrorKind {
+        self.kind
+    }
 }
 
 impl std::fmt::Display for DownloadingError {
@@ -59,3 +66,99 @@ impl std::fmt::Display for DownloadingError {
         write!(f, "{kind_name}：{}", self.message)
     }
 }
+
+/// 批量下载结束后仍未能下载的程序包
+#[derive(Error, Debug)]
+pub struct DownloadingFailures {
+    failures: Vec<(Requirement, DownloadingError)>,
+}
+
+impl DownloadingFailures {
+    pub fn new(failures: Vec<(Requirement, DownloadingError)>) -> Self {
+        DownloadingFailures { failures }
+    }
+
+    pub fn failures(&self) -> &[(Requirement, DownloadingError)] {
+        &self.failures
+    }
+
+    pub fn requirements(&self) -> Vec<Requirement> {
+        self.failures.iter().map(|(req, _)| req.clone()).collect()
+    }
+}
+
+impl std::fmt::Display for DownloadingFailures {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "共有{}个程序包下载失败:", self.failures.len())?;
+        for (requirement, err) in &self.failures {
+            writeln!(f, "{}: {}", requirement.name, err)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallingErrorKind {
+    NotFound,    // 下载目录中没有满足需求的程序包
+    BuildFailed, // 从源码包构建wheel失败
+    Conflict,    // 依赖的版本相互冲突
+    Io,          // 读写文件错误
+    Other,
+}
+
+/// 离线安装阶段的错误，尽可能指明出错的程序包
+#[derive(Error, Debug)]
+pub struct InstallingError {
+    kind: InstallingErrorKind,
+    package: Option<String>,
+    message: String,
+}
+
+impl InstallingError {
+    pub fn new(kind: InstallingErrorKind, package: Option<String>, message: String) -> Self {
+        InstallingError {
+            kind,
+            package,
+            message,
+        }
+    }
+
+    pub fn other(err: impl std::fmt::Display) -> Self {
+        InstallingError {
+            kind: InstallingErrorKind::Other,
+            package: None,
+            message: err.to_string(),
+        }
+    }
+
+    pub fn kind(&self) -> InstallingErrorKind {
+        self.kind
+    }
+
+    pub fn package(&self) -> Option<&str> {
+        self.package.as_deref()
+    }
+}
+
+impl std::fmt::Display for InstallingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind_name = match self.kind {
+            InstallingErrorKind::NotFound => "找不到适合的程序包",
+            InstallingErrorKind::BuildFailed => "构建wheel失败",
+            InstallingErrorKind::Conflict => "依赖版本冲突",
+            InstallingErrorKind::Io => "读写文件错误",
+            InstallingErrorKind::Other => {
+                return match &self.package {
+                    Some(package) => write!(f, "安装{package}错误：{}", self.message),
+                    None => write!(f, "{}", self.message),
+                };
+            }
+        };
+
+        match &self.package {
+            Some(package) => write!(f, "安装{package}时{kind_name}：{}", self.message),
+            None => write!(f, "{kind_name}：{}", self.message),
+        }
+    }
+}
@@ -2,6 +2,7 @@
 // 在debug模式下终端显示print，发行版不显示终端窗口
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+pub mod cli;
 pub mod dialog;
 pub mod errors;
 pub mod myapp;
@@ -19,7 +20,7 @@ fn init_log(prog: &str) -> Result<()> {
     use log::LevelFilter;
     use log4rs::append::console::ConsoleAppender;
     use log4rs::append::file::FileAppender;
-    use log4rs::config::{Appender, Config, Root};
+    use log4rs::config::{Appender, Config, Logger, Root};
     use log4rs::encode::pattern::PatternEncoder;
 
     let mut log_path: std::path::PathBuf = std::env::current_exe()?;
@@ -37,6 +38,13 @@ fn init_log(prog: &str) -> Result<()> {
     let config = Config::builder()
         .appender(Appender::builder().build("stdout", Box::new(stdout)))
         .appender(Appender::builder().build("logfile", Box::new(logfile)))
+        // 命令行状态信息已直接输出到终端，只需记入日志文件
+        .logger(
+            Logger::builder()
+                .appender("logfile")
+                .additive(false)
+                .build(cli::CONSOLE_LOG_TARGET, LevelFilter::Info),
+        )
         .build(
             Root::builder()
                 .appender("stdout")
@@ -78,7 +86,41 @@ async fn main() -> Result<()> {
                 .action(clap::ArgAction::SetTrue)
                 .help("python 3.8"),
         )
+        .subcommand(cli::install_command())
+        .subcommand(cli::resolve_command())
+        .subcommand(cli::lock_command())
+        .subcommand(cli::download_command())
+        .subcommand(cli::bundle_command())
         .get_matches();
+
+    if let Some(subcommand) = args.subcommand() {
+        cli::attach_parent_console();
+        let code = match subcommand {
+            ("install", sub_args) => {
+                log::info!("start installing in command line");
+                cli::run_install(cli::InstallArgs::from_matches(sub_args)).await
+            }
+            ("resolve", sub_args) => {
+                log::info!("start resolving requirements in command line");
+                cli::run_resolve(cli::ResolveArgs::from_matches(sub_args)).await
+            }
+            ("lock", sub_args) => {
+                log::info!("start locking requirements in command line");
+                cli::run_lock(cli::LockArgs::from_matches(sub_args)).await
+            }
+            ("download", sub_args) => {
+                log::info!("start downloading packages for another target in command line");
+                cli::run_download(cli::DownloadArgs::from_matches(sub_args)).await
+            }
+            ("bundle", sub_args) => {
+                log::info!("start creating offline bundle in command line");
+                cli::run_bundle(cli::BundleArgs::from_matches(sub_args)).await
+            }
+            (name, _) => unreachable!("unknown subcommand {}", name),
+        };
+        std::process::exit(code as i32);
+    }
+
     let flag_legacy_py38 = args.get_flag("py38");
 
     log::info!("start creating app");
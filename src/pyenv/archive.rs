@@ -1,123 +1,677 @@
 use anyhow::{bail, Result};
-use sha2::{Digest, Sha256};
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
 
-pub fn unpack_archive(extension: &str, bytes: &[u8], dest: &Path) -> Result<()> {
-    match extension {
-        ".zip" => unpack_zip(bytes, dest)?,
-        ".tar.zstd" => {
-            unpack_tar(zstd::stream::read::Decoder::with_buffer(bytes)?, dest)?;
+/// 解压后的总字节数上限，防止压缩炸弹
+const MAX_UNPACKED_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// 压缩包中的条目数上限
+const MAX_ENTRIES: usize = 200_000;
+
+/// zip中符号链接的目标路径长度上限
+const MAX_LINK_TARGET_LEN: u64 = 4096;
+
+/// 支持的tar包扩展名（含压缩格式），.tar.lz为lzip格式，无法解压，不在其列
+static TAR_EXTENSIONS: [&str; 12] = [
+    ".tar",
+    ".tar.gz",
+    ".tgz",
+    ".tar.bz2",
+    ".tbz",
+    ".tar.zst",
+    ".tar.zstd",
+    ".tzst",
+    ".tar.xz",
+    ".txz",
+    ".tar.lzma",
+    ".tlz",
+];
+
+/// 扩展名（不区分大小写）是否为可以解压的tar包
+pub fn is_tar_extension(extension: &str) -> bool {
+    TAR_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+/// 从压缩包文件边读取边解压，不将整个压缩包读入内存。
+///
+/// strip_components为去掉的路径前缀层数，与`tar --strip-components`相同。
+/// 先解压到dest旁的临时目录，全部完成后再整体移动为dest，解压失败时不留下不完整的目录。
+/// 路径或链接超出解压目录、解压后总大小或条目数超过上限时报错
+pub fn unpack_archive(
+    extension: &str,
+    archive_path: &Path,
+    dest: &Path,
+    strip_components: usize,
+) -> Result<()> {
+    unpack_archive_with_budget(
+        extension,
+        archive_path,
+        dest,
+        strip_components,
+        UnpackBudget::default(),
+    )
+}
+
+fn unpack_archive_with_budget(
+    extension: &str,
+    archive_path: &Path,
+    dest: &Path,
+    strip_components: usize,
+    mut budget: UnpackBudget,
+) -> Result<()> {
+    let extension = extension.to_lowercase();
+    if extension != ".zip" && !is_tar_extension(&extension) {
+        bail!("unknown archive extension: '{}'", extension);
+    }
+
+    let Some(parent) = dest.parent() else {
+        bail!("解压目录{}没有上级目录", dest.display())
+    };
+    std::fs::create_dir_all(parent)?;
+
+    // 与dest在同一目录下，保证可以直接改名
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".unpacking-")
+        .tempdir_in(parent)?;
+
+    let file = File::open(archive_path)?;
+    if extension == ".zip" {
+        unpack_zip(file, temp_dir.path(), strip_components, &mut budget)?;
+    } else {
+        let reader = open_tar(&extension, file)?;
+        unpack_tar(reader, temp_dir.path(), strip_components, &mut budget)?;
+    }
+
+    log::info!(
+        "{}解压完成: {}个条目，{}字节",
+        archive_path.display(),
+        budget.entries,
+        budget.bytes
+    );
+
+    replace_dir(temp_dir, dest)
+}
+
+/// 按扩展名选择解压tar包的解码器
+pub fn open_tar(extension: &str, file: File) -> Result<Box<dyn Read>> {
+    let reader = BufReader::new(file);
+
+    Ok(match extension.to_lowercase().as_str() {
+        ".tar" => Box::new(reader),
+        ".tar.gz" | ".tgz" => Box::new(flate2::bufread::GzDecoder::new(reader)),
+        ".tar.bz2" | ".tbz" => Box::new(bzip2::bufread::BzDecoder::new(reader)),
+        ".tar.zst" | ".tar.zstd" | ".tzst" => {
+            Box::new(zstd::stream::read::Decoder::with_buffer(reader)?)
+        }
+        ".tar.xz" | ".txz" => Box::new(xz2::bufread::XzDecoder::new(reader)),
+        ".tar.lzma" | ".tlz" => {
+            let stream = xz2::stream::Stream::new_lzma_decoder(u64::MAX)?;
+            Box::new(xz2::bufread::XzDecoder::new_stream(reader, stream))
+        }
+        // .lz为lzip格式，与lzma的文件头不同，liblzma不能解码
+        ".tar.lz" => bail!("不支持lzip格式的压缩包: '{}'", extension),
+        _ => bail!("unknown archive extension: '{}'", extension),
+    })
+}
+
+/// 以解压完成的临时目录替换dest，原有的dest先改名备份，替换成功后删除
+fn replace_dir(temp_dir: tempfile::TempDir, dest: &Path) -> Result<()> {
+    if !dest.exists() {
+        std::fs::rename(temp_dir.path(), dest)?;
+        let _ = temp_dir.into_path();
+        return Ok(());
+    }
+
+    let file_name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let backup = dest.with_file_name(format!(".{}.old", file_name));
+    if backup.exists() {
+        std::fs::remove_dir_all(&backup)?;
+    }
+
+    std::fs::rename(dest, &backup)?;
+    if let Err(err) = std::fs::rename(temp_dir.path(), dest) {
+        let _ = std::fs::rename(&backup, dest);
+        bail!("移动解压目录到{}错误: {}", dest.display(), err);
+    }
+    let _ = temp_dir.into_path();
+
+    if let Err(err) = std::fs::remove_dir_all(&backup) {
+        log::warn!("删除原目录{}错误: {}", backup.display(), err);
+    }
+
+    Ok(())
+}
+
+/// 已解压的条目数和字节数，超过上限时报错
+struct UnpackBudget {
+    entries: usize,
+    bytes: u64,
+    max_entries: usize,
+    max_bytes: u64,
+}
+
+impl Default for UnpackBudget {
+    fn default() -> Self {
+        UnpackBudget::new(MAX_ENTRIES, MAX_UNPACKED_BYTES)
+    }
+}
+
+impl UnpackBudget {
+    fn new(max_entries: usize, max_bytes: u64) -> Self {
+        UnpackBudget {
+            entries: 0,
+            bytes: 0,
+            max_entries,
+            max_bytes,
         }
-        ".tar.gz" | ".tgz" => {
-            unpack_tar(flate2::bufread::GzDecoder::new(bytes), dest)?;
+    }
+
+    fn add_entry(&mut self) -> Result<()> {
+        self.entries += 1;
+        if self.entries > self.max_entries {
+            bail!("压缩包中的条目超过{}个", self.max_entries);
         }
-        ".tar.bz2" | ".tbz" => {
-            unpack_tar(bzip2::bufread::BzDecoder::new(bytes), dest)?;
+
+        Ok(())
+    }
+
+    /// 按实际解压出的字节计数，不采信压缩包中声明的大小
+    fn copy_to_file(&mut self, reader: &mut impl Read, path: &Path) -> Result<()> {
+        // 已有的文件或链接先删除，避免经由链接写到别处
+        if path.symlink_metadata().is_ok() {
+            std::fs::remove_file(path)?;
         }
-        ".tar.xz" | ".txz" | ".tlz" | ".tar.lz" | ".tar.lzma" => {
-            bail!("unimplemented archive extension: '{}'", extension);
+
+        let remaining = self.max_bytes - self.bytes;
+        let mut file = File::create(path)?;
+        let n = std::io::copy(&mut reader.take(remaining + 1), &mut file)?;
+
+        self.bytes += n;
+        if self.bytes > self.max_bytes {
+            bail!("解压后的大小超过{}字节", self.max_bytes);
         }
-        _ => {
-            bail!("unknown archive extension: '{}'", extension);
+
+        Ok(())
+    }
+}
+
+/// 去掉前strip_components层后的相对路径，路径本身被去掉时返回None。
+/// 含`..`、根目录或盘符的路径报错
+fn stripped_path(name: &Path, strip_components: usize) -> Result<Option<PathBuf>> {
+    let mut parts = Vec::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => parts.push(part),
+            Component::CurDir => {}
+            _ => bail!("压缩包中的路径{}超出解压目录", name.display()),
+        }
+    }
+
+    if parts.len() <= strip_components {
+        return Ok(None);
+    }
+
+    Ok(Some(parts[strip_components..].iter().collect()))
+}
+
+/// 创建目录，并确认目录（解析链接后）仍在解压目录内，返回解析后的目录
+fn prepare_dir(root: &Path, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+
+    let resolved = dir.canonicalize()?;
+    if !resolved.starts_with(root) {
+        bail!("压缩包中的路径{}经由链接超出解压目录", dir.display());
+    }
+
+    Ok(resolved)
+}
+
+fn prepare_parent(root: &Path, path: &Path) -> Result<PathBuf> {
+    let Some(parent) = path.parent() else {
+        bail!("路径{}没有上级目录", path.display())
+    };
+
+    prepare_dir(root, parent)
+}
+
+/// 符号链接的目标相对于链接所在目录（已解析链接）逐段解析，每一步都必须在解压目录内。
+///
+/// 经由已解压出的链接的目标报错，否则链接串联后可以超出解压目录。
+/// `..`只能从已存在的真实目录返回上级，否则之后在该位置创建的链接会改变解析结果
+fn check_link_target(root: &Path, parent: &Path, target: &Path) -> Result<()> {
+    let mut resolved = parent.to_path_buf();
+    for component in target.components() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                let is_link = resolved
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink());
+                if is_link {
+                    bail!("链接目标{}经由已有的链接", target.display());
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                let is_dir = resolved
+                    .symlink_metadata()
+                    .is_ok_and(|metadata| metadata.is_dir());
+                if !is_dir {
+                    bail!("链接目标{}经由不存在的目录返回上级", target.display());
+                }
+                resolved.pop();
+            }
+            _ => bail!("链接目标{}为绝对路径", target.display()),
+        }
+
+        if !resolved.starts_with(root) {
+            bail!("链接目标{}超出解压目录", target.display());
         }
     }
 
     Ok(())
 }
 
+#[cfg(unix)]
+fn create_symlink(target: &Path, path: &Path) -> Result<()> {
+    if path.symlink_metadata().is_ok() {
+        std::fs::remove_file(path)?;
+    }
+    std::os::unix::fs::symlink(target, path)?;
 
+    Ok(())
+}
+
+/// Windows上创建符号链接需要管理员权限，跳过
+#[cfg(not(unix))]
+fn create_symlink(target: &Path, path: &Path) -> Result<()> {
+    log::warn!("跳过符号链接{} -> {}", path.display(), target.display());
+
+    Ok(())
+}
 
-fn unpack_zip(bytes: &[u8], dest: &Path) -> Result<()> {
-    use std::io::Cursor;
+/// 保留可执行位，去掉setuid/setgid/sticky位和组、其他用户的写权限，所有者总可读写
+#[cfg(unix)]
+fn set_sanitized_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = (mode & 0o755) | 0o600;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+
+    Ok(())
+}
+
+/// Windows只有只读属性，解压出的文件保持可写
+#[cfg(not(unix))]
+fn set_sanitized_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+fn unpack_zip(
+    file: File,
+    dest: &Path,
+    strip_components: usize,
+    budget: &mut UnpackBudget,
+) -> Result<()> {
     use zip::read::ZipArchive;
 
-    let strip_components = 1;
+    let root = dest.canonicalize()?;
+
+    let mut archive = ZipArchive::new(BufReader::new(file))?;
+    if archive.len() > budget.max_entries {
+        bail!("压缩包中的条目超过{}个", budget.max_entries);
+    }
 
-    let mut archive = ZipArchive::new(Cursor::new(bytes))?;
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
+        budget.add_entry()?;
 
         let Some(name) = file.enclosed_name() else {
-            bail!("Invalid file path in zip")
+            bail!("压缩包中的路径{}超出解压目录", file.name())
         };
+        let Some(relative) = stripped_path(name, strip_components)? else {
+            continue;
+        };
+        let path = root.join(relative);
 
-        let mut components = name.components();
-        for _ in 0..strip_components {
-            components.next();
+        if file.is_dir() {
+            prepare_dir(&root, &path)?;
+            continue;
         }
 
-        let path = dest.join(components.as_path());
-        if path != Path::new("") && path.strip_prefix(dest).is_ok() {
-            if file.name().ends_with('/') {
-                std::fs::create_dir_all(&path)?;
-            } else {
-                if let Some(p) = path.parent() {
-                    if !p.exists() {
-                        std::fs::create_dir_all(p)?;
-                    }
-                }
-                std::io::copy(&mut file, &mut std::fs::File::create(&path)?)?;
-            }
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                if let Some(mode) = file.unix_mode() {
-                    fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
-                }
-            }
+        let parent = prepare_parent(&root, &path)?;
+        let mode = file.unix_mode();
+
+        // unix_mode的文件类型为S_IFLNK时，内容为链接目标
+        if mode.is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            let mut target = String::new();
+            (&mut file)
+                .take(MAX_LINK_TARGET_LEN)
+                .read_to_string(&mut target)?;
+            let target = PathBuf::from(target);
+            check_link_target(&root, &parent, &target)?;
+            create_symlink(&target, &path)?;
+            continue;
+        }
+
+        budget.copy_to_file(&mut file, &path)?;
+        if let Some(mode) = mode {
+            set_sanitized_mode(&path, mode)?;
         }
     }
 
     Ok(())
 }
 
-fn unpack_tar<R: std::io::Read>(obj: R, dest: &Path) -> Result<()> {
-    let strip_components = 1;
+fn unpack_tar<R: std::io::Read>(
+    obj: R,
+    dest: &Path,
+    strip_components: usize,
+    budget: &mut UnpackBudget,
+) -> Result<()> {
+    use tar::EntryType;
+
+    let root = dest.canonicalize()?;
 
     let mut archive = tar::Archive::new(obj);
 
     for entry in archive.entries()? {
         let mut entry = entry?;
-        let name = entry.path()?;
-
-        let mut components = name.components();
+        budget.add_entry()?;
 
-        for _ in 0..strip_components {
-            components.next();
-        }
-
-        let dst_path = dest.join(components.as_path());
+        let name = entry.path()?.to_path_buf();
+        let Some(relative) = stripped_path(&name, strip_components)? else {
+            continue;
+        };
+        let path = root.join(relative);
 
-        // only unpack if it's save to do so
-        if dst_path != Path::new("") && dst_path.strip_prefix(dest).is_ok() {
-            if let Some(dir) = dst_path.parent() {
-                std::fs::create_dir_all(dir).ok();
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            EntryType::Directory => {
+                prepare_dir(&root, &path)?;
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                prepare_parent(&root, &path)?;
+                budget.copy_to_file(&mut entry, &path)?;
+                set_sanitized_mode(&path, entry.header().mode()?)?;
+            }
+            EntryType::Symlink => {
+                let Some(target) = entry.link_name()? else {
+                    bail!("符号链接{}没有目标", name.display())
+                };
+                let parent = prepare_parent(&root, &path)?;
+                check_link_target(&root, &parent, &target)?;
+                create_symlink(&target, &path)?;
+            }
+            EntryType::Link => {
+                // 硬链接的目标是压缩包中的另一个路径
+                let Some(target) = entry.link_name()? else {
+                    bail!("硬链接{}没有目标", name.display())
+                };
+                let Some(target) = stripped_path(&target, strip_components)? else {
+                    bail!(
+                        "硬链接{}的目标{}超出解压目录",
+                        name.display(),
+                        target.display()
+                    )
+                };
+                prepare_parent(&root, &path)?;
+                let target = root.join(target);
+                prepare_parent(&root, &target)?;
+                if path.symlink_metadata().is_ok() {
+                    std::fs::remove_file(&path)?;
+                }
+                std::fs::hard_link(&target, &path)?;
+            }
+            _ => {
+                log::warn!("跳过压缩包中的{:?}类型条目{}", entry_type, name.display());
             }
-            entry.unpack(&dst_path)?;
         }
     }
 
     Ok(())
 }
 
-pub fn checksum(method: &str, content: &[u8], hexcode:&str) -> Result<bool> {
-    match method.to_lowercase().as_str() {
-        "sha256" => Ok(sha256_checksum(content, hexcode)),
-        _ => bail!("不支持checksum方法: {}", method)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tar::{EntryType, Header};
+    use zip::write::{FileOptions, ZipWriter};
+    use zip::CompressionMethod;
+
+    /// tar条目：名称、类型、文件内容或链接目标
+    type TarEntry<'a> = (&'a str, EntryType, &'a str);
+
+    /// 名称和链接目标按原样写入，tar::Builder会拒绝`..`和绝对路径
+    fn write_tar(path: &Path, entries: &[TarEntry]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for (name, entry_type, content) in entries {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+
+            let data = match entry_type {
+                EntryType::Symlink | EntryType::Link => {
+                    header.set_link_name_literal(content).unwrap();
+                    ""
+                }
+                _ => *content,
+            };
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
     }
-}
 
-/// Takes a bytes slice and compares it to a given string checksum.
-fn sha256_checksum(content: &[u8], hexcode: &str) -> bool {
-    let mut hasher = Sha256::new();
-    hasher.update(content);
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
 
-    let digest = hasher.finalize();
-    
-    let digest = hex::encode(digest);
-    if !digest.eq_ignore_ascii_case(hexcode) {
-        false
-    } else {
-        true
+    fn unpack_tar_entries(entries: &[TarEntry]) -> (tempfile::TempDir, Result<()>) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("pkg.tar");
+        write_tar(&archive_path, entries);
+
+        let dest = temp_dir.path().join("out").join("pkg");
+        let result = unpack_archive(".tar", &archive_path, &dest, 0);
+        (temp_dir, result)
+    }
+
+    #[test]
+    fn tar_symlink_escaping_destination_is_rejected() {
+        for target in ["../../outside", "/etc/passwd"] {
+            let entries = [
+                ("pkg/a.txt", EntryType::Regular, "a"),
+                ("pkg/link", EntryType::Symlink, target),
+            ];
+            let (temp_dir, result) = unpack_tar_entries(&entries);
+
+            assert!(result.is_err(), "{target}");
+            assert!(!temp_dir.path().join("out").join("pkg").exists());
+        }
+    }
+
+    #[test]
+    fn tar_chained_symlinks_escaping_destination_are_rejected() {
+        // 单独看y的目标为pkg/x/..，即pkg，经由x实际指向解压目录的上级
+        let chained = [
+            ("pkg/x", EntryType::Symlink, "."),
+            ("pkg/y", EntryType::Symlink, "x/.."),
+        ];
+        // 先创建y时x还不存在，之后创建的x同样使y超出解压目录
+        let reversed = [
+            ("pkg/y", EntryType::Symlink, "x/.."),
+            ("pkg/x", EntryType::Symlink, "."),
+        ];
+        for entries in [chained, reversed] {
+            let (temp_dir, result) = unpack_tar_entries(&entries);
+
+            assert!(result.is_err());
+            assert!(!temp_dir.path().join("out").join("pkg").exists());
+        }
+    }
+
+    #[test]
+    fn tar_symlink_inside_destination_is_unpacked() {
+        let entries = [
+            ("pkg/a.txt", EntryType::Regular, "a"),
+            ("pkg/sub/link", EntryType::Symlink, "../a.txt"),
+        ];
+        let (temp_dir, result) = unpack_tar_entries(&entries);
+
+        result.unwrap();
+        let dest = temp_dir.path().join("out").join("pkg");
+        assert!(dest.join("pkg/sub").is_dir());
+        // Windows上跳过符号链接
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_to_string(dest.join("pkg/sub/link")).unwrap(),
+            "a"
+        );
+    }
+
+    #[test]
+    fn tar_hardlink_escaping_destination_is_rejected() {
+        let entries = [
+            ("pkg/a.txt", EntryType::Regular, "a"),
+            ("pkg/link", EntryType::Link, "../outside"),
+        ];
+        let (temp_dir, result) = unpack_tar_entries(&entries);
+
+        assert!(result.is_err());
+        assert!(!temp_dir.path().join("out").join("pkg").exists());
+    }
+
+    #[test]
+    fn tar_hardlink_inside_destination_is_unpacked() {
+        let entries = [
+            ("pkg/a.txt", EntryType::Regular, "a"),
+            ("pkg/b.txt", EntryType::Link, "pkg/a.txt"),
+        ];
+        let (temp_dir, result) = unpack_tar_entries(&entries);
+
+        result.unwrap();
+        let dest = temp_dir.path().join("out").join("pkg");
+        assert_eq!(
+            std::fs::read_to_string(dest.join("pkg/b.txt")).unwrap(),
+            "a"
+        );
+    }
+
+    #[test]
+    fn parent_and_absolute_entry_names_are_rejected() {
+        for name in ["../evil.txt", "pkg/../../evil.txt", "/tmp/evil.txt"] {
+            let (temp_dir, result) = unpack_tar_entries(&[(name, EntryType::Regular, "evil")]);
+            assert!(result.is_err(), "tar: {name}");
+            assert!(!temp_dir.path().join("evil.txt").exists());
+
+            let archive_path = temp_dir.path().join("pkg.zip");
+            write_zip(&archive_path, &[(name, "evil")]);
+            let dest = temp_dir.path().join("out").join("pkg");
+            assert!(
+                unpack_archive(".zip", &archive_path, &dest, 0).is_err(),
+                "zip: {name}"
+            );
+            assert!(!temp_dir.path().join("evil.txt").exists());
+        }
+    }
+
+    #[test]
+    fn entry_count_over_limit_is_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let files = [("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")];
+        let dest = temp_dir.path().join("out");
+
+        let tar_path = temp_dir.path().join("pkg.tar");
+        let entries: Vec<TarEntry> = files
+            .iter()
+            .map(|(name, content)| (*name, EntryType::Regular, *content))
+            .collect();
+        write_tar(&tar_path, &entries);
+        let zip_path = temp_dir.path().join("pkg.zip");
+        write_zip(&zip_path, &files);
+
+        for (extension, path) in [(".tar", &tar_path), (".zip", &zip_path)] {
+            let budget = UnpackBudget::new(2, 1024);
+            let result = unpack_archive_with_budget(extension, path, &dest, 0, budget);
+            assert!(result.is_err(), "{extension}");
+
+            let budget = UnpackBudget::new(3, 1024);
+            unpack_archive_with_budget(extension, path, &dest, 0, budget).unwrap();
+        }
+    }
+
+    #[test]
+    fn unpacked_bytes_over_limit_are_rejected() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let files = [("a.txt", "0123456789"), ("b.txt", "0123456789")];
+        let dest = temp_dir.path().join("out");
+
+        let tar_path = temp_dir.path().join("pkg.tar");
+        let entries: Vec<TarEntry> = files
+            .iter()
+            .map(|(name, content)| (*name, EntryType::Regular, *content))
+            .collect();
+        write_tar(&tar_path, &entries);
+        let zip_path = temp_dir.path().join("pkg.zip");
+        write_zip(&zip_path, &files);
+
+        for (extension, path) in [(".tar", &tar_path), (".zip", &zip_path)] {
+            let budget = UnpackBudget::new(10, 19);
+            let result = unpack_archive_with_budget(extension, path, &dest, 0, budget);
+            assert!(result.is_err(), "{extension}");
+
+            let budget = UnpackBudget::new(10, 20);
+            unpack_archive_with_budget(extension, path, &dest, 0, budget).unwrap();
+        }
+    }
+
+    #[test]
+    fn failed_unpack_keeps_existing_destination() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let parent = temp_dir.path().join("out");
+        let dest = parent.join("pkg");
+        std::fs::create_dir_all(&dest).unwrap();
+        std::fs::write(dest.join("old.txt"), "old").unwrap();
+
+        let archive_path = temp_dir.path().join("pkg.tar");
+        let entries = [
+            ("pkg/new.txt", EntryType::Regular, "new"),
+            ("../evil.txt", EntryType::Regular, "evil"),
+        ];
+        write_tar(&archive_path, &entries);
+
+        assert!(unpack_archive(".tar", &archive_path, &dest, 1).is_err());
+        assert_eq!(
+            std::fs::read_to_string(dest.join("old.txt")).unwrap(),
+            "old"
+        );
+        assert!(!dest.join("new.txt").exists());
+
+        // 临时解压目录已删除，只剩原有的目录
+        let names: Vec<_> = std::fs::read_dir(&parent)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["pkg"]);
+
+        // 解压成功时替换原有的目录
+        write_tar(&archive_path, &[("pkg/new.txt", EntryType::Regular, "new")]);
+        unpack_archive(".tar", &archive_path, &dest, 1).unwrap();
+        assert!(!dest.join("old.txt").exists());
+        assert_eq!(
+            std::fs::read_to_string(dest.join("new.txt")).unwrap(),
+            "new"
+        );
     }
 }
@@ -0,0 +1,602 @@
+use anyhow::{bail, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::super::resources::RESOURCES;
+use super::super::status::StatusUpdate;
+use super::archive::is_tar_extension;
+use super::checksum::{checksum_file, Hasher};
+use super::installer::Installer;
+use super::requirements::get_requirements_path;
+use super::utils::{canonicalize_name, split_filename_extension};
+use super::venv::download_python_dist;
+
+/// 离线安装包格式版本
+const BUNDLE_VERSION: u32 = 1;
+
+/// 离线安装包中的清单文件，记录其它各文件的大小和sha256
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// 离线安装包中CPython安装包和程序包所在的目录
+const CPYTHON_DIR: &str = "cpython/";
+const PACKAGES_DIR: &str = "packages/";
+
+/// 制作离线安装包时安装程序内置的配置文件和锁文件
+const CONFIG_NAME: &str = "config.toml";
+const LOCK_NAME: &str = "tgba.lock";
+
+/// 与安装程序放在同一目录时自动采用的离线安装包
+pub const DEFAULT_BUNDLE_NAME: &str = "tgba-bundle.zip";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BundleManifest {
+    version: u32,
+    python_version: String,
+    python_version_full: String,
+    target: String,
+    platform_tag: String,
+    cpython: String,
+    requirements: String,
+    files: Vec<BundleFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BundleFile {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+/// 离线安装包：一个zip文件，包括CPython安装包、为需求选定的程序包、需求文件、
+/// 制作时的锁文件和配置文件及记录各文件sha256的清单。
+///
+/// 从离线安装包安装时，CPython和程序包都从包中解出并按清单校验，不访问网络
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    path: PathBuf,
+    manifest: BundleManifest,
+}
+
+impl Bundle {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut archive = open_zip(path)?;
+
+        let mut text = String::new();
+        match archive.by_name(MANIFEST_NAME) {
+            Ok(mut entry) => entry.read_to_string(&mut text)?,
+            Err(err) => bail!(
+                "离线安装包{}中没有{}: {}",
+                path.display(),
+                MANIFEST_NAME,
+                err
+            ),
+        };
+
+        let manifest: BundleManifest = match serde_json::from_str(&text) {
+            Ok(manifest) => manifest,
+            Err(err) => bail!("离线安装包{}的清单格式错误: {}", path.display(), err),
+        };
+
+        if manifest.version != BUNDLE_VERSION {
+            bail!("不支持的离线安装包版本: {}", manifest.version);
+        }
+
+        for name in [&manifest.cpython, &manifest.requirements] {
+            if manifest.find(name).is_none() {
+                bail!("离线安装包{}的清单中没有{}", path.display(), name);
+            }
+        }
+
+        Ok(Bundle {
+            path: path.to_path_buf(),
+            manifest,
+        })
+    }
+
+    /// 安装程序所在目录中的离线安装包
+    pub fn find_default() -> Option<PathBuf> {
+        let mut path = std::env::current_exe().ok()?;
+        path.pop();
+        path.push(DEFAULT_BUNDLE_NAME);
+
+        path.is_file().then_some(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 制作离线安装包时的Python版本，如`3.11`
+    pub fn python_version(&self) -> &str {
+        &self.manifest.python_version
+    }
+
+    /// 离线安装包是否为该Python版本和目标平台制作
+    pub fn check_target(&self, python_version_full: &str, target: &str) -> Result<()> {
+        if self.manifest.python_version_full != python_version_full
+            || self.manifest.target != target
+        {
+            bail!(
+                "离线安装包为Python {}({})制作，不适用于Python {}({})",
+                self.manifest.python_version_full,
+                self.manifest.target,
+                python_version_full,
+                target
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn cpython_file_name(&self) -> Result<&str> {
+        entry_file_name(&self.manifest.cpython)
+    }
+
+    /// 解出CPython安装包到dest并校验
+    pub fn extract_cpython(&self, dest: &Path) -> Result<()> {
+        let mut archive = open_zip(&self.path)?;
+        let file = self.manifest.find(&self.manifest.cpython).unwrap();
+
+        extract_entry(&mut archive, file, dest)
+    }
+
+    /// 解出需求文件到dest并校验
+    pub fn extract_requirements(&self, dest: &Path) -> Result<()> {
+        let mut archive = open_zip(&self.path)?;
+        let file = self.manifest.find(&self.manifest.requirements).unwrap();
+
+        extract_entry(&mut archive, file, dest)
+    }
+
+    /// 制作离线安装包时的配置文件内容，已按清单校验
+    pub fn config_toml(&self) -> Result<Option<String>> {
+        self.read_text(CONFIG_NAME)
+    }
+
+    /// 制作离线安装包时的锁文件内容，制作时没有适用的锁文件则为None
+    pub fn lock_file(&self) -> Result<Option<String>> {
+        self.read_text(LOCK_NAME)
+    }
+
+    /// 读出包中的文本文件并按清单校验大小和sha256，清单中没有时返回None
+    fn read_text(&self, path: &str) -> Result<Option<String>> {
+        let Some(file) = self.manifest.find(path) else {
+            return Ok(None);
+        };
+
+        let mut archive = open_zip(&self.path)?;
+        let entry = match archive.by_name(&file.path) {
+            Ok(entry) => entry,
+            Err(err) => bail!("离线安装包中没有{}: {}", file.path, err),
+        };
+
+        let mut content = Vec::new();
+        entry.take(file.size + 1).read_to_end(&mut content)?;
+
+        let mut hasher = Hasher::new("sha256")?;
+        hasher.update(&content);
+        if content.len() as u64 != file.size || !hasher.verify(&file.sha256) {
+            bail!("离线安装包中的{}与清单不符，文件可能已损坏", file.path);
+        }
+
+        match String::from_utf8(content) {
+            Ok(text) => Ok(Some(text)),
+            Err(err) => bail!("离线安装包中的{}不是UTF-8文本: {}", file.path, err),
+        }
+    }
+
+    /// 解出全部程序包到dest_dir，逐个校验大小和sha256
+    pub fn extract_packages(
+        &self,
+        dest_dir: &Path,
+        status_updater: &impl StatusUpdate,
+    ) -> Result<()> {
+        let packages = self
+            .manifest
+            .files
+            .iter()
+            .filter(|file| file.path.starts_with(PACKAGES_DIR))
+            .collect::<Vec<&BundleFile>>();
+
+        if let Err(err) = std::fs::create_dir_all(dest_dir) {
+            bail!("创建目录{}失败: {}", dest_dir.display(), err)
+        }
+
+        let mut archive = open_zip(&self.path)?;
+
+        status_updater.update_progress(0, 0, packages.len());
+        for (idx, file) in packages.iter().enumerate() {
+            let dest = dest_dir.join(entry_file_name(&file.path)?);
+            extract_entry(&mut archive, file, &dest)?;
+            status_updater.update_progress(idx + 1, 0, packages.len());
+        }
+
+        log::info!(
+            "从离线安装包{}解出{}个程序包",
+            self.path.display(),
+            packages.len()
+        );
+
+        Ok(())
+    }
+}
+
+impl BundleManifest {
+    fn find(&self, path: &str) -> Option<&BundleFile> {
+        self.files.iter().find(|file| file.path == path)
+    }
+}
+
+fn open_zip(path: &Path) -> Result<zip::ZipArchive<File>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => bail!("打开离线安装包{}错误: {}", path.display(), err),
+    };
+
+    match zip::ZipArchive::new(file) {
+        Ok(archive) => Ok(archive),
+        Err(err) => bail!("离线安装包{}格式错误: {}", path.display(), err),
+    }
+}
+
+/// 包内路径的文件名，不能含有目录
+fn entry_file_name(path: &str) -> Result<&str> {
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+    if file_name.is_empty() || file_name == "." || file_name == ".." || file_name.contains('\\') {
+        bail!("离线安装包中的文件名错误: {}", path);
+    }
+
+    Ok(file_name)
+}
+
+/// 解出一个文件，大小或sha256与清单不符时删除已解出的文件。
+/// dest已存在且与清单相符时不再解出
+fn extract_entry(
+    archive: &mut zip::ZipArchive<File>,
+    file: &BundleFile,
+    dest: &Path,
+) -> Result<()> {
+    if dest.is_file() && checksum_file("sha256", dest, &file.sha256)? {
+        return Ok(());
+    }
+
+    let entry = match archive.by_name(&file.path) {
+        Ok(entry) => entry,
+        Err(err) => bail!("离线安装包中没有{}: {}", file.path, err),
+    };
+
+    let mut hasher = Hasher::new("sha256")?;
+    let mut output = File::create(dest)?;
+
+    // 多读一个字节，以发现比清单记录更大的文件
+    let mut reader = entry.take(file.size + 1);
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        output.write_all(&buffer[..n])?;
+        size += n as u64;
+    }
+    output.sync_all()?;
+    drop(output);
+
+    if size != file.size || !hasher.verify(&file.sha256) {
+        let _ = std::fs::remove_file(dest);
+        bail!("离线安装包中的{}与清单不符，文件可能已损坏", file.path);
+    }
+
+    Ok(())
+}
+
+/// 把CPython安装包、为需求选定的程序包、需求文件、内置锁文件和配置文件打包为离线安装包，
+/// 清单只记录这些文件。
+///
+/// 需在准备需求、下载程序包并构建源码包之后调用
+pub async fn create_bundle(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    output: &Path,
+) -> Result<()> {
+    let cpython_archive = download_python_dist(installer, status_updater).await?;
+    let cpython_file_name = cpython_archive
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+
+    let requirements_path = get_requirements_path(installer);
+    if !requirements_path.is_file() {
+        bail!("需求文件{}不存在", requirements_path.display());
+    }
+
+    let packages = find_bundle_packages(
+        &installer.cached_packages_dir,
+        &installer.selected_packages(),
+    )?;
+
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Err(err) = std::fs::create_dir_all(parent) {
+        bail!("创建目录{}失败: {}", parent.display(), err)
+    }
+
+    // 先写入同目录的临时文件，完成后再改名，避免留下不完整的离线安装包
+    let tmp_file = tempfile::NamedTempFile::new_in(parent)?;
+    let mut writer = BundleWriter::new(tmp_file.reopen()?);
+
+    status_updater.message(&format!(
+        "打包CPython-{}安装包",
+        installer.python_version_full
+    ));
+    let cpython = format!("{}{}", CPYTHON_DIR, cpython_file_name);
+    writer.add_file(&cpython, &cpython_archive)?;
+
+    status_updater.update_progress(0, 0, packages.len());
+    for (idx, path) in packages.iter().enumerate() {
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        writer.add_file(&format!("{}{}", PACKAGES_DIR, file_name), path)?;
+        status_updater.update_progress(idx + 1, 0, packages.len());
+    }
+
+    let requirements = "requirements.txt".to_string();
+    writer.add_file(&requirements, &requirements_path)?;
+
+    // 安装时按制作离线安装包时的配置，而不是安装程序内置的配置
+    if let Some(lock) = installer.lock_file() {
+        writer.add_bytes(LOCK_NAME, lock.to_toml()?.as_bytes())?;
+    }
+    writer.add_bytes(CONFIG_NAME, RESOURCES.get_config_toml().as_bytes())?;
+
+    let manifest = BundleManifest {
+        version: BUNDLE_VERSION,
+        python_version: installer.python_version.clone(),
+        python_version_full: installer.python_version_full.clone(),
+        target: installer.target().to_string(),
+        platform_tag: installer.platform_tag.clone().unwrap_or_default(),
+        cpython,
+        requirements,
+        files: writer.files.clone(),
+    };
+    writer.finish(&serde_json::to_string_pretty(&manifest)?)?;
+
+    if let Err(err) = tmp_file.persist(output) {
+        bail!("写入离线安装包{}错误: {}", output.display(), err);
+    }
+
+    status_updater.message(&format!(
+        "离线安装包已写入{}，共{}个程序包",
+        output.display(),
+        packages.len()
+    ));
+
+    Ok(())
+}
+
+/// 为需求选定的程序包，加上从其中的源码包构建的wheel，按文件名排序。
+/// 缓存目录中的其它文件（以前下载的其它版本、构建源码包所需的程序包等）不放入离线安装包
+fn find_bundle_packages(cached_dir: &Path, selected: &[String]) -> Result<Vec<PathBuf>> {
+    let mut packages = BTreeSet::new();
+    let mut sdists = Vec::new();
+    for file_name in selected {
+        let path = cached_dir.join(file_name);
+        if !path.is_file() {
+            bail!("程序包{}不在缓存目录{}中", file_name, cached_dir.display());
+        }
+
+        if let Some(name_version) = sdist_name_version(file_name) {
+            sdists.push(name_version);
+        }
+        packages.insert(path);
+    }
+
+    // 源码包已在制作时构建为wheel，安装时不必再构建
+    for entry in std::fs::read_dir(cached_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let Some(base) = file_name.strip_suffix(".whl") else {
+            continue;
+        };
+
+        // wheel文件名为{name}-{version}-...
+        let mut parts = base.split('-');
+        if let (Some(name), Some(version)) = (parts.next(), parts.next()) {
+            if sdists.contains(&(canonicalize_name(name), version.to_string())) {
+                packages.insert(path);
+            }
+        }
+    }
+
+    Ok(packages.into_iter().collect())
+}
+
+/// 源码包文件名{name}-{version}中的规范名称和版本，不是源码包时返回None
+fn sdist_name_version(file_name: &str) -> Option<(String, String)> {
+    let (base, extension) = split_filename_extension(file_name).ok()?;
+    let extension = extension.to_lowercase();
+    if extension != ".zip" && !is_tar_extension(&extension) {
+        return None;
+    }
+
+    let (name, version) = base.rsplit_once('-')?;
+    Some((canonicalize_name(name), version.to_string()))
+}
+
+/// 写入离线安装包的各文件，同时记录大小和sha256
+struct BundleWriter {
+    zip: zip::ZipWriter<File>,
+    files: Vec<BundleFile>,
+}
+
+impl BundleWriter {
+    fn new(file: File) -> Self {
+        BundleWriter {
+            zip: zip::ZipWriter::new(file),
+            files: Vec::new(),
+        }
+    }
+
+    /// 安装包和程序包本身已压缩，不再压缩
+    fn add_file(&mut self, name: &str, path: &Path) -> Result<()> {
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored)
+            .large_file(true);
+        self.zip.start_file(name, options)?;
+
+        let mut input = File::open(path)?;
+        let mut hasher = Hasher::new("sha256")?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut size = 0u64;
+        loop {
+            let n = input.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+            self.zip.write_all(&buffer[..n])?;
+            size += n as u64;
+        }
+
+        self.files.push(BundleFile {
+            path: name.to_string(),
+            size,
+            sha256: hasher.hexdigest(),
+        });
+
+        Ok(())
+    }
+
+    fn add_bytes(&mut self, name: &str, content: &[u8]) -> Result<()> {
+        self.zip
+            .start_file(name, zip::write::FileOptions::default())?;
+        self.zip.write_all(content)?;
+
+        let mut hasher = Hasher::new("sha256")?;
+        hasher.update(content);
+        self.files.push(BundleFile {
+            path: name.to_string(),
+            size: content.len() as u64,
+            sha256: hasher.hexdigest(),
+        });
+
+        Ok(())
+    }
+
+    /// 最后写入清单
+    fn finish(mut self, manifest: &str) -> Result<()> {
+        self.zip
+            .start_file(MANIFEST_NAME, zip::write::FileOptions::default())?;
+        self.zip.write_all(manifest.as_bytes())?;
+
+        let mut file = self.zip.finish()?;
+        file.flush()?;
+        file.sync_all()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 只有清单所需的CPython安装包和需求文件，以及给出的其它文件
+    fn write_bundle(path: &Path, files: &[(&str, &str)], tamper: Option<&str>) {
+        let mut writer = BundleWriter::new(File::create(path).unwrap());
+        writer
+            .add_bytes("cpython/cpython.tar.gz", b"cpython")
+            .unwrap();
+        writer
+            .add_bytes("requirements.txt", b"demo-pkg==1.0.0\n")
+            .unwrap();
+        for (name, content) in files {
+            writer.add_bytes(name, content.as_bytes()).unwrap();
+        }
+
+        let mut files = writer.files.clone();
+        if let Some(name) = tamper {
+            let file = files.iter_mut().find(|file| file.path == name).unwrap();
+            file.sha256 = "00".repeat(32);
+        }
+
+        let manifest = BundleManifest {
+            version: BUNDLE_VERSION,
+            python_version: "3.11".to_string(),
+            python_version_full: "3.11.5".to_string(),
+            target: "x86_64-pc-windows-msvc".to_string(),
+            platform_tag: "win_amd64".to_string(),
+            cpython: "cpython/cpython.tar.gz".to_string(),
+            requirements: "requirements.txt".to_string(),
+            files,
+        };
+        writer
+            .finish(&serde_json::to_string_pretty(&manifest).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn bundled_config_and_lock_are_verified_against_manifest() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(DEFAULT_BUNDLE_NAME);
+
+        let config = "pip_version = \"23.2.1\"\n";
+        write_bundle(&path, &[(CONFIG_NAME, config)], None);
+        let bundle = Bundle::open(&path).unwrap();
+        assert_eq!(bundle.config_toml().unwrap().as_deref(), Some(config));
+        assert_eq!(bundle.lock_file().unwrap(), None);
+
+        let lock = "version = 1\n";
+        write_bundle(&path, &[(CONFIG_NAME, config), (LOCK_NAME, lock)], None);
+        let bundle = Bundle::open(&path).unwrap();
+        assert_eq!(bundle.lock_file().unwrap().as_deref(), Some(lock));
+
+        write_bundle(&path, &[(CONFIG_NAME, config)], Some(CONFIG_NAME));
+        let bundle = Bundle::open(&path).unwrap();
+        assert!(bundle.config_toml().is_err());
+    }
+
+    #[test]
+    fn bundle_packages_are_limited_to_selected_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cached_dir = temp_dir.path();
+        for file_name in [
+            "demo_pkg-1.0.0-py3-none-any.whl",
+            "demo_pkg-0.9.0-py3-none-any.whl", // 以前下载的其它版本
+            "other-pkg-2.0.tar.gz",
+            "other_pkg-2.0-cp311-cp311-win_amd64.whl", // 从源码包构建
+            "setuptools-68.0.0-py3-none-any.whl",      // 构建源码包所需
+            "cpython-3.11.5.tar.gz",
+        ] {
+            std::fs::write(cached_dir.join(file_name), file_name).unwrap();
+        }
+
+        let selected = [
+            "demo_pkg-1.0.0-py3-none-any.whl".to_string(),
+            "other-pkg-2.0.tar.gz".to_string(),
+        ];
+        let packages = find_bundle_packages(cached_dir, &selected).unwrap();
+        let file_names = packages
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            file_names,
+            [
+                "demo_pkg-1.0.0-py3-none-any.whl",
+                "other-pkg-2.0.tar.gz",
+                "other_pkg-2.0-cp311-cp311-win_amd64.whl",
+            ]
+        );
+
+        let missing = ["demo_pkg-1.1.0-py3-none-any.whl".to_string()];
+        assert!(find_bundle_packages(cached_dir, &missing).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::{bail, Result};
+use sha2::digest::DynDigest;
+use std::path::Path;
+
+/// 摘要方法按强度从高到低的顺序，链接提供多个摘要时选择最强的
+pub static HASH_PREFERENCE: [&str; 6] = ["sha512", "sha384", "sha256", "sha224", "sha1", "md5"];
+
+/// 已不足以防篡改的摘要方法，只在配置`allow_weak_hashes`时采用
+static WEAK_HASHES: [&str; 2] = ["sha1", "md5"];
+
+pub fn is_weak_hash(method: &str) -> bool {
+    WEAK_HASHES.contains(&method.to_lowercase().as_str())
+}
+
+/// 增量计算文件摘要，用于边下载边校验
+pub struct Hasher {
+    inner: Box<dyn DynDigest + Send>,
+}
+
+impl Hasher {
+    pub fn new(method: &str) -> Result<Self> {
+        let method = method.to_lowercase();
+        let inner: Box<dyn DynDigest + Send> = match method.as_str() {
+            "sha512" => Box::new(sha2::Sha512::default()),
+            "sha384" => Box::new(sha2::Sha384::default()),
+            "sha256" => Box::new(sha2::Sha256::default()),
+            "sha224" => Box::new(sha2::Sha224::default()),
+            "sha1" => Box::new(sha1::Sha1::default()),
+            "md5" => Box::new(md5::Md5::default()),
+            _ => bail!("不支持checksum方法: {}", method),
+        };
+
+        Ok(Hasher { inner })
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn hexdigest(self) -> String {
+        hex::encode(self.inner.finalize())
+    }
+
+    /// 与给定的十六进制摘要比较
+    pub fn verify(self, hexcode: &str) -> bool {
+        self.hexdigest().eq_ignore_ascii_case(hexcode)
+    }
+}
+
+pub fn checksum(method: &str, content: &[u8], hexcode: &str) -> Result<bool> {
+    let mut hasher = Hasher::new(method)?;
+    hasher.update(content);
+
+    Ok(hasher.verify(hexcode))
+}
+
+/// 分块读取文件计算摘要，避免将整个文件读入内存
+pub fn checksum_file(method: &str, path: &Path, hexcode: &str) -> Result<bool> {
+    let mut hasher = Hasher::new(method)?;
+    update_from_file(&mut hasher, path)?;
+
+    Ok(hasher.verify(hexcode))
+}
+
+pub fn update_from_file(hasher: &mut Hasher, path: &Path) -> Result<()> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(())
+}
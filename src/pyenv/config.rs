@@ -1,12 +1,77 @@
 use anyhow::{anyhow, Result};
 use serde_derive::Deserialize;
 
+use super::network::NetworkConfig;
+use super::retry::RetryPolicy;
+
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pip_version: String,
+    #[serde(default = "default_download_concurrency")]
+    download_concurrency: usize,
     pypi: Vec<PyPIMirror>,
     cpython: Vec<CPythonDistSource>,
     obligated_requirements: Vec<String>,
+    #[serde(default)]
+    retry: RetryPolicy,
+    #[serde(default = "default_mirror_failure_threshold")]
+    mirror_failure_threshold: u32,
+    #[serde(default)]
+    network: NetworkConfig,
+    #[serde(default)]
+    resolve_requirements: bool,
+    #[serde(default = "default_prefer_binary")]
+    prefer_binary: bool,
+    #[serde(default)]
+    install_backend: InstallBackend,
+    #[serde(default = "default_compile_bytecode")]
+    compile_bytecode: bool,
+    #[serde(default)]
+    hash_policy: HashPolicy,
+    #[serde(default)]
+    allow_weak_hashes: bool,
+    #[serde(default)]
+    allow_prereleases: bool,
+}
+
+/// 离线安装程序包的方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallBackend {
+    /// 由安装程序直接解压wheel到site-packages
+    #[default]
+    Native,
+    /// 调用虚拟环境中的`pip install --no-index`
+    Pip,
+}
+
+/// 镜像未给出文件摘要时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashPolicy {
+    /// 拒绝下载
+    #[default]
+    Reject,
+    /// 记录警告后不经校验下载
+    Warn,
+    /// 锁文件锁定了该文件时按锁定的sha256校验，否则按需求文件的`--hash`，都没有时拒绝
+    TrustLock,
+}
+
+fn default_download_concurrency() -> usize {
+    4
+}
+
+fn default_mirror_failure_threshold() -> u32 {
+    5
+}
+
+fn default_prefer_binary() -> bool {
+    true
+}
+
+fn default_compile_bytecode() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -19,14 +84,32 @@ pub struct PyPIMirror {
 pub struct CPythonDistSource {
     python_version: String,
     version: String,
+    #[serde(default = "default_cpython_target")]
+    target: String,
     url: String,
-    checksum: String,
+    checksum: Option<String>,
+    checksum_url: Option<String>,
+    #[serde(default = "default_strip_components")]
+    strip_components: usize,
+}
+
+fn default_cpython_target() -> String {
+    "x86_64-pc-windows-msvc".to_string()
+}
+
+/// python-build-standalone的install_only包内容都在顶层的`python/`目录下
+fn default_strip_components() -> usize {
+    1
 }
 
 impl Config {
     pub fn load() -> Result<Config> {
         use super::super::resources::RESOURCES;
-        let config: Config = toml::from_str(RESOURCES.get_config_toml())?;
+        Self::parse(RESOURCES.get_config_toml())
+    }
+
+    pub fn parse(content: &str) -> Result<Config> {
+        let config: Config = toml::from_str(content)?;
 
         Ok(config)
     }
@@ -35,10 +118,15 @@ impl Config {
         &self.pip_version
     }
 
+    pub fn download_concurrency(&self) -> usize {
+        self.download_concurrency
+    }
+
     pub fn get_cpytion_source(
         &self,
-        mut python_version: Option<String>,
+        #[allow(unused_mut)] mut python_version: Option<String>,
     ) -> Result<&CPythonDistSource> {
+        #[cfg(windows)]
         if python_version.is_none() {
             use super::utils::get_windows_major_versoin;
             let win_major = get_windows_major_versoin()?;
@@ -47,21 +135,38 @@ impl Config {
             }
         }
 
-        if self.cpython.is_empty() {
-            return Err(anyhow!("在配置文件无[[cpython]]配置信息"));
-        }
+        use super::utils::current_target_triple;
+        let target = current_target_triple()?;
+
+        self.get_cpython_source_for_target(python_version, target)
+    }
+
+    /// 指定目标三元组的CPython发行包，未指定Python版本时为配置文件中该目标的第一个
+    pub fn get_cpython_source_for_target(
+        &self,
+        python_version: Option<String>,
+        target: &str,
+    ) -> Result<&CPythonDistSource> {
+        let mut dists = self.cpython.iter().filter(|dist| dist.target == target);
 
         match python_version {
             Some(python_version) => {
-                for dist in &self.cpython {
+                for dist in dists {
                     if dist.python_version == python_version {
                         return Ok(dist);
                     }
                 }
 
-                Err(anyhow!("在安装配置文件没找到{}下载信息", python_version))
+                Err(anyhow!(
+                    "在安装配置文件没找到{}({})下载信息",
+                    python_version,
+                    target
+                ))
             }
-            None => Ok(self.cpython.first().unwrap()),
+            None => match dists.next() {
+                Some(dist) => Ok(dist),
+                None => Err(anyhow!("在配置文件无{}的[[cpython]]配置信息", target)),
+            },
         }
     }
 
@@ -72,9 +177,56 @@ impl Config {
     pub fn obligated_requirements(&self) -> &[String] {
         &self.obligated_requirements
     }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    pub fn mirror_failure_threshold(&self) -> u32 {
+        self.mirror_failure_threshold
+    }
+
+    pub fn network(&self) -> &NetworkConfig {
+        &self.network
+    }
+
+    pub fn resolve_requirements(&self) -> bool {
+        self.resolve_requirements
+    }
+
+    pub fn prefer_binary(&self) -> bool {
+        self.prefer_binary
+    }
+
+    pub fn install_backend(&self) -> InstallBackend {
+        self.install_backend
+    }
+
+    pub fn compile_bytecode(&self) -> bool {
+        self.compile_bytecode
+    }
+
+    pub fn hash_policy(&self) -> HashPolicy {
+        self.hash_policy
+    }
+
+    pub fn allow_weak_hashes(&self) -> bool {
+        self.allow_weak_hashes
+    }
+
+    pub fn allow_prereleases(&self) -> bool {
+        self.allow_prereleases
+    }
 }
 
 impl PyPIMirror {
+    pub fn new(name: &str, url: &str) -> Self {
+        PyPIMirror {
+            name: name.to_string(),
+            url: url.to_string(),
+        }
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -101,7 +253,21 @@ impl CPythonDistSource {
         &self.url
     }
 
-    pub fn checksum(&self) -> &str {
-        &self.checksum
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref()
+    }
+
+    /// 发行包对应的sha256文件地址，未直接给出checksum时从该地址获取
+    pub fn checksum_url(&self) -> Option<&str> {
+        self.checksum_url.as_deref()
+    }
+
+    /// 解压时去掉的路径前缀层数
+    pub fn strip_components(&self) -> usize {
+        self.strip_components
     }
 }
@@ -1,58 +1,175 @@
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, RANGE};
+use reqwest::{Response, StatusCode};
+use serde_derive::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use super::super::errors::DownloadingError;
 use super::super::status::{DownloadingStats, StatusUpdate};
+use super::checksum::{update_from_file, Hasher};
 use super::installer::Installer;
-use super::super::errors::DownloadingError;
 
-pub async fn download(
+/// 未下载完的`.part`文件的说明，保存在同目录的`.part.json`中，用于下次断点续传
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    mirror: String, // 下载地址的主机名，有多个镜像可选时优先从同一镜像续传
+    hash_method: String,
+    hash: String,
+    etag: Option<String>,
+    total_size: u64,
+}
+
+/// 续传请求的应答
+enum RangeReply {
+    Resumed(Response, u64), // 从已下载部分之后续传，及文件总长度
+    Full(Response),         // 镜像返回了整个文件，作为新的完整下载
+    Restart,                // 应答不可用，需重新发送完整下载的请求
+}
+
+/// 下载url到文件dest。
+///
+/// 下载内容边接收边写入临时文件`dest.part`，同时增量计算摘要，
+/// 校验通过后再改名为dest，避免留下不完整的文件，也不必将整个文件保存在内存中。
+///
+/// 下载中断时保留`.part`文件，再次下载同一摘要的文件时以`Range`请求续传，
+/// 镜像不支持`Range`或文件已改变时重新完整下载，镜像对续传请求直接返回整个文件时不再重复请求。
+pub async fn download_file(
     installer: &Installer,
     status_updater: &impl StatusUpdate,
     url: &str,
     title: &str,
-) -> Result<Vec<u8>, DownloadingError> {
-    let mut resp = match installer.client.get(url).send().await {
-        Ok(resp) => resp,
-        Err(err) => {
-            return Err(if err.is_timeout() {
-                DownloadingError::timeout_error(format!("下载{}", url))
-            } else {
-                DownloadingError::server_error(format!("{}", err))
-            });
+    dest: &Path,
+    expected_hash: Option<(&str, &str)>, // (hash_method, hash_code)
+) -> Result<(), DownloadingError> {
+    let mut hasher = match expected_hash {
+        Some((method, _)) => match Hasher::new(method) {
+            Ok(hasher) => Some(hasher),
+            Err(err) => return Err(DownloadingError::error(format!("{err}"))),
+        },
+        None => None,
+    };
+
+    let part_path = part_file_path(dest);
+    let meta_path = part_meta_path(dest);
+
+    // 只有给定摘要时才能确认续传的文件与已下载部分是同一文件
+    let partial = match expected_hash {
+        Some((method, hexcode)) => {
+            load_partial_download(&part_path, &meta_path).filter(|(meta, _)| {
+                meta.hash_method == method && meta.hash.eq_ignore_ascii_case(hexcode)
+            })
         }
+        None => None,
     };
 
-    let http_status = resp.status();
-    if !http_status.is_success() {
-        let status_code = http_status.as_u16();
-        if status_code == 404 {
-            return Err(DownloadingError::not_found(format!("{}", url)));
-        } else {
-            return Err(DownloadingError::server_error(format!(
-                "HTTP状态码[{}]",
-                status_code
-            )));
+    let mut resumed: Option<(Response, u64, u64)> = None; // (resp, resumed_size, total_size)
+    let mut full_resp: Option<Response> = None;
+    if let Some((meta, part_size)) = partial {
+        if meta.mirror != mirror_of(url) {
+            log::info!("上次从{}下载{}，改从其它镜像续传", meta.mirror, url);
+        }
+
+        match send_range_request(installer, url, &meta, part_size).await? {
+            RangeReply::Resumed(resp, total_size) => {
+                log::info!("从{}字节处续传{}", part_size, url);
+                resumed = Some((resp, part_size, total_size));
+            }
+            RangeReply::Full(resp) => {
+                log::info!("镜像不支持续传或文件已改变，改为完整下载{}", url);
+                full_resp = Some(resp);
+            }
+            RangeReply::Restart => {
+                log::info!("续传应答与已下载部分不符，重新下载{}", url);
+            }
         }
     }
 
-    let Some(total_size) = resp.content_length() else {
-        return Err(DownloadingError::error(format!(
-            "HTTP响应异常，无内容长度信息：{url}",
-        )));
-    };
+    let (mut resp, mut part_file, mut stats) = match resumed {
+        Some((resp, part_size, total_size)) => {
+            // 已下载的部分先计入摘要
+            if let Some(hasher) = hasher.as_mut() {
+                if let Err(err) = update_from_file(hasher, &part_path) {
+                    return Err(DownloadingError::error(format!(
+                        "读取下载临时文件{}错误：{err}",
+                        part_path.display()
+                    )));
+                }
+            }
 
-    let mut buffer: Vec<u8> = Vec::new();
+            let part_file = match OpenOptions::new().append(true).open(&part_path) {
+                Ok(file) => file,
+                Err(err) => {
+                    return Err(DownloadingError::error(format!(
+                        "打开下载临时文件{}错误：{err}",
+                        part_path.display()
+                    )));
+                }
+            };
+
+            let stats = DownloadingStats::resumed(title, total_size, part_size);
+            (resp, part_file, stats)
+        }
+        None => {
+            // 续传请求得到的完整应答直接使用，已下载的部分在创建临时文件时清空，摘要从头计算
+            let resp = match full_resp {
+                Some(resp) => resp,
+                None => send_request(installer, installer.client.get(url), url).await?,
+            };
+
+            // 分块传输编码等情况下没有Content-Length，只能显示已下载的字节数
+            let total_size = resp.content_length();
+
+            let part_file = match File::create(&part_path) {
+                Ok(file) => file,
+                Err(err) => {
+                    return Err(DownloadingError::error(format!(
+                        "创建下载临时文件{}错误：{err}",
+                        part_path.display()
+                    )));
+                }
+            };
+
+            // 总长度未知时无法确认续传的范围，不保存续传信息
+            if let (Some((method, hexcode)), Some(total_size)) = (expected_hash, total_size) {
+                let meta = PartialDownload {
+                    url: url.to_string(),
+                    mirror: mirror_of(url),
+                    hash_method: method.to_string(),
+                    hash: hexcode.to_string(),
+                    etag: header_value(&resp, ETAG),
+                    total_size,
+                };
+                save_partial_download(&meta_path, &meta);
+            }
+
+            let stats = DownloadingStats::new(title, total_size);
+            (resp, part_file, stats)
+        }
+    };
 
-    let mut stats = DownloadingStats::new(title, total_size);
     status_updater.update_downloading(&stats);
     loop {
-        match resp.chunk().await {
+        let Ok(result) = tokio::time::timeout(installer.read_timeout(), resp.chunk()).await else {
+            // 保留已下载的部分，下次续传
+            return Err(DownloadingError::timeout_error(format!(
+                "下载{}超过{}秒未收到数据",
+                url,
+                installer.read_timeout().as_secs()
+            )));
+        };
+
+        match result {
             Ok(Some(chunk)) => {
                 use std::io::Write;
-                if let Err(err) = buffer.write_all(&chunk) {
-                    return Err(DownloadingError::server_error(format!(
-                        "下载写入缓存错误：{}",
-                        err
-                    )));
+                if let Err(err) = part_file.write_all(&chunk) {
+                    return Err(DownloadingError::error(format!("下载文件写入错误：{}", err)));
                 };
 
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(&chunk);
+                }
+
                 stats.update(chunk.len() as u64);
                 if stats.out_of_tick() {
                     status_updater.update_downloading(&stats);
@@ -63,6 +180,7 @@ pub async fn download(
                 break;
             }
             Err(err) => {
+                // 保留已下载的部分，下次续传
                 return Err(if err.is_timeout() {
                     DownloadingError::timeout_error(format!("下载{}", url))
                 } else {
@@ -72,22 +190,403 @@ pub async fn download(
         };
     }
 
+    if let Some(total_size) = stats.total_size() {
+        if stats.downloaded() != total_size {
+            return Err(DownloadingError::server_error(format!(
+                "下载不完整({}/{}字节): {}",
+                stats.downloaded(),
+                total_size,
+                url
+            )));
+        }
+    }
+
+    if let Err(err) = part_file.sync_all() {
+        return Err(DownloadingError::error(format!("下载文件写入错误：{err}")));
+    }
+    drop(part_file);
+
     stats.finish();
     status_updater.update_downloading(&stats);
 
-    Ok(buffer)
+    if let (Some(hasher), Some((method, hexcode))) = (hasher, expected_hash) {
+        if !hasher.verify(hexcode) {
+            std::fs::remove_file(&part_path).ok();
+            std::fs::remove_file(&meta_path).ok();
+            return Err(DownloadingError::error(format!(
+                "文件{}完整检验与原文件不一致: {}",
+                method.to_uppercase(),
+                url
+            )));
+        }
+    }
+
+    if let Err(err) = std::fs::rename(&part_path, dest) {
+        return Err(DownloadingError::error(format!(
+            "下载文件{}改名错误：{err}",
+            part_path.display()
+        )));
+    }
+    std::fs::remove_file(&meta_path).ok();
+
+    Ok(())
+}
+
+/// 发送请求并检查HTTP状态
+pub(super) async fn send_request(
+    installer: &Installer,
+    request: reqwest::RequestBuilder,
+    url: &str,
+) -> Result<Response, DownloadingError> {
+    let resp = match send_with_timeout(installer, request, url).await? {
+        Ok(resp) => resp,
+        Err(err) => {
+            return Err(if err.is_timeout() {
+                DownloadingError::timeout_error(format!("下载{}", url))
+            } else {
+                DownloadingError::server_error(format!("{}", err))
+            });
+        }
+    };
+
+    let http_status = resp.status();
+    if !http_status.is_success() {
+        let status_code = http_status.as_u16();
+        if status_code == 404 {
+            return Err(DownloadingError::not_found(url.to_string()));
+        } else {
+            return Err(DownloadingError::server_error(format!(
+                "HTTP状态码[{}]",
+                status_code
+            )));
+        }
+    }
+
+    Ok(resp)
+}
+
+/// 请求从part_size处续传。
+/// 镜像按请求返回206且范围、长度与已下载部分吻合时续传；返回200时为整个文件，
+/// 作为新的完整下载；其它情况需重新请求
+async fn send_range_request(
+    installer: &Installer,
+    url: &str,
+    meta: &PartialDownload,
+    part_size: u64,
+) -> Result<RangeReply, DownloadingError> {
+    let mut request = installer
+        .client
+        .get(url)
+        .header(RANGE, format!("bytes={}-", part_size));
+
+    // ETag只对同一地址有效，换了镜像时只依靠长度和最后的摘要检验
+    if meta.url == url {
+        if let Some(etag) = &meta.etag {
+            request = request.header(IF_RANGE, etag.as_str());
+        }
+    }
+
+    let resp = match send_with_timeout(installer, request, url).await? {
+        Ok(resp) => resp,
+        Err(err) => {
+            return Err(if err.is_timeout() {
+                DownloadingError::timeout_error(format!("下载{}", url))
+            } else {
+                DownloadingError::server_error(format!("{}", err))
+            });
+        }
+    };
+
+    if resp.status() == StatusCode::OK {
+        // 镜像不支持Range或者If-Range校验失败，应答即是整个文件
+        return Ok(RangeReply::Full(resp));
+    }
+
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        // 416等其它情况重新下载
+        return Ok(RangeReply::Restart);
+    }
+
+    let Some((start, total_size)) = header_value(&resp, CONTENT_RANGE)
+        .as_deref()
+        .and_then(parse_content_range)
+    else {
+        return Ok(RangeReply::Restart);
+    };
+
+    if start != part_size || total_size != meta.total_size {
+        return Ok(RangeReply::Restart);
+    }
+
+    if let Some(content_length) = resp.content_length() {
+        if content_length != total_size - part_size {
+            return Ok(RangeReply::Restart);
+        }
+    }
+
+    Ok(RangeReply::Resumed(resp, total_size))
 }
 
-// pub async fn fetch_text(installer: &Installer, url: &str, _title: &str) -> Result<String> {
-//     let resp = match installer.client.get(url).send().await {
-//         Ok(resp) => resp,
-//         Err(err) => bail!("{}", err),
-//     };
+/// 发送请求，超过读超时仍未收到响应头时按超时错误返回
+async fn send_with_timeout(
+    installer: &Installer,
+    request: reqwest::RequestBuilder,
+    url: &str,
+) -> Result<reqwest::Result<Response>, DownloadingError> {
+    match tokio::time::timeout(installer.read_timeout(), request.send()).await {
+        Ok(result) => Ok(result),
+        Err(_) => Err(DownloadingError::timeout_error(format!(
+            "下载{}超过{}秒无响应",
+            url,
+            installer.read_timeout().as_secs()
+        ))),
+    }
+}
+
+/// 解析`Content-Range: bytes <start>-<end>/<total>`，返回(start, total)
+pub(super) fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let range = value.trim().strip_prefix("bytes")?.trim();
+    let (range, total) = range.split_once('/')?;
+    let (start, _end) = range.split_once('-')?;
+
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()?))
+}
 
-//     let text = match resp.text().await {
-//         Ok(text) => text,
-//         Err(err) => bail!("{}", err),
-//     };
+pub(super) fn header_value(resp: &Response, name: reqwest::header::HeaderName) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// 下载地址所在镜像的主机名
+pub(super) fn mirror_of(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(url) => url.host_str().unwrap_or_default().to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+/// dest有未下载完的部分时，上次下载所用镜像的主机名
+pub(super) fn partial_download_mirror(dest: &Path) -> Option<String> {
+    load_partial_download(&part_file_path(dest), &part_meta_path(dest)).map(|(meta, _)| meta.mirror)
+}
 
-//     Ok(text)
-// }
+fn load_partial_download(part_path: &Path, meta_path: &Path) -> Option<(PartialDownload, u64)> {
+    let part_size = std::fs::metadata(part_path).ok()?.len();
+    let meta: PartialDownload = serde_json::from_slice(&std::fs::read(meta_path).ok()?).ok()?;
+
+    if part_size == 0 || part_size >= meta.total_size {
+        return None;
+    }
+
+    Some((meta, part_size))
+}
+
+fn save_partial_download(meta_path: &Path, meta: &PartialDownload) {
+    let result = serde_json::to_vec(meta)
+        .map_err(|err| err.to_string())
+        .and_then(|data| std::fs::write(meta_path, data).map_err(|err| err.to_string()));
+
+    if let Err(err) = result {
+        // 没有说明文件只是无法续传，不影响本次下载
+        log::warn!("写入{}错误: {}", meta_path.display(), err);
+    }
+}
+
+/// 下载中的临时文件路径，在原文件名后加`.part`
+pub fn part_file_path(dest: &Path) -> PathBuf {
+    let mut file_name = dest.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+    dest.with_file_name(file_name)
+}
+
+fn part_meta_path(dest: &Path) -> PathBuf {
+    let mut file_name = dest.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part.json");
+    dest.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::super::testutil::{http_response, test_installer, NoStatus, TestServer};
+    use super::*;
+
+    const CONTENT: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+    fn sha256_hex(data: &[u8]) -> String {
+        hex::encode(Sha256::digest(data))
+    }
+
+    /// 在dest旁准备已下载part的`.part`文件和续传说明
+    fn prepare_partial(dest: &Path, url: &str, part: &[u8], etag: Option<&str>) {
+        std::fs::write(part_file_path(dest), part).unwrap();
+
+        let meta = PartialDownload {
+            url: url.to_string(),
+            mirror: mirror_of(url),
+            hash_method: "sha256".to_string(),
+            hash: sha256_hex(CONTENT),
+            etag: etag.map(|etag| etag.to_string()),
+            total_size: CONTENT.len() as u64,
+        };
+        save_partial_download(&part_meta_path(dest), &meta);
+    }
+
+    async fn download(dir: &Path, url: &str, dest: &Path) -> Result<(), DownloadingError> {
+        let installer = test_installer(dir);
+        let hash = sha256_hex(CONTENT);
+
+        download_file(
+            &installer,
+            &NoStatus,
+            url,
+            "demo",
+            dest,
+            Some(("sha256", hash.as_str())),
+        )
+        .await
+    }
+
+    fn is_range_request(request: &str) -> bool {
+        request.contains("\r\nrange: ")
+    }
+
+    #[tokio::test]
+    async fn resume_appends_to_part_file() {
+        let server = TestServer::start(|request| {
+            if request.contains("\r\nrange: bytes=10-") {
+                let range = format!("bytes 10-{}/{}", CONTENT.len() - 1, CONTENT.len());
+                http_response(
+                    "206 Partial Content",
+                    &[("content-range", range.as_str()), ("etag", "\"v1\"")],
+                    &CONTENT[10..],
+                )
+            } else {
+                http_response("200 OK", &[("etag", "\"v1\"")], CONTENT)
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("demo-1.0-py3-none-any.whl");
+        let url = server.url("/demo-1.0-py3-none-any.whl");
+        prepare_partial(&dest, &url, &CONTENT[..10], Some("\"v1\""));
+
+        download(dir.path(), &url, &dest).await.unwrap();
+
+        assert_eq!(std::fs::read(&dest).unwrap(), CONTENT);
+        assert!(!part_file_path(&dest).exists());
+        assert!(!part_meta_path(&dest).exists());
+
+        // 只请求了未下载的部分
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("\r\nif-range: \"v1\""));
+    }
+
+    #[tokio::test]
+    async fn full_response_to_range_request_is_reused_from_zero() {
+        let server = TestServer::start(|_| http_response("200 OK", &[], CONTENT));
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("demo-1.0.tar.gz");
+        let url = server.url("/demo-1.0.tar.gz");
+        // 已下载部分若被追加，最后的摘要检验会失败
+        prepare_partial(&dest, &url, b"XXXXXXXXXX", None);
+
+        download(dir.path(), &url, &dest).await.unwrap();
+
+        assert_eq!(std::fs::read(&dest).unwrap(), CONTENT);
+        assert!(!part_file_path(&dest).exists());
+
+        // 续传请求的200应答即作为完整下载，不再发送第二个请求
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(is_range_request(&requests[0]));
+    }
+
+    #[tokio::test]
+    async fn etag_mismatch_discards_part_file() {
+        // 文件已改变，If-Range与当前ETag不一致时按HTTP语义返回整个文件
+        let server = TestServer::start(|request| {
+            if is_range_request(request) && request.contains("\r\nif-range: \"v2\"") {
+                let range = format!("bytes 10-{}/{}", CONTENT.len() - 1, CONTENT.len());
+                http_response(
+                    "206 Partial Content",
+                    &[("content-range", range.as_str()), ("etag", "\"v2\"")],
+                    &CONTENT[10..],
+                )
+            } else {
+                http_response("200 OK", &[("etag", "\"v2\"")], CONTENT)
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("demo-1.0.zip");
+        let url = server.url("/demo-1.0.zip");
+        prepare_partial(&dest, &url, b"stale-data", Some("\"v1\""));
+
+        download(dir.path(), &url, &dest).await.unwrap();
+
+        assert_eq!(std::fs::read(&dest).unwrap(), CONTENT);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].contains("\r\nif-range: \"v1\""));
+    }
+
+    #[tokio::test]
+    async fn mismatched_content_range_is_rejected() {
+        // 返回206但范围起点与已下载的部分不一致
+        let server = TestServer::start(|request| {
+            if is_range_request(request) {
+                let range = format!("bytes 5-{}/{}", CONTENT.len() - 1, CONTENT.len());
+                http_response(
+                    "206 Partial Content",
+                    &[("content-range", range.as_str())],
+                    &CONTENT[5..],
+                )
+            } else {
+                http_response("200 OK", &[], CONTENT)
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("demo-1.0.tar.bz2");
+        let url = server.url("/demo-1.0.tar.bz2");
+        prepare_partial(&dest, &url, &CONTENT[..10], None);
+
+        download(dir.path(), &url, &dest).await.unwrap();
+
+        assert_eq!(std::fs::read(&dest).unwrap(), CONTENT);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn partial_download_remembers_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("demo-1.0.tar.gz");
+        assert_eq!(partial_download_mirror(&dest), None);
+
+        let url = "https://mirrors.example.com/pypi/packages/demo-1.0.tar.gz";
+        prepare_partial(&dest, url, &CONTENT[..10], None);
+        assert_eq!(
+            partial_download_mirror(&dest).as_deref(),
+            Some("mirrors.example.com")
+        );
+    }
+
+    #[test]
+    fn parse_content_range_rejects_malformed_values() {
+        assert_eq!(parse_content_range("bytes 10-35/36"), Some((10, 36)));
+        assert_eq!(parse_content_range(" bytes 0-0/1 "), Some((0, 1)));
+
+        assert_eq!(parse_content_range("bytes 10-35/*"), None);
+        assert_eq!(parse_content_range("bytes */36"), None);
+        assert_eq!(parse_content_range("items 10-35/36"), None);
+        assert_eq!(parse_content_range("bytes 10-35"), None);
+        assert_eq!(parse_content_range(""), None);
+    }
+}
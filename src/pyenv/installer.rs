@@ -1,11 +1,20 @@
 use anyhow::{bail, Error, Result};
+use pep508_rs::MarkerEnvironment;
 use reqwest;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-use super::utils::parse_version;
+use super::utils::{make_venv_python_path, make_venv_site_packages_dir, parse_version};
 
-use super::config::{CPythonDistSource, Config, PyPIMirror};
+use super::bundle::Bundle;
+use super::config::{CPythonDistSource, Config, HashPolicy, InstallBackend, PyPIMirror};
+use super::lockfile::LockFile;
+use super::markers::marker_env_for_target;
+use super::mirrors::MirrorHealth;
+use super::network::{NetworkConfig, NetworkOverrides};
+use super::retry::RetryPolicy;
+use super::tags::{compute_platform_tags, CustomTarget, PlatformTags};
 
 #[derive(Clone, Debug)]
 pub struct Installer {
@@ -16,25 +25,102 @@ pub struct Installer {
     pub(crate) python_version_full: String,
     pub(crate) cached_packages_dir: PathBuf,
     pub(crate) pydist_dir: PathBuf,
-    pub(crate) pydist_source: CPythonDistSource,
+    pydist_source: Option<CPythonDistSource>,
+    target: String,
     pub(crate) venv_dir: PathBuf,
     pub(crate) venv_python_path: PathBuf,
     pub(crate) client: reqwest::Client,
     pub platform_tag: Option<String>,
     pub support_tags_map: HashMap<String, u32>,
+    pub marker_env: Option<MarkerEnvironment>,
 
     pypi_mirrors: Vec<PyPIMirror>,
     obligated_requirements: Vec<String>,
+    download_concurrency: usize,
+    retry_policy: RetryPolicy,
+    mirror_health: MirrorHealth,
+    selected_packages: Arc<Mutex<BTreeSet<String>>>, // 各克隆共享
+    network: NetworkConfig,
+    resolve_requirements: bool,
+    prefer_binary: bool,
+    install_backend: InstallBackend,
+    compile_bytecode: bool,
+    hash_policy: HashPolicy,
+    allow_weak_hashes: bool,
+    allow_prereleases: bool,
+    lock: Option<Arc<LockFile>>,
+    requirements_file: Option<PathBuf>,
+    hash_pins: HashMap<String, Vec<(String, String)>>,
+    bundle: Option<Arc<Bundle>>,
 }
 
 impl Installer {
     pub fn new(target_dir: PathBuf, python_version: Option<String>) -> Result<Self, Error> {
-        let tgba_dir = target_dir.join(".tgba_platform");
+        Self::with_network(target_dir, python_version, &NetworkOverrides::default())
+    }
 
+    /// 以命令行参数覆盖配置文件中的网络设置
+    pub fn with_network(
+        target_dir: PathBuf,
+        python_version: Option<String>,
+        overrides: &NetworkOverrides,
+    ) -> Result<Self, Error> {
         let config = Config::load()?;
         let cpython_source = config.get_cpytion_source(python_version)?;
 
-        let python_version = parse_version(cpython_source.cpython_version())?;
+        Self::from_config(
+            target_dir,
+            &config,
+            cpython_source.cpython_version(),
+            cpython_source.target(),
+            Some(cpython_source.clone()),
+            overrides,
+        )
+    }
+
+    /// 为其它平台或Python版本下载程序包，不安装CPython，也不创建虚拟环境，
+    /// 因此不需要配置文件中有该目标的`[[cpython]]`配置。
+    ///
+    /// 标签和标记环境按明确给出的目标设置，下载的文件和需求文件都保存到dest_dir
+    pub fn for_download(
+        dest_dir: PathBuf,
+        target: &CustomTarget,
+        overrides: &NetworkOverrides,
+    ) -> Result<Self, Error> {
+        let config = Config::load()?;
+        let triple = target.target_triple()?;
+
+        let mut installer = Self::from_config(
+            dest_dir.clone(),
+            &config,
+            target.python_version_full(),
+            triple,
+            None,
+            overrides,
+        )?;
+        installer.tgba_dir = dest_dir.clone();
+        installer.cached_packages_dir = dest_dir;
+
+        installer.apply_platform_tags(target.platform_tags()?);
+        installer.marker_env = Some(marker_env_for_target(
+            &installer.python_version_full,
+            triple,
+        )?);
+
+        Ok(installer)
+    }
+
+    fn from_config(
+        target_dir: PathBuf,
+        config: &Config,
+        python_version_full: &str,
+        target: &str,
+        cpython_source: Option<CPythonDistSource>,
+        overrides: &NetworkOverrides,
+    ) -> Result<Self, Error> {
+        let tgba_dir = target_dir.join(".tgba_platform");
+
+        let python_version = parse_version(python_version_full)?;
         let nums = &python_version.release;
         if nums.len() < 3 {
             bail!("Python版本号不全: major.minor.micro")
@@ -47,54 +133,344 @@ impl Installer {
         let py_venv_dir = tgba_dir.join(format!("venv"));
         let cached_packages_dir = tgba_dir.join("cached_packages");
 
-        let mut venv_python_path = py_venv_dir.clone();
-        venv_python_path.push("Scripts");
-        venv_python_path.push("python.exe");
+        let venv_python_path = make_venv_python_path(&py_venv_dir);
 
-        let mirrors = config
-            .get_pypi_mirrors()
-            .iter()
-            .map(|m| m.clone())
-            .collect();
+        let network = config.network().with_overrides(overrides);
 
-        let client = reqwest::Client::builder()
-            .user_agent(pip_user_agent(config.pip_version()))
-            .build()?;
+        let mut mirrors: Vec<PyPIMirror> = config.get_pypi_mirrors().to_vec();
 
-        Ok(Installer {
+        if let Some(index_url) = network.env_index_url() {
+            log::info!("采用环境变量PIP_INDEX_URL指定的镜像: {}", index_url);
+            mirrors.insert(0, PyPIMirror::new("PIP_INDEX_URL", &index_url));
+        }
+
+        let client = network.build_client(&pip_user_agent(config.pip_version()))?;
+
+        use super::super::resources::RESOURCES;
+        let lock = match RESOURCES.get_lock_file() {
+            Some(content) => match LockFile::parse(content) {
+                Ok(lock) => Some(Arc::new(lock)),
+                Err(err) => bail!("内置锁文件格式错误: {}", err),
+            },
+            None => None,
+        };
+
+        let mut installer = Installer {
             target_dir,
             python_version,
             python_version_full,
             tgba_dir,
             pydist_dir: py_dist_dir,
-            pydist_source: cpython_source.clone(),
+            pydist_source: cpython_source,
+            target: target.to_string(),
             venv_python_path,
             venv_dir: py_venv_dir,
             cached_packages_dir,
             client,
             platform_tag: None,
             support_tags_map: HashMap::new(),
+            marker_env: None,
             pypi_mirrors: mirrors,
             obligated_requirements: config.obligated_requirements().to_vec(),
-        })
+            download_concurrency: config.download_concurrency(),
+            retry_policy: config.retry_policy().clone(),
+            mirror_health: MirrorHealth::new(config.mirror_failure_threshold()),
+            selected_packages: Arc::new(Mutex::new(BTreeSet::new())),
+            network,
+            resolve_requirements: config.resolve_requirements(),
+            prefer_binary: config.prefer_binary(),
+            install_backend: config.install_backend(),
+            compile_bytecode: config.compile_bytecode(),
+            hash_policy: config.hash_policy(),
+            allow_weak_hashes: config.allow_weak_hashes(),
+            allow_prereleases: config.allow_prereleases(),
+            lock,
+            requirements_file: None,
+            hash_pins: HashMap::new(),
+            bundle: None,
+        };
+
+        // 标签只取决于目标Python版本和平台，不必等待虚拟环境创建
+        if let Err(err) = installer.set_platform_tags() {
+            log::warn!("计算平台标签错误: {}", err);
+        }
+
+        Ok(installer)
+    }
+
+    /// 由目标Python版本和目标三元组计算平台标签和支持的wheel标签
+    pub(super) fn set_platform_tags(&mut self) -> Result<()> {
+        let tags = compute_platform_tags(&self.python_version, &self.target)?;
+        self.apply_platform_tags(tags);
+
+        Ok(())
+    }
+
+    fn apply_platform_tags(&mut self, tags: PlatformTags) {
+        log::info!("系统平台标签: {}", tags.platform_tag);
+        log::info!("系统支持的平台标签: {:?}", tags.supported);
+
+        self.platform_tag = Some(tags.platform_tag);
+        self.support_tags_map = tags
+            .supported
+            .into_iter()
+            .enumerate()
+            .map(|(i, tag)| (tag, i as u32))
+            .collect();
     }
 
     pub fn target_dir(&self) -> &PathBuf {
         &self.target_dir
     }
 
+    /// 目标三元组，如`x86_64-pc-windows-msvc`
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// 安装的CPython发行包，只下载程序包时没有
+    pub(crate) fn pydist_source(&self) -> Result<&CPythonDistSource> {
+        match &self.pydist_source {
+            Some(cpython_source) => Ok(cpython_source),
+            None => bail!("只下载程序包时没有CPython发行包的配置"),
+        }
+    }
+
     pub fn tgba_dir(&self) -> &PathBuf {
         &self.tgba_dir
     }
 
+    pub fn site_packages_dir(&self) -> PathBuf {
+        make_venv_site_packages_dir(&self.venv_dir, &self.python_version)
+    }
+
 
     pub fn pypi_mirrors(&self) -> &[PyPIMirror] {
         &self.pypi_mirrors
     }
 
+    /// 本次安装中未断路的镜像，全部断路时仍返回所有镜像
+    pub fn available_pypi_mirrors(&self) -> Vec<&PyPIMirror> {
+        let mirrors = self
+            .pypi_mirrors
+            .iter()
+            .filter(|mirror| self.mirror_health.is_available(mirror))
+            .collect::<Vec<&PyPIMirror>>();
+
+        if mirrors.is_empty() {
+            self.pypi_mirrors.iter().collect()
+        } else {
+            mirrors
+        }
+    }
+
+    /// 按镜像名称重新排列镜像顺序，未列出的镜像保持原顺序排在后面
+    pub fn set_mirror_order(&mut self, names: &[String]) {
+        let position = |mirror: &PyPIMirror| {
+            names
+                .iter()
+                .position(|name| name == mirror.name())
+                .unwrap_or(names.len())
+        };
+
+        self.pypi_mirrors.sort_by_key(|mirror| position(mirror));
+    }
+
+    /// 需求文件中`-i`/`--extra-index-url`给出的镜像，前者排在最前，后者排在最后
+    pub fn add_index_urls(&mut self, index_url: Option<&str>, extra_index_urls: &[String]) {
+        if let Some(url) = index_url {
+            log::info!("采用需求文件指定的镜像: {}", url);
+            self.pypi_mirrors
+                .insert(0, PyPIMirror::new("index-url", url));
+        }
+
+        for (idx, url) in extra_index_urls.iter().enumerate() {
+            log::info!("采用需求文件指定的额外镜像: {}", url);
+            let name = format!("extra-index-url-{}", idx + 1);
+            self.pypi_mirrors.push(PyPIMirror::new(&name, url));
+        }
+    }
+
+    pub fn mirror_health(&self) -> &MirrorHealth {
+        &self.mirror_health
+    }
+
+    /// 记录为需求选定并已下载到缓存目录的文件
+    pub fn record_selected_package(&self, file_name: String) {
+        self.selected_packages.lock().unwrap().insert(file_name);
+    }
+
+    /// 为需求选定的全部文件名，按文件名排序，不包括构建源码包所需的程序包
+    pub fn selected_packages(&self) -> Vec<String> {
+        let selected = self.selected_packages.lock().unwrap();
+        selected.iter().cloned().collect()
+    }
+
+    /// 是否从顶层需求解析依赖，而不使用预先固定版本的需求文件
+    pub fn resolve_requirements(&self) -> bool {
+        self.resolve_requirements
+    }
+
+    /// 是否优先选择有wheel的较早版本，而不是只有源码包的最新版本
+    pub fn prefer_binary(&self) -> bool {
+        self.prefer_binary
+    }
+
+    pub fn install_backend(&self) -> InstallBackend {
+        self.install_backend
+    }
+
+    /// native方式安装后是否编译.pyc文件
+    pub fn compile_bytecode(&self) -> bool {
+        self.compile_bytecode
+    }
+
+    /// 镜像未给出文件摘要时的处理方式
+    pub fn hash_policy(&self) -> HashPolicy {
+        self.hash_policy
+    }
+
+    /// 是否采用md5、sha1等弱摘要校验下载的文件
+    pub fn allow_weak_hashes(&self) -> bool {
+        self.allow_weak_hashes
+    }
+
+    /// 需求未明确给出预发布版本时，是否也考虑预发布版本
+    pub fn allow_prereleases(&self) -> bool {
+        self.allow_prereleases
+    }
+
+    /// 需求文件中的`--pre`
+    pub(super) fn set_allow_prereleases(&mut self, allow_prereleases: bool) {
+        log::info!("需求文件设置考虑预发布版本: {}", allow_prereleases);
+        self.allow_prereleases = allow_prereleases;
+    }
+
+    /// 需求文件中的`--prefer-binary`或`--only-binary :all:`
+    pub(super) fn set_prefer_binary(&mut self, prefer_binary: bool) {
+        log::info!("需求文件设置优先选择wheel: {}", prefer_binary);
+        self.prefer_binary = prefer_binary;
+    }
+
+    /// 以指定的需求文件代替内置的需求文件，指定后不再使用内置锁文件和依赖解析
+    pub fn set_requirements_file(&mut self, path: PathBuf) {
+        self.requirements_file = Some(path);
+    }
+
+    pub fn requirements_file(&self) -> Option<&PathBuf> {
+        self.requirements_file.as_ref()
+    }
+
+    pub(super) fn set_hash_pins(&mut self, hash_pins: HashMap<String, Vec<(String, String)>>) {
+        self.hash_pins = hash_pins;
+    }
+
+    /// 需求文件中为该程序包给出的`--hash`，下载的文件须与其中之一相符
+    pub fn hash_pins(&self, name: &str) -> &[(String, String)] {
+        use super::utils::canonicalize_name;
+
+        match self.hash_pins.get(&canonicalize_name(name)) {
+            Some(hashes) => hashes,
+            None => &[],
+        }
+    }
+
+    /// 从离线安装包安装，不再访问网络。
+    ///
+    /// 安装方式等设置和锁文件按制作离线安装包时的配置，而不是本安装程序内置的配置
+    pub fn set_bundle(&mut self, bundle: Bundle) -> Result<()> {
+        bundle.check_target(&self.python_version_full, &self.target)?;
+        log::info!("采用离线安装包: {}", bundle.path().display());
+
+        self.apply_bundle_config(&bundle)?;
+
+        self.bundle = Some(Arc::new(bundle));
+        Ok(())
+    }
+
+    fn apply_bundle_config(&mut self, bundle: &Bundle) -> Result<()> {
+        use super::super::resources::RESOURCES;
+
+        // 较早的离线安装包中没有配置文件
+        match bundle.config_toml()? {
+            Some(content) if content != RESOURCES.get_config_toml() => {
+                let config = match Config::parse(&content) {
+                    Ok(config) => config,
+                    Err(err) => bail!("离线安装包中的配置文件格式错误: {}", err),
+                };
+                log::warn!(
+                    "离线安装包制作时的配置与安装程序内置的配置不同，按离线安装包的配置安装"
+                );
+
+                self.obligated_requirements = config.obligated_requirements().to_vec();
+                self.install_backend = config.install_backend();
+                self.compile_bytecode = config.compile_bytecode();
+                self.hash_policy = config.hash_policy();
+                self.allow_weak_hashes = config.allow_weak_hashes();
+            }
+            Some(_) => {}
+            None => log::warn!("离线安装包中没有配置文件，按安装程序内置的配置安装"),
+        }
+
+        self.lock = match bundle.lock_file()? {
+            Some(content) => match LockFile::parse(&content) {
+                Ok(lock) => Some(Arc::new(lock)),
+                Err(err) => bail!("离线安装包中的锁文件格式错误: {}", err),
+            },
+            None => None,
+        };
+
+        Ok(())
+    }
+
+    pub fn bundle(&self) -> Option<&Bundle> {
+        self.bundle.as_deref()
+    }
+
+    pub fn has_lock_file(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// 适用于目标Python版本和平台的内置锁文件
+    pub fn lock_file(&self) -> Option<&LockFile> {
+        let lock = self.lock.as_deref()?;
+        let platform_tag = self.platform_tag.as_deref()?;
+
+        if lock.matches(&self.python_version, platform_tag) {
+            Some(lock)
+        } else {
+            None
+        }
+    }
+
+    /// 等待响应或两次收到数据之间的最长时间
+    pub fn read_timeout(&self) -> std::time::Duration {
+        self.network.read_timeout()
+    }
+
     pub fn obligated_requirements(&self) -> &[String] {
         &self.obligated_requirements
     }
+
+    pub fn download_concurrency(&self) -> usize {
+        self.download_concurrency
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+}
+
+#[cfg(test)]
+impl Installer {
+    pub(super) fn set_hash_policy(&mut self, hash_policy: HashPolicy) {
+        self.hash_policy = hash_policy;
+    }
+
+    /// 代替内置的锁文件
+    pub(super) fn set_lock_file(&mut self, lock: LockFile) {
+        self.lock = Some(Arc::new(lock));
+    }
 }
 
 fn pip_user_agent(pip_version: &str) -> String {
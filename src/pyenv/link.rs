@@ -1,23 +1,75 @@
 use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
+use pep440_rs::Version;
 use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
 use url::Url;
 
 use crate::pyenv::utils::parse_version;
 
+use super::archive::is_tar_extension;
+use super::checksum::{is_weak_hash, HASH_PREFERENCE};
 use super::utils::{canonicalize_name, split_filename_extension};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PackageLink {
     url: Url,
     requires_python: Option<String>,
     yanked_reason: Option<String>,
-    hash: Option<(String, String)>, // (hash_method, hash_code)
+    hashes: HashMap<String, String>, // hash_method -> hash_code
     file_name: String,
     filename_base: String,
     filename_extension: String,
     package_version: String,
+    version: Version,
     wheel_info: Option<WheelInfo>,
+    core_metadata: Option<CoreMetadata>,
+}
+
+/// 索引页中无法解析而跳过的链接
+#[derive(Debug, Clone)]
+pub struct SkippedLink {
+    file_name: String,
+    reason: String,
+}
+
+impl SkippedLink {
+    pub(super) fn new(file_name: &str, reason: impl std::fmt::Display) -> Self {
+        SkippedLink {
+            file_name: file_name.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl std::fmt::Display for SkippedLink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file_name, self.reason)
+    }
+}
+
+/// PEP 658/714：索引提供与文件同名加`.metadata`的核心元数据文件
+#[derive(Debug, Clone)]
+pub struct CoreMetadata {
+    hash: Option<(String, String)>, // (hash_method, hash_code)
+}
+
+impl CoreMetadata {
+    pub fn checksum(&self) -> Option<(&str, &str)> {
+        let Some((method, digest)) = &self.hash else {
+            return None;
+        };
+
+        Some((method.as_str(), digest.as_str()))
+    }
 }
 
 impl PackageLink {
@@ -41,12 +93,26 @@ impl PackageLink {
         self.yanked_reason.as_deref()
     }
 
+    /// 链接提供的最强的摘要，不采用md5、sha1等弱摘要
     pub fn checksum(&self) -> Option<(&str, &str)> {
-        let Some((method, digest)) = &self.hash else {
-            return None;
-        };
+        self.strongest_checksum(false)
+    }
 
-        Some((method.as_str(), digest.as_str()))
+    /// 链接提供的最强的摘要，allow_weak时没有更强的摘要也可采用弱摘要
+    pub fn strongest_checksum(&self, allow_weak: bool) -> Option<(&str, &str)> {
+        HASH_PREFERENCE
+            .iter()
+            .filter(|method| allow_weak || !is_weak_hash(method))
+            .find_map(|method| {
+                self.hashes
+                    .get_key_value(*method)
+                    .map(|(method, digest)| (method.as_str(), digest.as_str()))
+            })
+    }
+
+    /// 链接提供的指定方法的摘要
+    pub fn hash(&self, method: &str) -> Option<&str> {
+        self.hashes.get(&method.to_lowercase()).map(|s| s.as_str())
     }
 
     // pub fn wheel_info(&self) -> Option<&WheelInfo> {
@@ -57,6 +123,17 @@ impl PackageLink {
         &self.package_version
     }
 
+    /// 解析链接时已校验的版本
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    /// wheel文件名中的构建标签(构建号, 其余部分)，如`1abc`为(1, "abc")
+    pub fn wheel_build(&self) -> Option<(u32, &str)> {
+        let build = self.wheel_info.as_ref()?.build.as_ref()?;
+        Some((build.0, build.1.as_str()))
+    }
+
     pub fn is_wheel(&self) -> bool {
         self.wheel_info.is_some()
     }
@@ -81,14 +158,79 @@ impl PackageLink {
     pub fn url(&self) -> &str {
         self.url.as_str()
     }
+
+    pub fn core_metadata(&self) -> Option<&CoreMetadata> {
+        self.core_metadata.as_ref()
+    }
+
+    /// 核心元数据文件的地址，索引未提供时为None
+    pub fn core_metadata_url(&self) -> Option<String> {
+        self.core_metadata
+            .as_ref()
+            .map(|_| format!("{}.metadata", self.url))
+    }
+
+    /// 以JSON索引中给出的摘要取代url中的摘要
+    pub(super) fn set_hashes(&mut self, hashes: &HashMap<String, String>) {
+        if !hashes.is_empty() {
+            self.hashes = hashes
+                .iter()
+                .map(|(method, hexcode)| (method.to_lowercase(), hexcode.to_string()))
+                .collect();
+        }
+    }
+
+    pub(super) fn set_core_metadata(&mut self, core_metadata: Option<CoreMetadata>) {
+        self.core_metadata = core_metadata;
+    }
+}
+
+fn choose_hash(hashes: &HashMap<String, String>) -> Option<(String, String)> {
+    for method in HASH_PREFERENCE {
+        if let Some(hexcode) = hashes.get(method) {
+            return Some((method.to_string(), hexcode.to_string()));
+        }
+    }
+
+    None
+}
+
+/// 解析HTML索引中`data-core-metadata`（或旧的`data-dist-info-metadata`）属性，
+/// 值为`true`或者`<hash_method>=<hash_code>`，`false`或缺失表示无元数据文件
+pub fn parse_core_metadata_attr(value: Option<&str>) -> Option<CoreMetadata> {
+    let value = value?.trim();
+    if value.is_empty() || value.eq_ignore_ascii_case("false") {
+        return None;
+    }
+
+    let hash = value
+        .split_once('=')
+        .map(|(method, hexcode)| (method.to_lowercase(), hexcode.to_string()));
+
+    Some(CoreMetadata { hash })
+}
+
+/// 由JSON索引中`core-metadata`（或旧的`dist-info-metadata`）的值得到元数据说明，
+/// 值为布尔值或者摘要表
+pub fn core_metadata_from_hashes(
+    available: bool,
+    hashes: Option<&HashMap<String, String>>,
+) -> Option<CoreMetadata> {
+    if !available {
+        return None;
+    }
+
+    Some(CoreMetadata {
+        hash: hashes.and_then(choose_hash),
+    })
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct WheelInfo {
     pyversions: Vec<String>,
     abis: Vec<String>,
     plats: Vec<String>,
-    // build: Option<String>,
+    build: Option<(u32, String)>,
 }
 
 static WHEEL_INFO_REGEX: Lazy<Regex> = Lazy::new(|| {
@@ -106,7 +248,8 @@ static WHEEL_INFO_REGEX: Lazy<Regex> = Lazy::new(|| {
     .unwrap()
 });
 
-fn parse_wheel_info(file_base: &str) -> Result<(String, WheelInfo)> {
+/// 解析wheel文件名，返回(程序包名称, 版本, wheel信息)
+fn parse_wheel_info(file_base: &str) -> Result<(String, String, WheelInfo)> {
     // lazy_static! {
     //     /// https://github.com/pypa/pip/blob/main/src/pip/_internal/models/wheel.py
     //     static ref WHEEL_INFO_REGEX: Regex = RegexBuilder::new(
@@ -127,86 +270,120 @@ fn parse_wheel_info(file_base: &str) -> Result<(String, WheelInfo)> {
         bail!("error in parsing: {}", file_base)
     };
 
-    // let pkg_name =  caps["name"].replace('_', "-");
+    let pkg_name = caps["name"].to_string();
     let pkg_version = caps["ver"].replace('_', "-");
 
     let pyversions = caps["pyver"].split('.').map(|s| s.to_string()).collect();
     let abis = caps["abi"].split('.').map(|s| s.to_string()).collect();
     let plats = caps["plat"].split('.').map(|s| s.to_string()).collect();
-    // let build = caps.name("build").map(|m| m.as_str().to_string());
+    let build = match caps.name("build") {
+        Some(build) => Some(parse_build_tag(build.as_str())?),
+        None => None,
+    };
 
     Ok((
+        pkg_name,
         pkg_version,
         WheelInfo {
             pyversions,
             abis,
             plats,
-            // build,
+            build,
         },
     ))
 }
 
+/// 构建标签以数字开头，按数字部分比较先后
+pub(super) fn parse_build_tag(build: &str) -> Result<(u32, String)> {
+    let digits = build
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect::<String>();
+    let Ok(number) = digits.parse::<u32>() else {
+        bail!("构建标签{}格式错误", build)
+    };
+
+    Ok((number, build[digits.len()..].to_string()))
+}
+
+/// 解析索引页中的链接，不能作为下载候选的链接返回跳过的原因
 pub fn parse_link_from_url(
     canonical_name: &str,
     mut url: Url,
     requires_python: Option<&str>,
     yanked_reason: Option<&str>,
-) -> Result<Option<PackageLink>> {
-    let hash = parse_link_hash(url.fragment());
+) -> Result<PackageLink, SkippedLink> {
+    let hashes = parse_link_hashes(url.fragment());
     url.set_fragment(None);
 
     let requires_python = requires_python.map(|s| s.to_string());
     let yanked_reason = yanked_reason.map(|s| s.to_string());
 
     // 从url拆分出文件名
-    let (_, file_name) = url.as_str().rsplit_once('/').unwrap();
-    let file_name = file_name.to_string();
+    let file_name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back());
+    let file_name = match file_name {
+        Some(file_name) if !file_name.is_empty() => file_name.to_string(),
+        _ => return Err(SkippedLink::new(url.as_str(), "链接中没有文件名")),
+    };
 
-    // let (file_base, file_ext, prj_ver, wheel_info) =
-    //     parse_url_file_name(file_name.as_str(), &canonical_name)?;
+    let (filename_base, filename_ext) = match split_filename_extension(&file_name) {
+        Ok(parts) => parts,
+        Err(_) => return Err(SkippedLink::new(&file_name, "没有扩展名")),
+    };
 
-    let (filename_base, filename_ext) = split_filename_extension(&file_name)?;
+    let (package_version, wheel_info) = if is_wheel_file(filename_ext) {
+        let (pkg_name, package_version, wheel) = match parse_wheel_info(filename_base) {
+            Ok(parsed) => parsed,
+            Err(err) => return Err(SkippedLink::new(&file_name, err)),
+        };
 
-    if is_wheel_file(filename_ext) {
-        let (package_version, wheel) = parse_wheel_info(filename_base)?;
+        if canonicalize_name(&pkg_name) != canonical_name {
+            return Err(SkippedLink::new(
+                &file_name,
+                format!("文件名与程序包名称{}不符", canonical_name),
+            ));
+        }
 
-        Ok(Some(PackageLink {
-            url,
-            requires_python,
-            yanked_reason,
-            hash,
-            file_name: file_name.to_string(),
-            filename_base: filename_base.to_string(),
-            filename_extension: filename_ext.to_string(),
-            package_version: package_version,
-            wheel_info: Some(wheel),
-        }))
+        (package_version, Some(wheel))
     } else if is_archive_file(filename_ext) {
         let Some(version_start) = split_version_from_filename(filename_base, canonical_name) else {
-            panic!("{} does not match {}", filename_base, canonical_name)
+            return Err(SkippedLink::new(
+                &file_name,
+                format!("文件名与程序包名称{}不符", canonical_name),
+            ));
         };
 
-        let package_version = filename_base[version_start..].to_string();
+        (filename_base[version_start..].to_string(), None)
+    } else if is_legacy_file(filename_ext) {
+        return Err(SkippedLink::new(
+            &file_name,
+            "不支持的旧格式（egg、Windows安装程序等）",
+        ));
+    } else {
+        return Err(SkippedLink::new(&file_name, "不支持的文件类型"));
+    };
 
-        if parse_version(package_version.as_str()).is_err() {
-            // 跳过在早期文件名不符合规范的
-            return Ok(None);
-        }
+    // 早期不符合PEP 440的版本号
+    let version = match parse_version(&package_version) {
+        Ok(version) => version,
+        Err(err) => return Err(SkippedLink::new(&file_name, err)),
+    };
 
-        Ok(Some(PackageLink {
-            url,
-            requires_python,
-            yanked_reason,
-            hash,
-            file_name: file_name.to_string(),
-            filename_base: filename_base.to_string(),
-            filename_extension: filename_ext.to_string(),
-            package_version,
-            wheel_info: None,
-        }))
-    } else {
-        Ok(None)
-    }
+    Ok(PackageLink {
+        url,
+        requires_python,
+        yanked_reason,
+        hashes,
+        file_name: file_name.to_string(),
+        filename_base: filename_base.to_string(),
+        filename_extension: filename_ext.to_string(),
+        package_version,
+        version,
+        wheel_info,
+        core_metadata: None,
+    })
 }
 
 static HASH_REGEX: Lazy<Regex> = Lazy::new(|| {
@@ -214,9 +391,10 @@ static HASH_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new("[#&]?(sha512|sha384|sha256|sha224|sha1|md5)=([^&]*)").unwrap()
 });
 
-fn parse_link_hash(url_fragment: Option<&str>) -> Option<(String, String)> {
+/// 链接片段中的全部摘要，如`#sha256=...&sha512=...`，忽略为空或不是十六进制数的摘要
+fn parse_link_hashes(url_fragment: Option<&str>) -> HashMap<String, String> {
     let Some(url_fragment) = url_fragment else {
-        return None;
+        return HashMap::new();
     };
 
     // lazy_static! {
@@ -224,17 +402,17 @@ fn parse_link_hash(url_fragment: Option<&str>) -> Option<(String, String)> {
     //         regex::Regex::new("[#&]?(sha512|sha384|sha256|sha224|sha1|md5)=([^&]*)").unwrap();
     // }
 
-    let Some(caps) = HASH_REGEX.captures(url_fragment) else {
-        return None;
-    };
-
-    Some((caps[1].to_string(), (caps[2].to_string())))
+    HASH_REGEX
+        .captures_iter(url_fragment)
+        .filter(|caps| !caps[2].is_empty() && caps[2].bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(|caps| (caps[1].to_string(), caps[2].to_string()))
+        .collect()
 }
 
 /// 从打包的文件名拆分出版本信息。
 /// 例如：pkg_name-1.2.3.tar.gz，pkg-name-1.2.3.tar.gz
 fn split_version_from_filename(filename: &str, canonical_name: &str) -> Option<usize> {
-    for (i, ch) in filename.chars().enumerate() {
+    for (i, ch) in filename.char_indices() {
         if ch != '-' {
             continue;
         }
@@ -255,37 +433,16 @@ fn is_wheel_file(filename_ext: &str) -> bool {
     filename_ext.to_lowercase() == ".whl"
 }
 
-static SUPPORT_EXTENSIONS: [&'static str; 11] = [
-    ".tar.gz",
-    ".tgz",
-    ".tar", 
-    ".zip",
-    ".tar.bz2",
-    ".tbz",
-    ".tar.xz",
-    ".txz",
-    ".tlz",
-    ".tar.lz",
-    ".tar.lzma",
-];
+/// setuptools的egg和bdist_wininst/bdist_msi等早期的二进制发布格式
+static LEGACY_EXTENSIONS: [&str; 4] = [".egg", ".exe", ".msi", ".rpm"];
 
 #[inline]
-fn is_archive_file(extension: &str) -> bool {
-    let extension = extension.to_lowercase();
-    // lazy_static! {
-    //     static ref SUPPORT_EXTENSIONS: [&'static str; 11]  = [
-    //         ".tar.gz", ".tgz", ".tar", // tar
-    //         ".zip", // zip
-    //         ".tar.bz2", ".tbz", // bz2
-    //         ".tar.xz", ".txz", ".tlz", ".tar.lz", ".tar.lzma", // xz
-    //     ];
-    // };
-
-    for ext in SUPPORT_EXTENSIONS.into_iter() {
-        if extension == ext {
-            return true;
-        }
-    }
+fn is_legacy_file(extension: &str) -> bool {
+    LEGACY_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
 
-    false
+/// 可以解压的源码包：zip或archive模块支持的tar包
+#[inline]
+fn is_archive_file(extension: &str) -> bool {
+    extension.eq_ignore_ascii_case(".zip") || is_tar_extension(extension)
 }
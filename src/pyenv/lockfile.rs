@@ -0,0 +1,327 @@
+use anyhow::{bail, Result};
+use pep508_rs::Requirement;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::super::errors::DownloadingError;
+use super::super::status::StatusUpdate;
+use super::checksum::checksum_file;
+use super::download::{download_file, mirror_of, partial_download_mirror};
+use super::installer::Installer;
+use super::project::{choose_link, fetch_project_index, find_candidates_links};
+use super::retry::with_retry;
+use super::utils::canonicalize_name;
+
+/// 锁文件格式版本
+const LOCK_VERSION: u32 = 1;
+
+/// `tgba.lock`：固定每个程序包的版本、文件名、sha256及其在各镜像的下载地址。
+///
+/// 按锁文件安装时不再读取索引页选择下载项，不同时间安装得到完全相同的文件。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockFile {
+    version: u32,
+    python_version: String,
+    platform_tag: String,
+    #[serde(rename = "package", default)]
+    packages: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedPackage {
+    name: String,
+    version: String,
+    filename: String,
+    sha256: String,
+    /// 镜像名称 -> 下载地址
+    urls: BTreeMap<String, String>,
+}
+
+impl LockFile {
+    pub fn parse(content: &str) -> Result<Self> {
+        let lock: LockFile = toml::from_str(content)?;
+        if lock.version != LOCK_VERSION {
+            bail!("不支持的锁文件版本: {}", lock.version);
+        }
+
+        Ok(lock)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// 锁文件是否为该Python版本和平台生成
+    pub fn matches(&self, python_version: &str, platform_tag: &str) -> bool {
+        self.python_version == python_version && self.platform_tag == platform_tag
+    }
+
+    pub fn python_version(&self) -> &str {
+        &self.python_version
+    }
+
+    pub fn platform_tag(&self) -> &str {
+        &self.platform_tag
+    }
+
+    pub fn packages(&self) -> &[LockedPackage] {
+        &self.packages
+    }
+
+    pub fn find(&self, name: &str) -> Option<&LockedPackage> {
+        let canonical_name = canonicalize_name(name);
+        self.packages
+            .iter()
+            .find(|package| canonicalize_name(&package.name) == canonical_name)
+    }
+
+    /// 锁定的全部程序包，作为`name==version`需求
+    pub fn requirements(&self) -> Result<Vec<Requirement>> {
+        let mut requirements = Vec::new();
+        for package in &self.packages {
+            let line = format!("{}=={}", package.name, package.version);
+            match Requirement::from_str(&line) {
+                Ok(requirement) => requirements.push(requirement),
+                Err(err) => bail!("锁文件中{}格式错误: {}", line, err),
+            }
+        }
+
+        Ok(requirements)
+    }
+}
+
+impl LockedPackage {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    pub fn urls(&self) -> &BTreeMap<String, String> {
+        &self.urls
+    }
+}
+
+/// 按锁文件下载程序包：依次从可用镜像的锁定地址下载，不读取索引页
+pub async fn download_locked_package(
+    installer: &Installer,
+    collector: &impl StatusUpdate,
+    package: &LockedPackage,
+) -> Result<String, DownloadingError> {
+    let cached_filename = installer.cached_packages_dir.join(&package.filename);
+
+    if cached_filename.is_file() {
+        match checksum_file("sha256", &cached_filename, &package.sha256) {
+            Ok(true) => {
+                log::info!("程序包{}本地已缓存，无需下载", cached_filename.display());
+                return Ok(package.filename.clone());
+            }
+            Ok(false) => {
+                log::warn!(
+                    "缓存文件{}与锁文件不一致，重新下载",
+                    cached_filename.display()
+                );
+            }
+            Err(err) => {
+                log::warn!("检查缓存文件{}出错: {}", cached_filename.display(), err);
+            }
+        }
+    }
+
+    let mirror_health = installer.mirror_health();
+
+    // 先按配置中的镜像顺序，再尝试锁文件中其它镜像的地址
+    let mut sources = Vec::new();
+    for pypi in installer.available_pypi_mirrors() {
+        if let Some(url) = package.urls.get(pypi.name()) {
+            sources.push((Some(pypi), pypi.name(), url.as_str()));
+        }
+    }
+    for (mirror_name, url) in &package.urls {
+        if sources
+            .iter()
+            .all(|(_, name, _)| *name != mirror_name.as_str())
+        {
+            sources.push((None, mirror_name.as_str(), url.as_str()));
+        }
+    }
+
+    // 上次下载中断时，优先从同一镜像续传
+    if let Some(host) = partial_download_mirror(&cached_filename) {
+        sources.sort_by_key(|(_, _, url)| mirror_of(url) != host);
+    }
+
+    let mut errors = Vec::new();
+    for (pypi, mirror_name, url) in sources {
+        let title = format!("从{}下载 {}", mirror_name, package.filename);
+        let result = with_retry(installer.retry_policy(), collector, &title, || {
+            download_file(
+                installer,
+                collector,
+                url,
+                &title,
+                &cached_filename,
+                Some(("sha256", package.sha256.as_str())),
+            )
+        })
+        .await;
+
+        match result {
+            Ok(()) => {
+                if let Some(pypi) = pypi {
+                    mirror_health.record_success(pypi);
+                }
+                return Ok(package.filename.clone());
+            }
+            Err(err) => {
+                log::error!(
+                    "从{}下载{}中发生错误: {}",
+                    mirror_name,
+                    package.filename,
+                    err
+                );
+                if let Some(pypi) = pypi {
+                    mirror_health.record_failure(pypi, &err);
+                }
+                errors.push(format!("尝试从{mirror_name}镜像下载发生错误: {err}"));
+            }
+        }
+    }
+
+    Err(DownloadingError::error(format!(
+        "下载{}发生错误:\n{}",
+        package.filename,
+        errors.join("\n")
+    )))
+}
+
+/// 为固定版本的需求生成锁文件。
+///
+/// 第一个能找到合适下载项的镜像决定文件名和sha256，其它镜像中同名且摘要相同的文件记为备用地址。
+pub async fn lock_requirements(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    requirements: &[Requirement],
+) -> Result<LockFile> {
+    let Some(platform_tag) = installer.platform_tag.as_ref() else {
+        bail!("未获取目标平台标签，无法生成锁文件")
+    };
+
+    let mut packages: Vec<LockedPackage> = Vec::new();
+    for requirement in requirements {
+        if packages
+            .iter()
+            .any(|p| canonicalize_name(&p.name) == canonicalize_name(&requirement.name))
+        {
+            continue;
+        }
+
+        status_updater.message(&format!("锁定{}", requirement));
+        packages.push(lock_requirement(installer, status_updater, requirement).await?);
+    }
+
+    packages.sort_by_key(|package| canonicalize_name(&package.name));
+
+    Ok(LockFile {
+        version: LOCK_VERSION,
+        python_version: installer.python_version.clone(),
+        platform_tag: platform_tag.clone(),
+        packages,
+    })
+}
+
+async fn lock_requirement(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    requirement: &Requirement,
+) -> Result<LockedPackage> {
+    let mut locked: Option<LockedPackage> = None;
+    let mut errors = Vec::new();
+
+    for pypi in installer.pypi_mirrors() {
+        let index =
+            match fetch_project_index(installer, status_updater, pypi, &requirement.name).await {
+                Ok(index) => index,
+                Err(err) => {
+                    errors.push(format!("{}: {}", pypi.name(), err));
+                    continue;
+                }
+            };
+
+        let candidates = match find_candidates_links(installer, &index, requirement) {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                errors.push(format!("{}: {}", pypi.name(), err));
+                continue;
+            }
+        };
+
+        match &mut locked {
+            None => {
+                let Some(link) = choose_link(installer, &candidates) else {
+                    errors.push(format!("{}: 未发现满足需求的包", pypi.name()));
+                    continue;
+                };
+
+                let Some(hexcode) = link.hash("sha256") else {
+                    errors.push(format!("{}: {}无sha256摘要", pypi.name(), link.file_name()));
+                    continue;
+                };
+
+                locked = Some(LockedPackage {
+                    name: requirement.name.clone(),
+                    version: link.package_version().to_string(),
+                    filename: link.file_name().to_string(),
+                    sha256: hexcode.to_lowercase(),
+                    urls: BTreeMap::from([(pypi.name().to_string(), link.url().to_string())]),
+                });
+            }
+            Some(package) => {
+                let same_file = candidates.iter().find(|link| {
+                    link.file_name() == package.filename
+                        && match link.hash("sha256") {
+                            Some(hexcode) => hexcode.eq_ignore_ascii_case(&package.sha256),
+                            None => false,
+                        }
+                });
+
+                if let Some(link) = same_file {
+                    package
+                        .urls
+                        .insert(pypi.name().to_string(), link.url().to_string());
+                }
+            }
+        }
+    }
+
+    match locked {
+        Some(package) => Ok(package),
+        None => bail!("无法锁定{}:\n{}", requirement, errors.join("\n")),
+    }
+}
+
+/// 写入锁文件
+pub fn write_lock_file(path: &Path, lock: &LockFile) -> Result<()> {
+    let content = format!(
+        "# 由`tgba-installer lock`生成，请勿手工修改\n{}",
+        lock.to_toml()?
+    );
+
+    if let Err(err) = std::fs::write(path, content) {
+        bail!("写入锁文件{}错误: {}", path.display(), err)
+    }
+
+    Ok(())
+}
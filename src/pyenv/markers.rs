@@ -0,0 +1,82 @@
+use anyhow::{anyhow, bail, Result};
+use pep508_rs::{MarkerEnvironment, StringVersion};
+use std::str::FromStr;
+
+/// 由目标Python输出的`markers`（见venv.rs中的PLATFORM_INFO_SCRIPT）创建PEP 508标记环境
+pub fn marker_env_from_json(value: &serde_json::Value) -> Result<MarkerEnvironment> {
+    let field = |name: &str| -> Result<String> {
+        match value[name].as_str() {
+            Some(s) => Ok(s.to_string()),
+            None => bail!("平台信息中缺少标记变量{}", name),
+        }
+    };
+
+    let version_field = |name: &str| -> Result<StringVersion> {
+        let s = field(name)?;
+        StringVersion::from_str(&s).map_err(|err| anyhow!("标记变量{}={}错误: {}", name, s, err))
+    };
+
+    Ok(MarkerEnvironment {
+        implementation_name: field("implementation_name")?,
+        implementation_version: version_field("implementation_version")?,
+        os_name: field("os_name")?,
+        platform_machine: field("platform_machine")?,
+        platform_python_implementation: field("platform_python_implementation")?,
+        platform_release: field("platform_release")?,
+        platform_system: field("platform_system")?,
+        platform_version: field("platform_version")?,
+        python_full_version: version_field("python_full_version")?,
+        python_version: version_field("python_version")?,
+        sys_platform: field("sys_platform")?,
+    })
+}
+
+/// 虚拟环境尚未创建时，由目标三元组和Python版本推断PEP 508标记环境。
+///
+/// `platform_release`和`platform_version`取决于运行的系统，无法推断时为空字符串
+pub fn marker_env_for_target(python_full_version: &str, target: &str) -> Result<MarkerEnvironment> {
+    let arch = target.split('-').next().unwrap_or_default();
+
+    let (os_name, sys_platform, system, machine) = if target.contains("-windows-") {
+        let machine = match arch {
+            "x86_64" => "AMD64",
+            "i686" | "i586" | "x86" => "x86",
+            "aarch64" => "ARM64",
+            _ => bail!("不支持的目标平台: {}", target),
+        };
+        ("nt", "win32", "Windows", machine)
+    } else if target.contains("-apple-darwin") {
+        let machine = match arch {
+            "aarch64" | "arm64" => "arm64",
+            _ => arch,
+        };
+        ("posix", "darwin", "Darwin", machine)
+    } else if target.contains("-linux-") {
+        ("posix", "linux", "Linux", arch)
+    } else {
+        bail!("不支持的目标平台: {}", target)
+    };
+
+    let python_version = match python_full_version.splitn(3, '.').collect::<Vec<&str>>()[..] {
+        [major, minor, ..] => format!("{}.{}", major, minor),
+        _ => bail!("Python版本号{}格式错误", python_full_version),
+    };
+
+    let version = |s: &str| -> Result<StringVersion> {
+        StringVersion::from_str(s).map_err(|err| anyhow!("Python版本号{}错误: {}", s, err))
+    };
+
+    Ok(MarkerEnvironment {
+        implementation_name: "cpython".to_string(),
+        implementation_version: version(python_full_version)?,
+        os_name: os_name.to_string(),
+        platform_machine: machine.to_string(),
+        platform_python_implementation: "CPython".to_string(),
+        platform_release: String::new(),
+        platform_system: system.to_string(),
+        platform_version: String::new(),
+        python_full_version: version(python_full_version)?,
+        python_version: version(&python_version)?,
+        sys_platform: sys_platform.to_string(),
+    })
+}
@@ -0,0 +1,559 @@
+use anyhow::{bail, Result};
+use pep508_rs::Requirement;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::super::errors::{DownloadingError, DownloadingErrorKind};
+use super::super::status::StatusUpdate;
+use super::checksum::checksum;
+use super::download::{download_file, header_value, parse_content_range, send_request};
+use super::installer::Installer;
+use super::link::PackageLink;
+use super::project::expected_hash;
+use super::retry::with_retry;
+
+/// 程序包的核心元数据（wheel中的METADATA或源码包中的PKG-INFO），只保留解析依赖所需的字段
+#[derive(Debug, Clone)]
+pub struct DistMetadata {
+    name: String,
+    version: String,
+    requires_python: Option<String>,
+    requires_dist: Vec<Requirement>,
+    provides_extra: Vec<String>,
+}
+
+impl DistMetadata {
+    /// 解析RFC 822格式的元数据头部，空行之后的正文（程序包说明）忽略
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in text.lines() {
+            if line.is_empty() {
+                break;
+            }
+
+            // 以空白开始的行是上一字段的续行
+            if line.starts_with(' ') || line.starts_with('\t') {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            headers.push((key.trim().to_lowercase(), value.trim().to_string()));
+        }
+
+        let field = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let Some(name) = field("name") else {
+            bail!("元数据中无Name字段")
+        };
+        let Some(version) = field("version") else {
+            bail!("元数据中无Version字段")
+        };
+
+        let mut requires_dist = Vec::new();
+        let mut provides_extra = Vec::new();
+        for (key, value) in &headers {
+            match key.as_str() {
+                "requires-dist" => match Requirement::from_str(value) {
+                    Ok(requirement) => requires_dist.push(requirement),
+                    Err(err) => {
+                        log::warn!("{}-{}依赖项'{}'格式错误: {}", name, version, value, err)
+                    }
+                },
+                "provides-extra" => provides_extra.push(value.clone()),
+                _ => {}
+            }
+        }
+
+        Ok(DistMetadata {
+            requires_python: field("requires-python").filter(|s| !s.is_empty()),
+            name,
+            version,
+            requires_dist,
+            provides_extra,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn requires_python(&self) -> Option<&str> {
+        self.requires_python.as_deref()
+    }
+
+    pub fn requires_dist(&self) -> &[Requirement] {
+        &self.requires_dist
+    }
+
+    pub fn provides_extra(&self) -> &[String] {
+        &self.provides_extra
+    }
+
+    /// 早期以setuptools打包的源码包在PKG-INFO中没有Requires-Dist，
+    /// 依赖写在`*.egg-info/requires.txt`中，按节`[extra:marker]`转换为带标记的依赖
+    fn merge_egg_info_requires(&mut self, text: &str) {
+        let mut section_marker: Option<String> = None;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                let (extra, marker) = match section.split_once(':') {
+                    Some((extra, marker)) => (extra.trim(), marker.trim()),
+                    None => (section.trim(), ""),
+                };
+
+                section_marker = match (extra.is_empty(), marker.is_empty()) {
+                    (true, true) => None,
+                    (false, true) => Some(format!("extra == \"{extra}\"")),
+                    (true, false) => Some(format!("({marker})")),
+                    (false, false) => Some(format!("extra == \"{extra}\" and ({marker})")),
+                };
+                continue;
+            }
+
+            let requirement = match &section_marker {
+                Some(marker) => format!("{line} ; {marker}"),
+                None => line.to_string(),
+            };
+
+            match Requirement::from_str(&requirement) {
+                Ok(requirement) => self.requires_dist.push(requirement),
+                Err(err) => log::warn!(
+                    "{}-{}依赖项'{}'格式错误: {}",
+                    self.name,
+                    self.version,
+                    requirement,
+                    err
+                ),
+            }
+        }
+    }
+}
+
+/// 获取下载项的核心元数据。
+///
+/// 索引提供PEP 658元数据文件时直接下载该文件；wheel包以`Range`请求只读取其中的METADATA，
+/// 镜像不支持`Range`时下载整个wheel到缓存目录；源码包下载后读取PKG-INFO。
+pub async fn fetch_dist_metadata(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    name: &str,
+    link: &PackageLink,
+) -> Result<DistMetadata, DownloadingError> {
+    let title = format!("获取{}元数据", link.file_name());
+    let hash_pins = installer.hash_pins(name);
+
+    let fetch = move || async move {
+        if let Some(text) = fetch_core_metadata_file(installer, link).await? {
+            return Ok(text);
+        }
+
+        if link.is_wheel() {
+            if let Some(text) = fetch_wheel_metadata_by_range(installer, link).await? {
+                return Ok(text);
+            }
+        }
+
+        let cached_filename = installer.cached_packages_dir.join(link.file_name());
+        if !cached_filename.is_file() {
+            // 与下载程序包相同，按hash_policy处理没有摘要的链接
+            let expected_hash = expected_hash(installer, name, link, hash_pins)?;
+            download_file(
+                installer,
+                status_updater,
+                link.url(),
+                &format!("下载 {}", link.file_name()),
+                &cached_filename,
+                expected_hash,
+            )
+            .await?;
+        }
+
+        read_metadata_from_file(link, &cached_filename).map_err(|err| {
+            DownloadingError::error(format!("读取{}元数据错误: {}", link.file_name(), err))
+        })
+    };
+
+    let (text, requires) =
+        with_retry(installer.retry_policy(), status_updater, &title, fetch).await?;
+
+    let mut metadata = match DistMetadata::parse(&text) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            return Err(DownloadingError::error(format!(
+                "解析{}元数据错误: {}",
+                link.file_name(),
+                err
+            )))
+        }
+    };
+
+    if let Some(requires) = requires {
+        if metadata.requires_dist.is_empty() {
+            metadata.merge_egg_info_requires(&requires);
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// (METADATA/PKG-INFO内容, 源码包中egg-info/requires.txt的内容)
+type MetadataText = (String, Option<String>);
+
+/// PEP 658：下载与文件同名加`.metadata`的元数据文件
+async fn fetch_core_metadata_file(
+    installer: &Installer,
+    link: &PackageLink,
+) -> Result<Option<MetadataText>, DownloadingError> {
+    let (Some(core_metadata), Some(url)) = (link.core_metadata(), link.core_metadata_url()) else {
+        return Ok(None);
+    };
+
+    let resp = match send_request(installer, installer.client.get(&url), &url).await {
+        Ok(resp) => resp,
+        // 有的镜像只转发了索引中的属性而没有同步元数据文件
+        Err(err) if err.kind() == DownloadingErrorKind::NotFound => {
+            log::warn!("镜像缺少元数据文件: {}", url);
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+
+    let data = read_body(installer, resp, &url).await?;
+
+    if let Some((method, hexcode)) = core_metadata.checksum() {
+        match checksum(method, &data, hexcode) {
+            Ok(true) => {}
+            Ok(false) => {
+                return Err(DownloadingError::error(format!(
+                    "元数据文件{}完整检验与原文件不一致",
+                    url
+                )))
+            }
+            Err(err) => log::warn!("无法校验元数据文件{}: {}", url, err),
+        }
+    }
+
+    Ok(Some((String::from_utf8_lossy(&data).to_string(), None)))
+}
+
+async fn read_body(
+    installer: &Installer,
+    resp: reqwest::Response,
+    url: &str,
+) -> Result<Vec<u8>, DownloadingError> {
+    let Ok(result) = tokio::time::timeout(installer.read_timeout(), resp.bytes()).await else {
+        return Err(DownloadingError::timeout_error(format!(
+            "下载{}超过{}秒未收到数据",
+            url,
+            installer.read_timeout().as_secs()
+        )));
+    };
+
+    match result {
+        Ok(data) => Ok(data.to_vec()),
+        Err(err) if err.is_timeout() => {
+            Err(DownloadingError::timeout_error(format!("下载{}", url)))
+        }
+        Err(err) => Err(DownloadingError::server_error(format!("{}", err))),
+    }
+}
+
+/// 请求文件的一段，range为`Range`头的值。
+/// 镜像按请求返回206时返回(数据, 文件总长度)，不支持`Range`时返回None
+async fn fetch_range(
+    installer: &Installer,
+    url: &str,
+    range: &str,
+) -> Result<Option<(Vec<u8>, u64)>, DownloadingError> {
+    let request = installer.client.get(url).header(RANGE, range);
+    let resp = send_request(installer, request, url).await?;
+
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return Ok(None);
+    }
+
+    let Some((_, total_size)) = header_value(&resp, CONTENT_RANGE)
+        .as_deref()
+        .and_then(parse_content_range)
+    else {
+        return Ok(None);
+    };
+
+    let data = read_body(installer, resp, url).await?;
+    Ok(Some((data, total_size)))
+}
+
+/// wheel文件尾部读取的长度，足以容纳绝大多数wheel的中央目录
+const WHEEL_TAIL_SIZE: u64 = 64 * 1024;
+
+const EOCD_SIGNATURE: u32 = 0x06054b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const LOCAL_HEADER_SIZE: u64 = 30;
+
+/// 以`Range`请求读取zip的中央目录，找到`*.dist-info/METADATA`后只下载这一项。
+/// 镜像不支持`Range`或者zip结构无法识别（如zip64）时返回None
+async fn fetch_wheel_metadata_by_range(
+    installer: &Installer,
+    link: &PackageLink,
+) -> Result<Option<MetadataText>, DownloadingError> {
+    let url = link.url();
+
+    let Some((tail, total_size)) =
+        fetch_range(installer, url, &format!("bytes=-{}", WHEEL_TAIL_SIZE)).await?
+    else {
+        return Ok(None);
+    };
+    let tail_start = total_size.saturating_sub(tail.len() as u64);
+
+    let Some(eocd) = find_eocd(&tail) else {
+        return Ok(None);
+    };
+    let cd_size = read_u32(&tail, eocd + 12) as u64;
+    let cd_offset = read_u32(&tail, eocd + 16) as u64;
+    if cd_offset == 0xFFFFFFFF || cd_size == 0xFFFFFFFF {
+        return Ok(None); // zip64
+    }
+    if cd_size == 0 {
+        return Ok(None); // 空的zip
+    }
+
+    // 记录损坏时中央目录可能超出文件范围
+    let Some(cd_end) = within_file(cd_offset, cd_size, total_size) else {
+        return Ok(None);
+    };
+
+    let central_dir = if cd_offset >= tail_start {
+        let start = (cd_offset - tail_start) as usize;
+        let end = (cd_end - tail_start) as usize;
+        if end > tail.len() {
+            return Ok(None);
+        }
+        tail[start..end].to_vec()
+    } else {
+        let range = format!("bytes={}-{}", cd_offset, cd_end - 1);
+        let Some((data, _)) = fetch_range(installer, url, &range).await? else {
+            return Ok(None);
+        };
+        data
+    };
+
+    let Some(entry) = find_metadata_entry(&central_dir) else {
+        return Ok(None);
+    };
+
+    let Some(header_end) = within_file(entry.local_offset, LOCAL_HEADER_SIZE, total_size) else {
+        return Ok(None);
+    };
+
+    let range = format!("bytes={}-{}", entry.local_offset, header_end - 1);
+    let Some((header, _)) = fetch_range(installer, url, &range).await? else {
+        return Ok(None);
+    };
+    if header.len() < LOCAL_HEADER_SIZE as usize || read_u32(&header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Ok(None);
+    }
+
+    let name_len = read_u16(&header, 26) as u64;
+    let extra_len = read_u16(&header, 28) as u64;
+    let data_start = header_end + name_len + extra_len;
+
+    let text = if entry.compressed_size == 0 {
+        Vec::new()
+    } else {
+        let Some(data_end) = within_file(data_start, entry.compressed_size, total_size) else {
+            return Ok(None);
+        };
+
+        let range = format!("bytes={}-{}", data_start, data_end - 1);
+        let Some((data, _)) = fetch_range(installer, url, &range).await? else {
+            return Ok(None);
+        };
+
+        match entry.method {
+            0 => data,
+            8 => {
+                let mut text = Vec::new();
+                let mut decoder = flate2::read::DeflateDecoder::new(data.as_slice());
+                if let Err(err) = decoder.read_to_end(&mut text) {
+                    return Err(DownloadingError::error(format!(
+                        "解压{}中METADATA错误: {}",
+                        link.file_name(),
+                        err
+                    )));
+                }
+                text
+            }
+            _ => return Ok(None),
+        }
+    };
+
+    Ok(Some((String::from_utf8_lossy(&text).to_string(), None)))
+}
+
+/// 从offset开始长size的一段在长total_size的文件之内时返回其结束位置
+fn within_file(offset: u64, size: u64, total_size: u64) -> Option<u64> {
+    offset.checked_add(size).filter(|end| *end <= total_size)
+}
+
+struct ZipEntry {
+    method: u16,
+    compressed_size: u64,
+    local_offset: u64,
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+/// 从尾部向前查找中央目录结束记录
+fn find_eocd(tail: &[u8]) -> Option<usize> {
+    if tail.len() < 22 {
+        return None;
+    }
+
+    (0..=tail.len() - 22)
+        .rev()
+        .find(|&pos| read_u32(tail, pos) == EOCD_SIGNATURE)
+}
+
+/// 在中央目录中查找顶层`*.dist-info/METADATA`
+fn find_metadata_entry(central_dir: &[u8]) -> Option<ZipEntry> {
+    let mut pos = 0;
+    while pos + 46 <= central_dir.len() {
+        if read_u32(central_dir, pos) != CENTRAL_HEADER_SIGNATURE {
+            return None;
+        }
+
+        let method = read_u16(central_dir, pos + 10);
+        let compressed_size = read_u32(central_dir, pos + 20) as u64;
+        let name_len = read_u16(central_dir, pos + 28) as usize;
+        let extra_len = read_u16(central_dir, pos + 30) as usize;
+        let comment_len = read_u16(central_dir, pos + 32) as usize;
+        let local_offset = read_u32(central_dir, pos + 42) as u64;
+
+        let name_end = pos + 46 + name_len;
+        if name_end > central_dir.len() {
+            return None;
+        }
+        let name = String::from_utf8_lossy(&central_dir[pos + 46..name_end]);
+
+        if is_top_level_metadata(&name, "METADATA") {
+            if compressed_size == 0xFFFFFFFF || local_offset == 0xFFFFFFFF {
+                return None; // zip64
+            }
+
+            return Some(ZipEntry {
+                method,
+                compressed_size,
+                local_offset,
+            });
+        }
+
+        pos = name_end + extra_len + comment_len;
+    }
+
+    None
+}
+
+/// `<dir>/<file_name>`，且dir以`.dist-info`（wheel）或无后缀（源码包）结尾
+fn is_top_level_metadata(path: &str, file_name: &str) -> bool {
+    let Some((dir, name)) = path.split_once('/') else {
+        return false;
+    };
+
+    name == file_name && (dir.ends_with(".dist-info") || file_name == "PKG-INFO")
+}
+
+/// 从已下载的wheel或源码包读取元数据
+fn read_metadata_from_file(link: &PackageLink, path: &Path) -> Result<MetadataText> {
+    let extension = link.filename_extension().to_lowercase();
+
+    if link.is_wheel() || extension == ".zip" {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(path)?)?;
+
+        let metadata_name = if link.is_wheel() {
+            "METADATA"
+        } else {
+            "PKG-INFO"
+        };
+        let mut metadata = None;
+        let mut requires = None;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let name = entry.name().to_string();
+
+            if metadata.is_none() && is_top_level_metadata(&name, metadata_name) {
+                let mut text = String::new();
+                entry.read_to_string(&mut text)?;
+                metadata = Some(text);
+            } else if requires.is_none() && name.ends_with(".egg-info/requires.txt") {
+                let mut text = String::new();
+                entry.read_to_string(&mut text)?;
+                requires = Some(text);
+            }
+        }
+
+        return match metadata {
+            Some(metadata) => Ok((metadata, requires)),
+            None => bail!("{}中没有{}", link.file_name(), metadata_name),
+        };
+    }
+
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let reader: Box<dyn Read> = match extension.as_str() {
+        ".tar.gz" | ".tgz" => Box::new(flate2::bufread::GzDecoder::new(file)),
+        ".tar.bz2" | ".tbz" => Box::new(bzip2::bufread::BzDecoder::new(file)),
+        ".tar" => Box::new(file),
+        _ => bail!("不支持读取{}格式源码包的元数据", extension),
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    let mut metadata = None;
+    let mut requires = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+
+        if metadata.is_none() && is_top_level_metadata(&name, "PKG-INFO") {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            metadata = Some(text);
+        } else if requires.is_none() && name.ends_with(".egg-info/requires.txt") {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            requires = Some(text);
+        }
+    }
+
+    match metadata {
+        Some(metadata) => Ok((metadata, requires)),
+        None => bail!("{}中没有PKG-INFO", link.file_name()),
+    }
+}
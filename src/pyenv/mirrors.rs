@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::super::errors::{DownloadingError, DownloadingErrorKind};
+use super::super::status::StatusUpdate;
+use super::config::PyPIMirror;
+use super::installer::Installer;
+
+/// 本次安装中各镜像的健康状况。
+///
+/// 某个镜像连续出现超时或服务错误达到阈值后断路，之后的程序包不再从该镜像下载；
+/// Installer的各个克隆共享同一状态。
+#[derive(Debug, Clone)]
+pub struct MirrorHealth {
+    failure_threshold: u32,
+    states: Arc<Mutex<HashMap<String, MirrorState>>>,
+}
+
+#[derive(Debug, Default)]
+struct MirrorState {
+    consecutive_failures: u32,
+    tripped: bool,
+}
+
+impl MirrorHealth {
+    pub fn new(failure_threshold: u32) -> Self {
+        MirrorHealth {
+            failure_threshold,
+            states: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_available(&self, mirror: &PyPIMirror) -> bool {
+        let states = self.states.lock().unwrap();
+        match states.get(mirror.name()) {
+            Some(state) => !state.tripped,
+            None => true,
+        }
+    }
+
+    pub fn record_success(&self, mirror: &PyPIMirror) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(mirror.name().to_string()).or_default();
+        state.consecutive_failures = 0;
+    }
+
+    /// 记录一次失败。程序包不存在等与镜像本身无关的错误不计入
+    pub fn record_failure(&self, mirror: &PyPIMirror, err: &DownloadingError) {
+        match err.kind() {
+            DownloadingErrorKind::Timeout | DownloadingErrorKind::ServerError => {}
+            _ => return,
+        }
+
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(mirror.name().to_string()).or_default();
+        state.consecutive_failures += 1;
+
+        if self.failure_threshold > 0
+            && !state.tripped
+            && state.consecutive_failures >= self.failure_threshold
+        {
+            state.tripped = true;
+            log::warn!(
+                "镜像{}连续失败{}次，本次安装中不再使用",
+                mirror.name(),
+                state.consecutive_failures
+            );
+        }
+    }
+}
+
+/// 镜像测速结果
+#[derive(Debug, Clone)]
+pub struct MirrorProbe {
+    name: String,
+    latency: Option<Duration>,
+    elapsed: Option<Duration>,
+    throughput: Option<f64>, // 字节每秒
+    error: Option<String>,
+}
+
+impl MirrorProbe {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    pub fn throughput(&self) -> Option<f64> {
+        self.throughput
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn summary(&self) -> String {
+        match (self.latency, &self.error) {
+            (Some(latency), None) => format!("{}({}ms)", self.name, latency.as_millis()),
+            _ => format!("{}(不可用)", self.name),
+        }
+    }
+}
+
+/// 用于测速的程序包索引页，各镜像都有且大小适中
+static PROBE_PROJECT: &str = "pip";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 测量各镜像获取同一索引页的延迟和吞吐量，按用时从短到长重新排列installer中的镜像顺序，
+/// 测速失败的镜像排在最后。
+pub async fn probe_mirrors(
+    installer: &mut Installer,
+    status_updater: &impl StatusUpdate,
+) -> Vec<MirrorProbe> {
+    status_updater.message("测试PyPI镜像速度");
+
+    let mut results = {
+        let installer: &Installer = installer;
+        let probes = installer
+            .pypi_mirrors()
+            .iter()
+            .map(|mirror| probe_mirror(installer, mirror));
+        futures::future::join_all(probes).await
+    };
+
+    results.sort_by(|a, b| match (a.elapsed, b.elapsed) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+
+    for probe in &results {
+        match &probe.error {
+            None => log::info!(
+                "镜像{}: 延迟{}ms，速度{:.0}KiB/s",
+                probe.name,
+                probe.latency.unwrap_or_default().as_millis(),
+                probe.throughput.unwrap_or_default() / 1024.0
+            ),
+            Some(err) => log::warn!("镜像{}测速失败: {}", probe.name, err),
+        }
+    }
+
+    let order = results
+        .iter()
+        .map(|probe| probe.name.clone())
+        .collect::<Vec<String>>();
+    installer.set_mirror_order(&order);
+
+    let summary = results
+        .iter()
+        .map(|probe| probe.summary())
+        .collect::<Vec<String>>()
+        .join(" > ");
+    status_updater.message(&format!("镜像顺序: {summary}"));
+
+    results
+}
+
+async fn probe_mirror(installer: &Installer, mirror: &PyPIMirror) -> MirrorProbe {
+    let mut probe = MirrorProbe {
+        name: mirror.name().to_string(),
+        latency: None,
+        elapsed: None,
+        throughput: None,
+        error: None,
+    };
+
+    let url = mirror.package_url(PROBE_PROJECT);
+    let start_time = Instant::now();
+
+    let result = tokio::time::timeout(PROBE_TIMEOUT, async {
+        let resp = installer.client.get(&url).send().await?;
+        let latency = start_time.elapsed();
+        let resp = resp.error_for_status()?;
+        let body = resp.bytes().await?;
+
+        Ok::<_, reqwest::Error>((latency, body.len()))
+    })
+    .await;
+
+    match result {
+        Ok(Ok((latency, size))) => {
+            let elapsed = start_time.elapsed();
+            probe.latency = Some(latency);
+            probe.elapsed = Some(elapsed);
+            probe.throughput = Some(size as f64 / elapsed.as_secs_f64().max(1e-3));
+        }
+        Ok(Err(err)) => probe.error = Some(err.to_string()),
+        Err(_) => probe.error = Some(format!("超过{}秒无响应", PROBE_TIMEOUT.as_secs())),
+    }
+
+    probe
+}
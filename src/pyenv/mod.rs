@@ -1,27 +1,54 @@
 mod archive;
+mod bundle;
+mod checksum;
 mod config;
 mod download;
 mod fonts;
 mod installer;
 mod link;
+mod lockfile;
+mod markers;
+mod metadata;
+mod mirrors;
+mod network;
+mod pipevents;
 mod project;
+mod reqfile;
 mod requirements;
+mod resolver;
+mod retry;
+mod scheduler;
+mod sdist;
 mod scriptfixs;
+mod tags;
+#[cfg(test)]
+mod testutil;
 mod utils;
+mod wheel;
 mod winlnk;
 
 pub mod venv;
 
+pub use bundle::{create_bundle, Bundle, DEFAULT_BUNDLE_NAME};
 pub use installer::Installer;
 pub use link::PackageLink;
+pub use lockfile::{lock_requirements, write_lock_file, LockFile};
+pub use mirrors::{probe_mirrors, MirrorProbe};
+pub use network::NetworkOverrides;
 pub use project::ProjectIndex;
+pub use tags::CustomTarget;
 
-pub use archive::{checksum, unpack_archive};
+pub use archive::unpack_archive;
+pub use checksum::{checksum, checksum_file};
 pub use requirements::{
-    offline_install_requirements, prepare_requirements, retry_download_requirement,
+    embedded_requirements, offline_install_requirements, prepare_requirements,
+    resolve_top_level_requirements, retry_download_requirement, write_requirements_file,
 };
 pub use venv::{ensure_python_dist, ensure_venv, set_platform_info};
 
+pub use scheduler::download_requirements;
+pub use sdist::build_sdist_wheels;
+
 pub use scriptfixs::fix_patches;
 pub use scriptfixs::clean_cached_dir;
 pub use winlnk::create_winlnk;
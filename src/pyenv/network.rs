@@ -0,0 +1,167 @@
+use anyhow::{bail, Result};
+use serde_derive::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// HTTP客户端的网络设置，对应config.toml中的`[network]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// 建立连接的超时秒数
+    connect_timeout: u64,
+    /// 等待响应头或两次收到数据之间的超时秒数
+    read_timeout: u64,
+    /// HTTP(S)代理地址，如`http://proxy.example.edu:8080`
+    proxy: Option<String>,
+    proxy_username: Option<String>,
+    proxy_password: Option<String>,
+    /// 不经过代理的主机，逗号分隔
+    no_proxy: Option<String>,
+    /// 额外信任的CA根证书文件，PEM（可包含多个证书）或DER格式
+    ca_certs: Vec<PathBuf>,
+    /// 是否采用环境变量`HTTPS_PROXY`、`HTTP_PROXY`、系统代理设置和`PIP_INDEX_URL`
+    trust_env: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            connect_timeout: 15,
+            read_timeout: 60,
+            proxy: None,
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: None,
+            ca_certs: Vec::new(),
+            trust_env: true,
+        }
+    }
+}
+
+/// 命令行参数对网络设置的覆盖，未给出的项沿用配置文件
+#[derive(Debug, Clone, Default)]
+pub struct NetworkOverrides {
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub proxy: Option<String>,
+    pub ca_certs: Vec<PathBuf>,
+    pub trust_env: Option<bool>,
+}
+
+impl NetworkConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout.max(1))
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout.max(1))
+    }
+
+    /// 合并命令行参数，命令行给出的CA证书追加在配置文件的证书之后
+    pub fn with_overrides(&self, overrides: &NetworkOverrides) -> NetworkConfig {
+        let mut config = self.clone();
+
+        if let Some(connect_timeout) = overrides.connect_timeout {
+            config.connect_timeout = connect_timeout;
+        }
+        if let Some(read_timeout) = overrides.read_timeout {
+            config.read_timeout = read_timeout;
+        }
+        if let Some(proxy) = &overrides.proxy {
+            config.proxy = Some(proxy.clone());
+        }
+        if let Some(trust_env) = overrides.trust_env {
+            config.trust_env = trust_env;
+        }
+        config.ca_certs.extend(overrides.ca_certs.iter().cloned());
+
+        config
+    }
+
+    /// 环境变量`PIP_INDEX_URL`指定的镜像地址，仅在trust_env时采用
+    pub fn env_index_url(&self) -> Option<String> {
+        if !self.trust_env {
+            return None;
+        }
+
+        match std::env::var("PIP_INDEX_URL") {
+            Ok(url) if !url.trim().is_empty() => Some(url.trim().to_string()),
+            _ => None,
+        }
+    }
+
+    /// 按网络设置创建HTTP客户端，索引页和文件下载共用该客户端
+    pub fn build_client(&self, user_agent: &str) -> Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(user_agent)
+            .connect_timeout(self.connect_timeout());
+
+        if let Some(proxy_url) = &self.proxy {
+            let mut proxy = match reqwest::Proxy::all(proxy_url) {
+                Ok(proxy) => proxy,
+                Err(err) => bail!("代理地址{}错误: {}", proxy_url, err),
+            };
+
+            if let Some(username) = &self.proxy_username {
+                let password = self.proxy_password.as_deref().unwrap_or_default();
+                proxy = proxy.basic_auth(username, password);
+            }
+
+            if let Some(no_proxy) = &self.no_proxy {
+                proxy = proxy.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+            }
+
+            log::info!("使用HTTP代理: {}", proxy_url);
+            builder = builder.proxy(proxy);
+        } else if !self.trust_env {
+            // 不采用环境变量和系统的代理设置
+            builder = builder.no_proxy();
+        }
+
+        for path in &self.ca_certs {
+            for cert in load_certificates(path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+            log::info!("信任CA证书: {}", path.display());
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// 读取证书文件，PEM文件可以包含多个证书，否则按DER格式读取
+fn load_certificates(path: &Path) -> Result<Vec<reqwest::Certificate>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) => bail!("读取CA证书{}错误: {}", path.display(), err),
+    };
+
+    const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const PEM_END: &str = "-----END CERTIFICATE-----";
+
+    let text = String::from_utf8_lossy(&data);
+    if !text.contains(PEM_BEGIN) {
+        return match reqwest::Certificate::from_der(&data) {
+            Ok(cert) => Ok(vec![cert]),
+            Err(err) => bail!("CA证书{}格式错误: {}", path.display(), err),
+        };
+    }
+
+    let mut certs = Vec::new();
+    let mut rest = text.as_ref();
+    while let Some(begin) = rest.find(PEM_BEGIN) {
+        let Some(end) = rest[begin..].find(PEM_END) else {
+            bail!("CA证书{}不完整", path.display())
+        };
+        let end = begin + end + PEM_END.len();
+
+        match reqwest::Certificate::from_pem(&rest.as_bytes()[begin..end]) {
+            Ok(cert) => certs.push(cert),
+            Err(err) => bail!("CA证书{}格式错误: {}", path.display(), err),
+        }
+
+        rest = &rest[end..];
+    }
+
+    Ok(certs)
+}
@@ -0,0 +1,287 @@
+use std::io::{BufRead, BufReader, Read};
+use std::sync::mpsc;
+
+use super::super::errors::{InstallingError, InstallingErrorKind};
+use super::super::utils::detect_decode;
+use super::utils::split_filename_extension;
+
+/// pip安装过程中输出的一行所表示的事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipEvent {
+    Collecting(String),       // 开始收集的需求的程序包名称
+    Processing(String),       // 正在处理的本地文件对应的程序包名称
+    AlreadySatisfied(String), // 已安装的程序包名称
+    Installing(Vec<String>),  // 即将安装的程序包名称
+    Installed(Vec<String>),   // 安装成功的{name}-{version}
+    Error(String),            // ERROR:之后的错误信息
+}
+
+/// 解析pip输出的一行，与安装进度无关的行返回None
+pub fn parse_pip_line(line: &str) -> Option<PipEvent> {
+    let line = line.trim();
+
+    if let Some(rest) = line.strip_prefix("Collecting ") {
+        return Some(PipEvent::Collecting(leading_name(rest)));
+    }
+
+    if let Some(rest) = line.strip_prefix("Processing ") {
+        // 作为依赖处理时路径后带有`(from 需求方)`
+        let path = rest.split(" (from ").next().unwrap_or(rest);
+        return Some(PipEvent::Processing(file_package_name(path.trim())));
+    }
+
+    if let Some(rest) = line.strip_prefix("Requirement already satisfied: ") {
+        return Some(PipEvent::AlreadySatisfied(leading_name(rest)));
+    }
+
+    if let Some(rest) = line.strip_prefix("Installing collected packages: ") {
+        return Some(PipEvent::Installing(split_list(rest, ',')));
+    }
+
+    if let Some(rest) = line.strip_prefix("Successfully installed ") {
+        return Some(PipEvent::Installed(split_list(rest, ' ')));
+    }
+
+    if let Some(rest) = line.strip_prefix("ERROR: ") {
+        return Some(PipEvent::Error(rest.trim().to_string()));
+    }
+
+    None
+}
+
+/// 需求字符串开头的程序包名称，如`numpy>=1.20 (from ...)`中的numpy
+fn leading_name(text: &str) -> String {
+    text.trim()
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect()
+}
+
+/// 本地文件路径对应的程序包名称，wheel为{name}-{version}-...，源码包为{name}-{version}
+fn file_package_name(path: &str) -> String {
+    let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+
+    let base = match split_filename_extension(file_name) {
+        Ok((base, extension)) if extension.eq_ignore_ascii_case(".whl") => {
+            return base.split('-').next().unwrap_or(base).to_string();
+        }
+        Ok((base, _)) => base,
+        Err(_) => file_name,
+    };
+
+    match base.rsplit_once('-') {
+        Some((name, _)) => name.to_string(),
+        None => base.to_string(),
+    }
+}
+
+fn split_list(text: &str, sep: char) -> Vec<String> {
+    text.split(sep)
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+/// 根据pip的输出判断失败原因。错误信息中没有程序包名称时，
+/// 以最后一个开始处理的程序包作为出错的程序包
+pub fn classify_pip_error(lines: &[String], last_package: Option<&str>) -> InstallingError {
+    let last_package = last_package.map(|s| s.to_string());
+
+    for line in lines {
+        let line = line.trim().trim_start_matches("ERROR: ");
+
+        if let Some(rest) =
+            line.strip_prefix("Could not find a version that satisfies the requirement ")
+        {
+            return InstallingError::new(
+                InstallingErrorKind::NotFound,
+                Some(leading_name(rest)),
+                line.to_string(),
+            );
+        }
+
+        if let Some(rest) = line.strip_prefix("No matching distribution found for ") {
+            return InstallingError::new(
+                InstallingErrorKind::NotFound,
+                Some(leading_name(rest)),
+                line.to_string(),
+            );
+        }
+
+        if let Some(rest) = line
+            .strip_prefix("Failed building wheel for ")
+            .or_else(|| line.strip_prefix("Failed to build "))
+        {
+            return InstallingError::new(
+                InstallingErrorKind::BuildFailed,
+                Some(leading_name(rest)),
+                line.to_string(),
+            );
+        }
+
+        if let Some(rest) = line.strip_prefix("Cannot install ") {
+            return InstallingError::new(
+                InstallingErrorKind::Conflict,
+                Some(leading_name(rest)),
+                line.to_string(),
+            );
+        }
+
+        if line.contains("ResolutionImpossible") {
+            return InstallingError::new(
+                InstallingErrorKind::Conflict,
+                last_package,
+                line.to_string(),
+            );
+        }
+
+        if line.starts_with("Could not install packages due to an OSError") {
+            return InstallingError::new(InstallingErrorKind::Io, last_package, line.to_string());
+        }
+    }
+
+    let errors = lines
+        .iter()
+        .filter_map(|line| line.trim().strip_prefix("ERROR: "))
+        .collect::<Vec<&str>>();
+    let message = if errors.is_empty() {
+        // 没有ERROR行时取最后几行输出
+        let start = lines.len().saturating_sub(5);
+        lines[start..].join("\n")
+    } else {
+        errors.join("\n")
+    };
+
+    InstallingError::new(InstallingErrorKind::Other, last_package, message)
+}
+
+/// 在后台线程中逐行读取子进程的stdout和stderr，合并到一个通道中
+pub fn read_lines_in_background(
+    stdout: impl Read + Send + 'static,
+    stderr: impl Read + Send + 'static,
+) -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    let stderr_sender = sender.clone();
+    std::thread::spawn(move || forward_lines(stdout, sender));
+    std::thread::spawn(move || forward_lines(stderr, stderr_sender));
+
+    receiver
+}
+
+fn forward_lines(reader: impl Read, sender: mpsc::Sender<String>) {
+    let reader = BufReader::new(reader);
+    for line in reader.split(b'\n') {
+        let Ok(line) = line else {
+            break;
+        };
+
+        let line = detect_decode(&line).trim_end().to_string();
+        if sender.send(line).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcript(text: &str) -> Vec<String> {
+        text.lines().map(|line| line.to_string()).collect()
+    }
+
+    fn events(lines: &[String]) -> Vec<PipEvent> {
+        lines
+            .iter()
+            .filter_map(|line| parse_pip_line(line))
+            .collect()
+    }
+
+    #[test]
+    fn successful_install_events() {
+        let lines = transcript(include_str!("../../testdata/pip-install-success.txt"));
+
+        assert_eq!(
+            events(&lines),
+            vec![
+                PipEvent::Processing("alpha".to_string()),
+                PipEvent::Processing("beta".to_string()),
+                PipEvent::Installing(vec!["beta".to_string(), "alpha".to_string()]),
+                PipEvent::Installed(vec!["alpha-1.0".to_string(), "beta-2.0".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn collecting_and_satisfied_lines_name_the_package() {
+        assert_eq!(
+            parse_pip_line("Collecting numpy>=1.20 (from -r requirements.txt (line 3))"),
+            Some(PipEvent::Collecting("numpy".to_string()))
+        );
+        assert_eq!(
+            parse_pip_line(
+                "Requirement already satisfied: typing_extensions in ./venv/lib/python3.11/site-packages"
+            ),
+            Some(PipEvent::AlreadySatisfied("typing_extensions".to_string()))
+        );
+        assert_eq!(
+            parse_pip_line("  Processing ./cached/pyyaml-6.0.1.tar.gz"),
+            Some(PipEvent::Processing("pyyaml".to_string()))
+        );
+        assert_eq!(parse_pip_line("Looking in links: links"), None);
+    }
+
+    #[test]
+    fn no_matching_distribution_is_not_found() {
+        let lines = transcript(include_str!("../../testdata/pip-install-not-found.txt"));
+
+        let error = classify_pip_error(&lines, None);
+        assert_eq!(error.kind(), InstallingErrorKind::NotFound);
+        assert_eq!(error.package(), Some("gamma"));
+    }
+
+    #[test]
+    fn failed_building_wheel_is_build_failed() {
+        let lines = transcript(include_str!("../../testdata/pip-install-build-failed.txt"));
+        assert_eq!(
+            events(&lines).first(),
+            Some(&PipEvent::Processing("broken".to_string()))
+        );
+
+        let error = classify_pip_error(&lines, Some("broken"));
+        assert_eq!(error.kind(), InstallingErrorKind::BuildFailed);
+        assert_eq!(error.package(), Some("broken"));
+    }
+
+    #[test]
+    fn resolution_impossible_is_conflict() {
+        let lines = transcript(include_str!(
+            "../../testdata/pip-install-resolution-impossible.txt"
+        ));
+
+        let error = classify_pip_error(&lines, Some("beta"));
+        assert_eq!(error.kind(), InstallingErrorKind::Conflict);
+        assert_eq!(error.package(), Some("alpha"));
+
+        // 没有Cannot install行时，以最后处理的程序包作为出错的程序包
+        let lines = lines
+            .into_iter()
+            .filter(|line| !line.contains("Cannot install"))
+            .collect::<Vec<String>>();
+        let error = classify_pip_error(&lines, Some("beta"));
+        assert_eq!(error.kind(), InstallingErrorKind::Conflict);
+        assert_eq!(error.package(), Some("beta"));
+    }
+
+    #[test]
+    fn unknown_errors_keep_the_error_lines() {
+        let lines = transcript("Looking in links: links\nERROR: something odd\n");
+
+        let error = classify_pip_error(&lines, Some("alpha"));
+        assert_eq!(error.kind(), InstallingErrorKind::Other);
+        assert_eq!(error.package(), Some("alpha"));
+        assert_eq!(error.to_string(), "安装alpha错误：something odd");
+    }
+}
@@ -1,19 +1,27 @@
 use anyhow::{bail, Error, Result};
+use pep440_rs::{Operator, Version, VersionSpecifiers};
 use pep508_rs::Requirement;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
 use scraper::{Html, Selector};
-use std::fs::File;
-use std::path::PathBuf;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use url::Url;
 
 use super::super::errors::DownloadingError;
-use super::download::download;
+use super::download::download_file;
 use super::installer::Installer;
-use super::link::{parse_link_from_url, PackageLink};
-use crate::pyenv::checksum;
+use super::link::{
+    core_metadata_from_hashes, parse_core_metadata_attr, parse_link_from_url, PackageLink,
+    SkippedLink,
+};
+use super::reqfile::{direct_url_file_name, direct_url_hash};
+use super::retry::with_retry;
+use crate::pyenv::checksum_file;
 use crate::pyenv::utils::canonicalize_name;
 
 use super::super::status::StatusUpdate;
-use super::config::PyPIMirror;
+use super::config::{HashPolicy, PyPIMirror};
 
 pub struct ProjectIndex {
     pypi: PyPIMirror,
@@ -21,6 +29,7 @@ pub struct ProjectIndex {
     project_url: String,
     canonical_name: String,
     links: Vec<PackageLink>,
+    skipped_links: Vec<SkippedLink>,
 }
 
 impl ProjectIndex {
@@ -33,6 +42,7 @@ impl ProjectIndex {
             project_url: pypi.package_url(canonical_name.as_str()),
             canonical_name,
             links: Vec::new(),
+            skipped_links: Vec::new(),
         }
     }
 
@@ -47,6 +57,36 @@ impl ProjectIndex {
     pub fn project_url(&self) -> &str {
         &self.project_url
     }
+
+    pub fn canonical_name(&self) -> &str {
+        &self.canonical_name
+    }
+
+    pub fn links(&self) -> &[PackageLink] {
+        &self.links
+    }
+
+    /// 索引页中无法解析而跳过的链接
+    pub fn skipped_links(&self) -> &[SkippedLink] {
+        &self.skipped_links
+    }
+
+    fn add_parsed_link(
+        &mut self,
+        parsed: Result<PackageLink, SkippedLink>,
+    ) -> Option<&mut PackageLink> {
+        match parsed {
+            Ok(link) => {
+                self.links.push(link);
+                self.links.last_mut()
+            }
+            Err(skipped) => {
+                log::debug!("{}跳过链接{}", self.project_url, skipped);
+                self.skipped_links.push(skipped);
+                None
+            }
+        }
+    }
 }
 
 pub async fn download_requirement(
@@ -54,17 +94,349 @@ pub async fn download_requirement(
     collector: &impl StatusUpdate,
     pypi: &PyPIMirror,
     requirement: &Requirement,
-) -> Result<(), DownloadingError> {
-    let project_name = requirement.name.as_str();
+) -> Result<String, DownloadingError> {
+    let project_index = fetch_project_index(installer, collector, pypi, &requirement.name).await?;
+
+    let candidates = match find_candidates_links(installer, &project_index, &requirement) {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            return Err(DownloadingError::error(format!(
+                "页面{}，解析候选下载项目出现错误: {err}",
+                project_index.project_url()
+            )));
+        }
+    };
+
+    // 需求文件给出--hash时只考虑摘要与之相符的文件
+    let hash_pins = installer.hash_pins(&requirement.name);
+    let candidates = if hash_pins.is_empty() {
+        candidates
+    } else {
+        candidates
+            .into_iter()
+            .filter(|link| matches_hash_pins(installer, link, hash_pins))
+            .collect()
+    };
+
+    let link = if let Some(link) = choose_link(installer, &candidates) {
+        link
+    } else if !hash_pins.is_empty() {
+        return Err(DownloadingError::error(format!(
+            "未在{}发现满足需求({})且与--hash相符的包: {}",
+            pypi.name(),
+            requirement,
+            project_index.project_url()
+        )));
+    } else {
+        return Err(DownloadingError::error(format!(
+            "未在{}发现满足需求({})的包: {}",
+            pypi.name(),
+            requirement,
+            project_index.project_url()
+        )));
+    };
+
+    if let Some(reason) = link.yanked_reason() {
+        let message = if reason.is_empty() {
+            format!("注意：{}已被撤回（yanked）", link.file_name())
+        } else {
+            format!("注意：{}已被撤回（yanked）：{}", link.file_name(), reason)
+        };
+        log::warn!("{}", message);
+        collector.message(&message);
+    }
+
+    let cached_filename = &installer.cached_packages_dir.join(link.file_name());
+    let expected_hash = expected_hash(installer, &requirement.name, link, hash_pins)?;
+
+    if is_cached_file_available(
+        installer.hash_policy(),
+        cached_filename,
+        expected_hash,
+        hash_pins,
+    )? {
+        log::info!("程序包{}本地已缓存，无需下载", cached_filename.display());
+        return Ok(link.file_name().to_string());
+    }
+
+    with_retry(
+        installer.retry_policy(),
+        collector,
+        &format!("从{}下载{}", pypi.name(), link.file_name()),
+        || {
+            download_link(
+                installer,
+                collector,
+                pypi,
+                link,
+                expected_hash,
+                cached_filename,
+            )
+        },
+    )
+    .await?;
+
+    // 下载时无摘要可校验的，按--hash校验
+    if expected_hash.is_none()
+        && !hash_pins.is_empty()
+        && !verify_direct_file(cached_filename, &None, hash_pins)
+    {
+        let _ = std::fs::remove_file(cached_filename);
+        return Err(DownloadingError::error(format!(
+            "文件{}与需求文件中的摘要不符",
+            link.file_name()
+        )));
+    }
+
+    Ok(link.file_name().to_string())
+}
+
+/// 校验下载文件所用的摘要：链接提供的最强摘要，没有时按配置的`hash_policy`处理
+pub(super) fn expected_hash<'a>(
+    installer: &'a Installer,
+    name: &str,
+    link: &'a PackageLink,
+    hash_pins: &'a [(String, String)],
+) -> Result<Option<(&'a str, &'a str)>, DownloadingError> {
+    if let Some(hash) = link.strongest_checksum(installer.allow_weak_hashes()) {
+        return Ok(Some(hash));
+    }
+
+    match installer.hash_policy() {
+        HashPolicy::Reject => Err(DownloadingError::error(format!(
+            "文件{}无checksum码",
+            link.file_name()
+        ))),
+        HashPolicy::Warn => {
+            log::warn!("文件{}无checksum码，下载后不作校验", link.file_name());
+            Ok(None)
+        }
+        HashPolicy::TrustLock => {
+            // 锁文件锁定的正是这个文件时，按锁定的sha256校验
+            let locked = installer
+                .lock_file()
+                .and_then(|lock| lock.find(name))
+                .filter(|locked| locked.filename() == link.file_name());
+            if let Some(locked) = locked {
+                return Ok(Some(("sha256", locked.sha256())));
+            }
+
+            match hash_pins {
+                [] => Err(DownloadingError::error(format!(
+                    "文件{}无checksum码，锁文件和需求文件的--hash都未固定其摘要",
+                    link.file_name()
+                ))),
+                [(method, hexcode)] => Ok(Some((method.as_str(), hexcode.as_str()))),
+                _ => Ok(None), // 有多个--hash时下载后校验与其中之一相符
+            }
+        }
+    }
+}
+
+/// 下载`name @ URL`直接给出链接的需求，按链接中的`#sha256=...`和需求文件中的`--hash`校验
+pub async fn download_direct_url(
+    installer: &Installer,
+    collector: &impl StatusUpdate,
+    requirement: &Requirement,
+    url: &str,
+) -> Result<String, DownloadingError> {
+    let file_name = match direct_url_file_name(url) {
+        Ok(file_name) => file_name,
+        Err(err) => return Err(DownloadingError::error(format!("{err}"))),
+    };
+    let cached_filename = installer.cached_packages_dir.join(&file_name);
+
+    let url_hash = direct_url_hash(url);
+    let hash_pins = installer.hash_pins(&requirement.name);
+    let url = url.split('#').next().unwrap_or(url);
+
+    if cached_filename.is_file() && verify_direct_file(&cached_filename, &url_hash, hash_pins) {
+        log::info!("程序包{}本地已缓存，无需下载", cached_filename.display());
+        return Ok(file_name);
+    }
+
+    if url_hash.is_none() && hash_pins.is_empty() {
+        log::warn!("直接链接{}未给出摘要，下载后不做校验", url);
+    }
+
+    if url.starts_with("file:") {
+        let source = match Url::parse(url).map(|url| url.to_file_path()) {
+            Ok(Ok(source)) => source,
+            _ => return Err(DownloadingError::error(format!("本地文件链接错误: {url}"))),
+        };
+        if let Err(err) = std::fs::copy(&source, &cached_filename) {
+            return Err(DownloadingError::error(format!(
+                "复制本地文件{}错误: {}",
+                source.display(),
+                err
+            )));
+        }
+    } else {
+        let title = format!("下载{}", file_name);
+        let expected_hash = url_hash
+            .as_ref()
+            .map(|(method, hexcode)| (method.as_str(), hexcode.as_str()));
+        with_retry(installer.retry_policy(), collector, &title, || {
+            download_file(
+                installer,
+                collector,
+                url,
+                &title,
+                &cached_filename,
+                expected_hash,
+            )
+        })
+        .await?;
+    }
+
+    if !verify_direct_file(&cached_filename, &url_hash, hash_pins) {
+        let _ = std::fs::remove_file(&cached_filename);
+        return Err(DownloadingError::error(format!(
+            "文件{}与需求文件中的摘要不符",
+            file_name
+        )));
+    }
+
+    Ok(file_name)
+}
+
+/// 文件与链接中的摘要相符，且在给出--hash时与其中之一相符
+fn verify_direct_file(
+    path: &Path,
+    url_hash: &Option<(String, String)>,
+    hash_pins: &[(String, String)],
+) -> bool {
+    let check = |method: &str, hexcode: &str| match checksum_file(method, path, hexcode) {
+        Ok(matched) => matched,
+        Err(err) => {
+            log::warn!("检查文件{}出错: {}", path.display(), err);
+            false
+        }
+    };
+
+    if let Some((method, hexcode)) = url_hash {
+        if !check(method, hexcode) {
+            return false;
+        }
+    }
+
+    hash_pins.is_empty()
+        || hash_pins
+            .iter()
+            .any(|(method, hexcode)| check(method, hexcode))
+}
+
+/// 从按版本从高到低排列的候选下载项中选择下载项，prefer_binary时优先选择有wheel的版本
+pub fn choose_link<'a>(
+    installer: &Installer,
+    candidates: &[&'a PackageLink],
+) -> Option<&'a PackageLink> {
+    let first = *candidates.first()?;
+    if first.is_wheel() || !installer.prefer_binary() {
+        return Some(first);
+    }
+
+    match candidates.iter().find(|link| link.is_wheel()) {
+        Some(wheel) => {
+            log::info!(
+                "{}只有源码包，改用有wheel的版本{}",
+                first.file_name(),
+                wheel.file_name()
+            );
+            Some(*wheel)
+        }
+        None => Some(first),
+    }
+}
 
+/// 链接的任一摘要与--hash相符。链接没有摘要时无法比较，除hash_policy为reject外留待下载后校验
+fn matches_hash_pins(
+    installer: &Installer,
+    link: &PackageLink,
+    hash_pins: &[(String, String)],
+) -> bool {
+    if link.strongest_checksum(true).is_none() {
+        return installer.hash_policy() != HashPolicy::Reject;
+    }
+
+    hash_pins
+        .iter()
+        .any(|(pin_method, pin_hexcode)| match link.hash(pin_method) {
+            Some(hexcode) => pin_hexcode.eq_ignore_ascii_case(hexcode),
+            None => false,
+        })
+}
+
+/// 从镜像获取并解析程序包的索引页
+pub async fn fetch_project_index(
+    installer: &Installer,
+    collector: &impl StatusUpdate,
+    pypi: &PyPIMirror,
+    project_name: &str,
+) -> Result<ProjectIndex, DownloadingError> {
     let mut project_index = ProjectIndex::new(pypi, project_name);
 
-    let resp = match installer
+    let (page_format, page_content) = with_retry(
+        installer.retry_policy(),
+        collector,
+        &format!("从{}获取{}程序包索引", pypi.name(), project_name),
+        || fetch_index_page(installer, &project_index),
+    )
+    .await?;
+
+    let parsed = match page_format {
+        IndexFormat::Json => parse_index_json_page(&mut project_index, page_content.as_str()),
+        IndexFormat::Html => parse_index_html_page(&mut project_index, page_content.as_str()),
+    };
+
+    if let Err(err) = parsed {
+        return Err(DownloadingError::error(format!(
+            "页面{}，解析出现错误: {err}",
+            project_index.project_url()
+        )));
+    };
+
+    Ok(project_index)
+}
+
+/// PEP 691 JSON格式的Simple API
+static SIMPLE_JSON_CONTENT_TYPE: &str = "application/vnd.pypi.simple.v1+json";
+
+/// 优先请求JSON格式，镜像不支持时返回HTML格式
+static SIMPLE_ACCEPT: &str = "application/vnd.pypi.simple.v1+json, \
+    application/vnd.pypi.simple.v1+html;q=0.2, text/html;q=0.01";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IndexFormat {
+    Json,
+    Html,
+}
+
+/// 获取程序包索引页，按响应的Content-Type区分JSON和HTML格式
+async fn fetch_index_page(
+    installer: &Installer,
+    project_index: &ProjectIndex,
+) -> Result<(IndexFormat, String), DownloadingError> {
+    let project_name = project_index.package_name();
+
+    let timeout_error = || {
+        DownloadingError::timeout_error(format!(
+            "获取{}程序包索引数据超过{}秒无响应",
+            project_name,
+            installer.read_timeout().as_secs()
+        ))
+    };
+
+    let request = installer
         .client
         .get(project_index.project_url())
-        .send()
-        .await
-    {
+        .header(ACCEPT, SIMPLE_ACCEPT)
+        .send();
+    let Ok(result) = tokio::time::timeout(installer.read_timeout(), request).await else {
+        return Err(timeout_error());
+    };
+
+    let resp = match result {
         Ok(resp) => resp,
         Err(err) => {
             return Err(if err.is_timeout() {
@@ -91,55 +463,28 @@ pub async fn download_requirement(
         }
     }
 
-    let page_content = match resp.text().await {
-        Ok(text) => text,
-        Err(err) => {
-            return Err(if err.is_timeout() {
-                DownloadingError::timeout_error(format!("获取{}程序包索引数据", project_name))
-            } else {
-                DownloadingError::server_error(format!("{}", err))
-            });
-        }
-    };
-
-    if let Err(err) = parse_index_html_page(&mut project_index, page_content.as_str()) {
-        return Err(DownloadingError::error(format!(
-            "页面{}，解析出现错误: {err}",
-            project_index.project_url()
-        )));
-    };
-
-    let candidates = match find_candidates_links(installer, &project_index, &requirement) {
-        Ok(candidates) => candidates,
-        Err(err) => {
-            return Err(DownloadingError::error(format!(
-                "页面{}，解析候选下载项目出现错误: {err}",
-                project_index.project_url()
-            )));
-        }
+    let page_format = match resp.headers().get(CONTENT_TYPE) {
+        Some(value) => match value.to_str() {
+            Ok(content_type) if content_type.starts_with(SIMPLE_JSON_CONTENT_TYPE) => {
+                IndexFormat::Json
+            }
+            _ => IndexFormat::Html,
+        },
+        None => IndexFormat::Html,
     };
 
-    let link = if candidates.len() > 0 {
-        candidates[0]
-    } else {
-        return Err(DownloadingError::error(format!(
-            "未在{}发现满足需求({})的包: {}",
-            pypi.name(),
-            requirement,
-            project_index.project_url()
-        )));
+    let Ok(result) = tokio::time::timeout(installer.read_timeout(), resp.text()).await else {
+        return Err(timeout_error());
     };
 
-    let cached_filename = &installer.cached_packages_dir.join(link.file_name());
-
-    if is_cached_file_available(link, cached_filename)? {
-        log::info!("程序包{}本地已缓存，无需下载", cached_filename.display());
-        return Ok(());
+    match result {
+        Ok(text) => Ok((page_format, text)),
+        Err(err) => Err(if err.is_timeout() {
+            DownloadingError::timeout_error(format!("获取{}程序包索引数据", project_name))
+        } else {
+            DownloadingError::server_error(format!("{}", err))
+        }),
     }
-
-    download_link(installer, collector, pypi, link, cached_filename).await?;
-
-    Ok(())
 }
 
 async fn download_link(
@@ -147,57 +492,25 @@ async fn download_link(
     status_updater: &impl StatusUpdate,
     pypi: &PyPIMirror,
     link: &PackageLink,
+    expected_hash: Option<(&str, &str)>,
     cached_filename: &PathBuf,
 ) -> Result<(), DownloadingError> {
-    let buffer = download(
+    download_file(
         installer,
         status_updater,
         link.url(),
         &format!("从{}下载 {}", pypi.name(), link.file_name()),
+        cached_filename,
+        expected_hash,
     )
-    .await?;
-
-    if let Some((checksum_method, hexcode)) = link.checksum() {
-        match checksum(checksum_method, &buffer, hexcode) {
-            Ok(valid) => {
-                if !valid {
-                    return Err(DownloadingError::error(format!(
-                        "文件SHA256完整检验与原文件不一致: {}",
-                        link.file_name()
-                    )));
-                }
-            }
-            Err(err) => {
-                return Err(DownloadingError::error(format!(
-                    "SHA256完整性检验中出现错误检查失败: {err}",
-                )));
-            }
-        };
-    } else {
-        return Err(DownloadingError::error(format!(
-            "文件{}无checksum码",
-            cached_filename.display()
-        )));
-    }
-
-    let mut package_file = match File::create(cached_filename) {
-        Ok(file) => file,
-        Err(err) => {
-            return Err(DownloadingError::error(format!("下载文件写入错误：{err}")));
-        }
-    };
-
-    use std::io::Write;
-    if let Err(err) = package_file.write_all(&buffer) {
-        return Err(DownloadingError::error(format!("下载文件写入错误：{err}")));
-    };
-
-    Ok(())
+    .await
 }
 
 fn is_cached_file_available(
-    link: &PackageLink,
+    hash_policy: HashPolicy,
     cached_filename: &PathBuf,
+    expected_hash: Option<(&str, &str)>,
+    hash_pins: &[(String, String)],
 ) -> Result<bool, DownloadingError> {
     if !cached_filename.is_file() {
         // 文件不存在，无需进一步的检查
@@ -205,20 +518,26 @@ fn is_cached_file_available(
     }
 
     // 文件已经存在，检查是否完整
-    let _err: Option<anyhow::Error> = match std::fs::read(cached_filename) {
-        Ok(buf) => {
-            if let Some((checksum_method, hexcode)) = link.checksum() {
-                match checksum(checksum_method, &buf, hexcode) {
-                    Ok(true) => return Ok(true),
-                    Ok(false) => None,
-                    Err(err) => Some(err),
-                }
-            } else {
-                None
+    if let Some((checksum_method, hexcode)) = expected_hash {
+        match checksum_file(checksum_method, cached_filename, hexcode) {
+            Ok(true) => return Ok(true),
+            Ok(false) => {}
+            Err(err) => {
+                log::warn!("检查缓存文件{}出错: {}", cached_filename.display(), err);
             }
         }
-        Err(err) => Some(err.into()),
-    };
+    } else if !hash_pins.is_empty() {
+        if verify_direct_file(cached_filename, &None, hash_pins) {
+            return Ok(true);
+        }
+    } else if hash_policy == HashPolicy::Warn {
+        // 无从校验，重新下载也一样无法校验，保留已缓存的文件
+        log::warn!(
+            "缓存文件{}无checksum码可校验，直接使用",
+            cached_filename.display()
+        );
+        return Ok(true);
+    }
 
     if let Err(err) = std::fs::remove_file(cached_filename) {
         return Err(DownloadingError::error(format!(
@@ -257,26 +576,155 @@ fn parse_index_html_page(
             None => continue,
         };
 
-        let url = base_url.join(href)?;
+        let url = match base_url.join(href) {
+            Ok(url) => url,
+            Err(err) => {
+                project_index.add_parsed_link(Err(SkippedLink::new(href, err)));
+                continue;
+            }
+        };
         let requires_python = elem.attr("data-requires-python");
         let yanked_reason = elem.attr("data-yanked");
+        let core_metadata = elem
+            .attr("data-core-metadata")
+            .or(elem.attr("data-dist-info-metadata"));
 
-        let Some(link) = parse_link_from_url(
+        let parsed = parse_link_from_url(
             &project_index.canonical_name,
             url,
             requires_python,
             yanked_reason,
-        )?
-        else {
+        );
+
+        if let Some(link) = project_index.add_parsed_link(parsed) {
+            link.set_core_metadata(parse_core_metadata_attr(core_metadata));
+        }
+    }
+
+    log_skipped_links(project_index);
+
+    Ok(())
+
+    // Ok(project_index)
+}
+
+/// PEP 691 JSON索引页
+#[derive(Debug, Deserialize)]
+struct SimpleJsonPage {
+    meta: SimpleJsonMeta,
+    files: Vec<SimpleJsonFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimpleJsonMeta {
+    #[serde(rename = "api-version")]
+    api_version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimpleJsonFile {
+    url: String,
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+    #[serde(rename = "requires-python")]
+    requires_python: Option<String>,
+    #[serde(default)]
+    yanked: JsonFlag,
+    #[serde(rename = "core-metadata")]
+    core_metadata: Option<JsonFlag>,
+    #[serde(rename = "dist-info-metadata")]
+    dist_info_metadata: Option<JsonFlag>,
+}
+
+/// JSON索引中既可以是布尔值、也可以是说明（yanked）或摘要表（core-metadata）的字段
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonFlag {
+    Bool(bool),
+    Reason(String),
+    Hashes(HashMap<String, String>),
+}
+
+impl Default for JsonFlag {
+    fn default() -> Self {
+        JsonFlag::Bool(false)
+    }
+}
+
+impl JsonFlag {
+    fn is_set(&self) -> bool {
+        match self {
+            JsonFlag::Bool(flag) => *flag,
+            JsonFlag::Reason(_) | JsonFlag::Hashes(_) => true,
+        }
+    }
+}
+
+fn parse_index_json_page(
+    project_index: &mut ProjectIndex,
+    json_content: &str,
+) -> Result<(), Error> {
+    let page: SimpleJsonPage = serde_json::from_str(json_content)?;
+
+    // 只支持1.x版本的API，次版本号增加时保持兼容
+    if !page.meta.api_version.starts_with("1.") {
+        bail!("不支持的Simple API版本: {}", page.meta.api_version);
+    }
+
+    let base_url = Url::parse(project_index.project_url())?;
+
+    for file in page.files {
+        let url = match base_url.join(&file.url) {
+            Ok(url) => url,
+            Err(err) => {
+                project_index.add_parsed_link(Err(SkippedLink::new(&file.url, err)));
+                continue;
+            }
+        };
+
+        let yanked_reason = match &file.yanked {
+            JsonFlag::Reason(reason) => Some(reason.as_str()),
+            flag if flag.is_set() => Some(""),
+            _ => None,
+        };
+
+        let parsed = parse_link_from_url(
+            &project_index.canonical_name,
+            url,
+            file.requires_python.as_deref(),
+            yanked_reason,
+        );
+
+        let Some(link) = project_index.add_parsed_link(parsed) else {
             continue;
         };
 
-        project_index.links.push(link);
+        link.set_hashes(&file.hashes);
+
+        let core_metadata = file
+            .core_metadata
+            .as_ref()
+            .or(file.dist_info_metadata.as_ref());
+        link.set_core_metadata(match core_metadata {
+            Some(JsonFlag::Hashes(hashes)) => core_metadata_from_hashes(true, Some(hashes)),
+            Some(flag) => core_metadata_from_hashes(flag.is_set(), None),
+            None => None,
+        });
     }
 
+    log_skipped_links(project_index);
+
     Ok(())
+}
 
-    // Ok(project_index)
+fn log_skipped_links(project_index: &ProjectIndex) {
+    if !project_index.skipped_links.is_empty() {
+        log::warn!(
+            "索引页{}中有{}个链接无法解析，已跳过",
+            project_index.project_url,
+            project_index.skipped_links.len()
+        );
+    }
 }
 
 pub fn find_candidates_links<'a>(
@@ -285,9 +733,66 @@ pub fn find_candidates_links<'a>(
     index: &'a ProjectIndex,
     requirement: &Requirement,
 ) -> Result<Vec<&'a PackageLink>, Error> {
-    use pep440_rs::{Version, VersionSpecifiers};
     use pep508_rs::VersionOrUrl;
 
+    let candidates = compatible_links(installer, index)?;
+
+    let specifiers = match &requirement.version_or_url {
+        Some(VersionOrUrl::VersionSpecifier(specifiers)) => Some(specifiers),
+        // 直接链接的需求由download_direct_url下载，不读取索引页
+        Some(VersionOrUrl::Url(url)) => bail!("直接给链接的需求不从镜像下载: {}", url),
+        None => None,
+    };
+
+    // 与pip相同，除非需求中明确给出预发布版本或者配置允许，不考虑预发布版本
+    let allow_prerelease =
+        installer.allow_prereleases() || specifiers.is_some_and(mentions_prerelease);
+
+    let matched = candidates
+        .into_iter()
+        .filter(|(pkg_version, _, _)| match specifiers {
+            Some(specifiers) => specifiers.contains(pkg_version),
+            None => true,
+        })
+        .filter(|(pkg_version, _, _)| allow_prerelease || !pkg_version.any_prerelease())
+        .map(|x| x.2)
+        .collect::<Vec<&PackageLink>>();
+
+    // PEP 592：不选择已撤回的文件，除非需求以==固定了版本且该版本只有已撤回的文件
+    let (yanked, available): (Vec<&PackageLink>, Vec<&PackageLink>) = matched
+        .into_iter()
+        .partition(|link| link.yanked_reason().is_some());
+
+    if available.is_empty() && specifiers.is_some_and(pins_exact_version) {
+        Ok(yanked)
+    } else {
+        Ok(available)
+    }
+}
+
+/// 版本限制是否以`==`或`===`固定了一个确切的版本
+pub fn pins_exact_version(specifiers: &VersionSpecifiers) -> bool {
+    specifiers
+        .iter()
+        .any(|specifier| matches!(specifier.operator(), Operator::Equal | Operator::ExactEqual))
+}
+
+/// 版本限制中是否出现预发布版本，如`>=2.0.0rc1`
+pub fn mentions_prerelease(specifiers: &VersionSpecifiers) -> bool {
+    specifiers
+        .iter()
+        .any(|specifier| specifier.version().any_prerelease())
+}
+
+/// 下载项及其版本和最适合的wheel tag的排名，源码包没有排名
+pub type CompatibleLink<'a> = (Version, Option<u32>, &'a PackageLink);
+
+/// 适合当前环境的全部下载项，按版本从高到低、同一版本内按tag从优到劣排列，
+/// 源码包排在同一版本的wheel之后
+pub fn compatible_links<'a>(
+    installer: &Installer,
+    index: &'a ProjectIndex,
+) -> Result<Vec<CompatibleLink<'a>>, Error> {
     use std::str::FromStr;
 
     let python_version = match Version::from_str(&installer.python_version_full) {
@@ -295,13 +800,7 @@ pub fn find_candidates_links<'a>(
         Err(err) => bail!("parsing version: {}", err),
     };
 
-    let pkg_specifiers = match &requirement.version_or_url {
-        Some(VersionOrUrl::VersionSpecifier(pkg_specifiers)) => pkg_specifiers,
-        Some(VersionOrUrl::Url(url)) => bail!("不支持直接给链接下载: {}", url),
-        None => bail!("requirement: None"),
-    };
-
-    let mut candidates: Vec<(Version, Option<u32>, &PackageLink)> = Vec::new();
+    let mut candidates: Vec<CompatibleLink> = Vec::new();
     for link in &index.links {
         // 检查连接是否满足当前环境的Python版本需求
         if let Some(requires_python) = link.requires_python() {
@@ -319,14 +818,8 @@ pub fn find_candidates_links<'a>(
             }
         };
 
-        // 检查包的版本是否满足需求
-        let Ok(pkg_version) = Version::from_str(link.package_version()) else {
-            bail!("parsing package version: '{}'", link.package_version());
-        };
-
-        if !pkg_specifiers.contains(&pkg_version) {
-            continue;
-        }
+        // 版本在解析链接时已校验
+        let pkg_version = link.version().clone();
 
         // 匹配环境最合适的tag
         if link.is_wheel() {
@@ -341,7 +834,8 @@ pub fn find_candidates_links<'a>(
     }
 
     candidates.sort_by(|a, b| {
-        // 从大到小排列version，从小到大排列tag的rank
+        // 从大到小排列version，从小到大排列tag的rank，
+        // tag相同时按PEP 427从大到小排列构建号，无构建号的排在最后
         use std::cmp::Ordering::{Equal, Greater, Less};
         match a.0.cmp(&b.0) {
             Less => Greater,
@@ -349,13 +843,15 @@ pub fn find_candidates_links<'a>(
                 (None, None) => Equal,
                 (None, Some(_)) => Greater,
                 (Some(_), None) => Less,
-                (Some(t_a), Some(t_b)) => t_a.cmp(&t_b),
+                (Some(t_a), Some(t_b)) => t_a
+                    .cmp(&t_b)
+                    .then_with(|| b.2.wheel_build().cmp(&a.2.wheel_build())),
             },
             Greater => Less,
         }
     });
 
-    Ok(candidates.iter().map(|x| x.2).collect())
+    Ok(candidates)
 }
 
 pub fn get_best_tag_rank(installer: &Installer, link: &PackageLink) -> Option<u32> {
@@ -372,3 +868,309 @@ pub fn get_best_tag_rank(installer: &Installer, link: &PackageLink) -> Option<u3
 
     ranks.iter().min().copied()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::lockfile::LockFile;
+    use super::super::testutil::test_installer;
+    use super::*;
+
+    type LinkInfo<'a> = (
+        &'a str,                            // url
+        Option<&'a str>,                    // sha256
+        Option<&'a str>,                    // requires_python
+        Option<&'a str>,                    // yanked_reason
+        Option<Option<(&'a str, &'a str)>>, // core_metadata
+    );
+
+    fn demo_index() -> ProjectIndex {
+        let pypi = PyPIMirror::new("demo", "https://pypi.example.org/simple");
+        ProjectIndex::new(&pypi, "Demo_Pkg")
+    }
+
+    /// 比较两种索引页的解析结果时关心的链接信息
+    fn describe(link: &PackageLink) -> LinkInfo<'_> {
+        (
+            link.url(),
+            link.hash("sha256"),
+            link.requires_python(),
+            link.yanked_reason(),
+            link.core_metadata().map(|metadata| metadata.checksum()),
+        )
+    }
+
+    #[test]
+    fn json_and_html_pages_yield_same_links() {
+        let json_page = include_str!("../../testdata/demo-pkg.json");
+        let mut json_index = demo_index();
+        parse_index_json_page(&mut json_index, json_page).unwrap();
+
+        let html_page = include_str!("../../testdata/demo-pkg.html");
+        let mut html_index = demo_index();
+        parse_index_html_page(&mut html_index, html_page).unwrap();
+
+        let json_links = json_index.links().iter().map(describe).collect::<Vec<_>>();
+        let html_links = html_index.links().iter().map(describe).collect::<Vec<_>>();
+        assert_eq!(json_links, html_links);
+
+        assert!(json_index.skipped_links().is_empty());
+        assert!(html_index.skipped_links().is_empty());
+
+        let (old_whl, old_metadata) = ("e5".repeat(32), "f6".repeat(32));
+        let sdist = "c3".repeat(32);
+        let (whl, metadata) = ("a1".repeat(32), "b2".repeat(32));
+        let yanked_whl = "d4".repeat(32);
+        let expected: Vec<LinkInfo> = vec![
+            (
+                "https://files.example.org/packages/demo_pkg-0.9.0-py3-none-any.whl",
+                Some(old_whl.as_str()),
+                Some("<4,>=3.7"),
+                Some(""),
+                Some(Some(("sha256", old_metadata.as_str()))),
+            ),
+            (
+                "https://pypi.example.org/packages/demo-pkg-1.0.0.tar.gz",
+                Some(sdist.as_str()),
+                None,
+                None,
+                None,
+            ),
+            (
+                "https://files.example.org/packages/demo_pkg-1.0.0-py3-none-any.whl",
+                Some(whl.as_str()),
+                Some(">=3.8"),
+                None,
+                Some(Some(("sha256", metadata.as_str()))),
+            ),
+            (
+                "https://files.example.org/packages/demo_pkg-1.1.0-py3-none-any.whl",
+                Some(yanked_whl.as_str()),
+                Some(">=3.8"),
+                Some("broken build"),
+                Some(None),
+            ),
+        ];
+        assert_eq!(json_links, expected);
+    }
+
+    #[test]
+    fn json_page_with_unsupported_api_version_is_rejected() {
+        let mut index = demo_index();
+        let page = r#"{"meta": {"api-version": "2.0"}, "files": []}"#;
+
+        assert!(parse_index_json_page(&mut index, page).is_err());
+    }
+
+    #[test]
+    fn trust_lock_policy_uses_locked_sha256() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut installer = test_installer(temp_dir.path());
+        installer.set_hash_policy(HashPolicy::TrustLock);
+        installer.platform_tag = Some("linux_x86_64".to_string());
+
+        let page = r#"<a href="https://pypi.example.org/packages/demo-pkg-1.0.0.tar.gz">x</a>"#;
+        let mut index = demo_index();
+        parse_index_html_page(&mut index, page).unwrap();
+        let link = &index.links()[0];
+        assert_eq!(link.strongest_checksum(false), None);
+
+        let digest = "a1".repeat(32);
+        let python_version = installer.python_version.clone();
+        let lock = |filename: &str| {
+            let content = format!(
+                "version = 1\n\
+                python_version = \"{}\"\n\
+                platform_tag = \"linux_x86_64\"\n\
+                [[package]]\n\
+                name = \"Demo_Pkg\"\n\
+                version = \"1.0.0\"\n\
+                filename = \"{}\"\n\
+                sha256 = \"{}\"\n\
+                urls = {{}}\n",
+                python_version, filename, digest
+            );
+            LockFile::parse(&content).unwrap()
+        };
+
+        // 锁定的是同一程序包的另一个文件时不采信
+        installer.set_lock_file(lock("demo_pkg-1.0.0-py3-none-any.whl"));
+        assert!(expected_hash(&installer, "demo-pkg", link, &[]).is_err());
+
+        installer.set_lock_file(lock("demo-pkg-1.0.0.tar.gz"));
+        assert_eq!(
+            expected_hash(&installer, "demo-pkg", link, &[]).unwrap(),
+            Some(("sha256", digest.as_str()))
+        );
+
+        // 没有锁定时按需求文件的--hash
+        let pins = [("sha256".to_string(), "b2".repeat(32))];
+        assert_eq!(
+            expected_hash(&installer, "other-pkg", link, &pins).unwrap(),
+            Some(("sha256", pins[0].1.as_str()))
+        );
+        assert!(expected_hash(&installer, "other-pkg", link, &[]).is_err());
+    }
+
+    #[test]
+    fn malformed_links_are_skipped_with_reasons() {
+        let page = include_str!("../../testdata/malformed-links.html");
+        let mut index = demo_index();
+        parse_index_html_page(&mut index, page).unwrap();
+
+        // 摘要为空或不是十六进制数的忽略，链接本身保留
+        let digest = "a1".repeat(32);
+        let links = index
+            .links()
+            .iter()
+            .map(|link| (link.file_name(), link.hash("sha256"), link.wheel_build()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            [
+                (
+                    "demo_pkg-1.0.0-py3-none-any.whl",
+                    Some(digest.as_str()),
+                    None
+                ),
+                ("demo-pkg-1.0.0.tar.gz", None, None),
+                ("demo-pkg-1.0.1.zip", None, None),
+                ("demo_pkg-1.0.2-1-py3-none-any.whl", None, Some((1, ""))),
+                ("demo-pkg-1.0.3.tar.gz", None, None),
+            ]
+        );
+
+        // 跳过的链接及其原因中应有的内容
+        let expected = [
+            ("http://[::1/demo-pkg-1.0.5.tar.gz", "invalid IPv6 address"),
+            (
+                "https://pypi.example.org/simple/demo-pkg/",
+                "链接中没有文件名",
+            ),
+            ("demo-pkg", "没有扩展名"),
+            ("demo_pkg-1.0-py3.whl", "error in parsing"),
+            ("demo_pkg-1.0.6-x1-py3-none-any.whl", "error in parsing"),
+            (
+                "other_pkg-1.0.0-py3-none-any.whl",
+                "文件名与程序包名称demo-pkg不符",
+            ),
+            ("other-pkg-1.0.0.tar.gz", "文件名与程序包名称demo-pkg不符"),
+            ("demo-pkg-latest.tar.gz", "parsing version"),
+            ("demo_pkg-0.1-py2.7.egg", "不支持的旧格式"),
+            ("demo-pkg-0.1.win32.exe", "不支持的旧格式"),
+            ("demo-pkg-1.0.0.tar.lz", "不支持的文件类型"),
+            ("README.txt", "不支持的文件类型"),
+        ];
+        let skipped = index
+            .skipped_links()
+            .iter()
+            .map(|skipped| skipped.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(skipped.len(), expected.len(), "{:?}", skipped);
+
+        for (skipped, (file_name, reason)) in index.skipped_links().iter().zip(expected) {
+            assert_eq!(skipped.file_name(), file_name);
+            assert!(skipped.reason().contains(reason), "{}", skipped);
+        }
+    }
+
+    /// 1.1.0的wheel和1.2.0已撤回，1.1.0另有未撤回的源码包，2.0.0rc1为预发布版本
+    const CANDIDATES_PAGE: &str = r#"{
+        "meta": {"api-version": "1.0"},
+        "name": "demo-pkg",
+        "files": [
+            {"filename": "demo_pkg-1.0.0-py3-none-any.whl",
+             "url": "https://files.example.org/demo_pkg-1.0.0-py3-none-any.whl"},
+            {"filename": "demo_pkg-1.1.0-py3-none-any.whl",
+             "url": "https://files.example.org/demo_pkg-1.1.0-py3-none-any.whl",
+             "yanked": "broken build"},
+            {"filename": "demo-pkg-1.1.0.tar.gz",
+             "url": "https://files.example.org/demo-pkg-1.1.0.tar.gz"},
+            {"filename": "demo_pkg-1.2.0-py3-none-any.whl",
+             "url": "https://files.example.org/demo_pkg-1.2.0-py3-none-any.whl",
+             "yanked": true},
+            {"filename": "demo_pkg-2.0.0rc1-py3-none-any.whl",
+             "url": "https://files.example.org/demo_pkg-2.0.0rc1-py3-none-any.whl"}
+        ]
+    }"#;
+
+    /// 按需求从CANDIDATES_PAGE中选出的文件名，按优先顺序排列
+    fn candidate_files(requirement: &str) -> Vec<String> {
+        use std::str::FromStr;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut installer = test_installer(temp_dir.path());
+        installer.support_tags_map = HashMap::from([("py3-none-any".to_string(), 0)]);
+
+        let mut index = demo_index();
+        parse_index_json_page(&mut index, CANDIDATES_PAGE).unwrap();
+
+        let requirement = Requirement::from_str(requirement).unwrap();
+        find_candidates_links(&installer, &index, &requirement)
+            .unwrap()
+            .iter()
+            .map(|link| link.file_name().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn yanked_files_are_skipped_when_alternatives_exist() {
+        assert_eq!(
+            candidate_files("demo-pkg"),
+            ["demo-pkg-1.1.0.tar.gz", "demo_pkg-1.0.0-py3-none-any.whl"]
+        );
+        assert_eq!(
+            candidate_files("demo-pkg==1.1.0"),
+            ["demo-pkg-1.1.0.tar.gz"]
+        );
+    }
+
+    #[test]
+    fn yanked_files_are_used_only_for_exact_pins() {
+        assert_eq!(
+            candidate_files("demo-pkg==1.2.0"),
+            ["demo_pkg-1.2.0-py3-none-any.whl"]
+        );
+        assert_eq!(
+            candidate_files("demo-pkg===1.2.0"),
+            ["demo_pkg-1.2.0-py3-none-any.whl"]
+        );
+        assert!(candidate_files("demo-pkg>=1.2.0,<2").is_empty());
+        assert!(candidate_files("demo-pkg~=1.2.0").is_empty());
+    }
+
+    #[test]
+    fn prereleases_are_excluded_by_default() {
+        assert!(!candidate_files("demo-pkg")
+            .iter()
+            .any(|file_name| file_name.contains("rc1")));
+        assert!(candidate_files("demo-pkg>=1.5").is_empty());
+    }
+
+    #[test]
+    fn prereleases_are_included_when_named() {
+        assert_eq!(
+            candidate_files("demo-pkg>=2.0.0rc1"),
+            ["demo_pkg-2.0.0rc1-py3-none-any.whl"]
+        );
+        assert_eq!(
+            candidate_files("demo-pkg==2.0.0rc1"),
+            ["demo_pkg-2.0.0rc1-py3-none-any.whl"]
+        );
+    }
+
+    #[test]
+    fn unverifiable_cached_file_is_kept_under_warn_policy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cached_filename = temp_dir.path().join("demo_pkg-1.0.0-py3-none-any.whl");
+
+        std::fs::write(&cached_filename, b"wheel").unwrap();
+        assert!(is_cached_file_available(HashPolicy::Warn, &cached_filename, None, &[]).unwrap());
+        assert!(cached_filename.is_file());
+
+        let pins = [("sha256".to_string(), "00".repeat(32))];
+        assert!(
+            !is_cached_file_available(HashPolicy::Warn, &cached_filename, None, &pins).unwrap()
+        );
+        assert!(!cached_filename.exists());
+    }
+}
@@ -0,0 +1,572 @@
+use anyhow::{anyhow, bail, Result};
+use pep440_rs::VersionSpecifiers;
+use pep508_rs::{Requirement, VersionOrUrl};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::utils::canonicalize_name;
+
+/// `-r`嵌套的最大层数，避免循环引用
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// 需求文件中不影响选择下载哪个文件的pip选项，读到时忽略
+static IGNORED_OPTIONS: [&str; 3] = ["--require-hashes", "--trusted-host", "--use-feature"];
+
+/// pip格式的需求文件。
+///
+/// 支持`#`注释、行尾`\`续行、`-r`/`-c`引用其它文件、`-i`/`--extra-index-url`、
+/// `--pre`、`--prefer-binary`、`--only-binary :all:`、每行的`--hash`、`-e`
+/// 以及`name @ URL`形式的直接链接
+#[derive(Debug, Clone, Default)]
+pub struct RequirementsFile {
+    entries: Vec<RequirementEntry>,
+    constraints: Vec<Requirement>,
+    index_url: Option<String>,
+    extra_index_urls: Vec<String>,
+    allow_prereleases: bool,
+    prefer_binary: bool,
+}
+
+/// 需求文件中的一条需求及其`--hash`
+#[derive(Debug, Clone)]
+struct RequirementEntry {
+    requirement: Requirement,
+    hashes: Vec<(String, String)>, // (hash_method, hash_code)
+}
+
+impl RequirementsFile {
+    /// 解析需求文本，base_dir为`-r`/`-c`相对路径的起点，内置的需求文本没有所在目录
+    pub fn parse(text: &str, base_dir: Option<&Path>) -> Result<Self> {
+        let mut file = RequirementsFile::default();
+        file.parse_into(text, base_dir, false, 0)?;
+
+        Ok(file)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut file = RequirementsFile::default();
+        file.read_into(path, false, 0)?;
+
+        Ok(file)
+    }
+
+    fn read_into(&mut self, path: &Path, constraint: bool, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            bail!("需求文件{}嵌套层数过多", path.display());
+        }
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) => bail!("读取需求文件{}错误: {}", path.display(), err),
+        };
+
+        if let Err(err) = self.parse_into(&text, path.parent(), constraint, depth) {
+            bail!("需求文件{}: {}", path.display(), err);
+        }
+
+        Ok(())
+    }
+
+    fn parse_into(
+        &mut self,
+        text: &str,
+        base_dir: Option<&Path>,
+        constraint: bool,
+        depth: usize,
+    ) -> Result<()> {
+        let mut errors = Vec::new();
+        for (line_no, line) in logical_lines(text) {
+            if let Err(err) = self.parse_line(&line, base_dir, constraint, depth) {
+                errors.push(format!("第{}行: {}", line_no, err));
+            }
+        }
+
+        if !errors.is_empty() {
+            bail!("解析需求文件错误:\n{}", errors.join("\n"))
+        }
+
+        Ok(())
+    }
+
+    fn parse_line(
+        &mut self,
+        line: &str,
+        base_dir: Option<&Path>,
+        constraint: bool,
+        depth: usize,
+    ) -> Result<()> {
+        if line.starts_with('-') {
+            let (option, value) = split_option(line);
+            return match option {
+                "-r" | "--requirement" | "-c" | "--constraint" => {
+                    let Some(value) = value else {
+                        bail!("选项{}缺少文件名", option)
+                    };
+                    let path = include_path(value, base_dir)?;
+                    let constraint = constraint || option == "-c" || option == "--constraint";
+                    self.read_into(&path, constraint, depth + 1)
+                }
+                "-i" | "--index-url" => {
+                    let Some(value) = value else {
+                        bail!("选项{}缺少镜像地址", option)
+                    };
+                    self.index_url = Some(value.to_string());
+                    Ok(())
+                }
+                "--extra-index-url" => {
+                    let Some(value) = value else {
+                        bail!("选项{}缺少镜像地址", option)
+                    };
+                    self.extra_index_urls.push(value.to_string());
+                    Ok(())
+                }
+                "-e" | "--editable" => {
+                    let Some(value) = value else {
+                        bail!("选项{}缺少安装路径", option)
+                    };
+                    log::warn!("可编辑安装的需求{}不适用于离线安装，忽略", value);
+                    Ok(())
+                }
+                "--no-index" => {
+                    log::warn!("忽略需求文件选项{}", option);
+                    Ok(())
+                }
+                "--pre" => {
+                    self.allow_prereleases = true;
+                    Ok(())
+                }
+                "--prefer-binary" => {
+                    self.prefer_binary = true;
+                    Ok(())
+                }
+                // 不能只下载wheel，按优先选择wheel处理
+                "--only-binary" => match value {
+                    Some(":all:") => {
+                        self.prefer_binary = true;
+                        Ok(())
+                    }
+                    Some(":none:") => Ok(()),
+                    _ => bail!("不支持的需求文件选项: {}", line),
+                },
+                "--no-binary" if value == Some(":none:") => Ok(()),
+                "--no-binary" | "--find-links" | "-f" => {
+                    bail!("不支持的需求文件选项: {}", line)
+                }
+                _ if IGNORED_OPTIONS.contains(&option) => {
+                    log::warn!("忽略需求文件选项{}", line);
+                    Ok(())
+                }
+                _ => bail!("不支持的需求文件选项: {}", option),
+            };
+        }
+
+        let (requirement_text, options) = split_requirement_options(line);
+
+        let requirement = match Requirement::from_str(requirement_text) {
+            Ok(requirement) => requirement,
+            Err(err) => bail!("{}", err),
+        };
+
+        let mut hashes = Vec::new();
+        let mut tokens = options.split_whitespace();
+        while let Some(token) = tokens.next() {
+            let value = match token.split_once('=') {
+                Some(("--hash", value)) => value,
+                None if token == "--hash" => tokens.next().unwrap_or_default(),
+                _ => bail!("不支持的需求选项: {}", token),
+            };
+
+            let Some((method, hexcode)) = value.split_once(':') else {
+                bail!("--hash格式错误，应为--hash=sha256:...: {}", value);
+            };
+            hashes.push((method.to_lowercase(), hexcode.to_lowercase()));
+        }
+
+        if constraint {
+            if !hashes.is_empty() {
+                log::warn!("约束文件中{}的--hash被忽略", requirement.name);
+            }
+            self.constraints.push(requirement);
+        } else {
+            self.entries.push(RequirementEntry {
+                requirement,
+                hashes,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn index_url(&self) -> Option<&str> {
+        self.index_url.as_deref()
+    }
+
+    pub fn extra_index_urls(&self) -> &[String] {
+        &self.extra_index_urls
+    }
+
+    /// 是否有`--pre`，需求未明确给出预发布版本时也考虑预发布版本
+    pub fn allow_prereleases(&self) -> bool {
+        self.allow_prereleases
+    }
+
+    /// 是否有`--prefer-binary`或`--only-binary :all:`
+    pub fn prefer_binary(&self) -> bool {
+        self.prefer_binary
+    }
+
+    /// 全部需求，约束文件中同名程序包的版本限制合并到需求的版本限制中
+    pub fn requirements(&self) -> Result<Vec<Requirement>> {
+        let mut requirements = Vec::new();
+        for entry in &self.entries {
+            let mut requirement = entry.requirement.clone();
+            for constraint in &self.constraints {
+                if canonicalize_name(&constraint.name) == canonicalize_name(&requirement.name) {
+                    apply_constraint(&mut requirement, constraint)?;
+                }
+            }
+            requirements.push(requirement);
+        }
+
+        Ok(requirements)
+    }
+
+    /// 以程序包规范名称为键的`--hash`，供下载时校验
+    pub fn hash_pins(&self) -> HashMap<String, Vec<(String, String)>> {
+        let mut pins: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for entry in &self.entries {
+            if !entry.hashes.is_empty() {
+                pins.entry(canonicalize_name(&entry.requirement.name))
+                    .or_default()
+                    .extend(entry.hashes.iter().cloned());
+            }
+        }
+
+        pins
+    }
+}
+
+/// 直接链接的需求对应的文件名，取链接路径的最后一段
+pub fn direct_url_file_name(url: &str) -> Result<String> {
+    let url = match url::Url::parse(url) {
+        Ok(url) => url,
+        Err(err) => bail!("链接{}格式错误: {}", url, err),
+    };
+
+    let file_name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("链接{}中没有文件名", url))?;
+
+    Ok(percent_decode(file_name))
+}
+
+/// 还原链接路径中`%2B`这样的转义字符
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[idx + 1..idx + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[idx]);
+        idx += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// 直接链接的`#sha256=...`片段中的摘要
+pub fn direct_url_hash(url: &str) -> Option<(String, String)> {
+    let (_, fragment) = url.split_once('#')?;
+    fragment.split('&').find_map(|part| {
+        let (method, hexcode) = part.split_once('=')?;
+        match method {
+            "sha256" | "sha384" | "sha512" | "sha224" => {
+                Some((method.to_string(), hexcode.to_lowercase()))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// 按行尾`\`拼接续行并去掉注释，返回(起始行号, 内容)，跳过空行
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut start_no = 0;
+
+    for (idx, line) in text.lines().enumerate() {
+        if current.is_empty() {
+            start_no = idx + 1;
+        }
+
+        match line.strip_suffix('\\') {
+            Some(line) => {
+                current.push_str(line);
+                current.push(' ');
+            }
+            None => {
+                current.push_str(line);
+                push_logical_line(&mut lines, start_no, &current);
+                current.clear();
+            }
+        }
+    }
+    push_logical_line(&mut lines, start_no, &current);
+
+    lines
+}
+
+fn push_logical_line(lines: &mut Vec<(usize, String)>, line_no: usize, line: &str) {
+    let line = strip_comment(line).trim();
+    if !line.is_empty() {
+        lines.push((line_no, line.to_string()));
+    }
+}
+
+/// 行首或空白之后的`#`开始注释，链接中的`#sha256=`不是注释
+fn strip_comment(line: &str) -> &str {
+    let mut prev_is_space = true;
+    for (pos, c) in line.char_indices() {
+        if c == '#' && prev_is_space {
+            return &line[..pos];
+        }
+        prev_is_space = c.is_whitespace();
+    }
+
+    line
+}
+
+/// 需求之后可以跟`--hash`等针对该需求的选项，以引号之外、空白之后的第一个`--`分开，
+/// 环境标记的字符串中可以有` -`
+fn split_requirement_options(line: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut prev_is_space = false;
+    for (pos, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if prev_is_space && line[pos..].starts_with("--") => {
+                return (line[..pos].trim(), line[pos..].trim());
+            }
+            None => {}
+        }
+        prev_is_space = c.is_whitespace();
+    }
+
+    (line, "")
+}
+
+/// 拆分`--option=value`或`--option value`
+fn split_option(line: &str) -> (&str, Option<&str>) {
+    let (option, value) = match line.find(|c: char| c == '=' || c.is_whitespace()) {
+        Some(pos) => (&line[..pos], line[pos + 1..].trim()),
+        None => (line, ""),
+    };
+
+    if value.is_empty() {
+        (option, None)
+    } else {
+        (option, Some(value))
+    }
+}
+
+fn include_path(value: &str, base_dir: Option<&Path>) -> Result<PathBuf> {
+    let path = PathBuf::from(value);
+    if path.is_absolute() {
+        return Ok(path);
+    }
+
+    match base_dir {
+        Some(base_dir) => Ok(base_dir.join(path)),
+        None => bail!("内置需求文件不能以相对路径引用其它文件: {}", value),
+    }
+}
+
+fn apply_constraint(requirement: &mut Requirement, constraint: &Requirement) -> Result<()> {
+    let Some(VersionOrUrl::VersionSpecifier(constraint_specifiers)) = &constraint.version_or_url
+    else {
+        return Ok(());
+    };
+
+    let specifiers = match &requirement.version_or_url {
+        Some(VersionOrUrl::Url(_)) => return Ok(()), // 直接链接不受版本约束
+        Some(VersionOrUrl::VersionSpecifier(specifiers)) => {
+            format!("{},{}", specifiers, constraint_specifiers)
+        }
+        None => constraint_specifiers.to_string(),
+    };
+
+    match VersionSpecifiers::from_str(&specifiers) {
+        Ok(specifiers) => {
+            requirement.version_or_url = Some(VersionOrUrl::VersionSpecifier(specifiers));
+            Ok(())
+        }
+        Err(err) => bail!("合并{}的约束{}错误: {}", requirement.name, specifiers, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requirement_lines(file: &RequirementsFile) -> Vec<String> {
+        file.requirements()
+            .unwrap()
+            .iter()
+            .map(|requirement| requirement.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn comments_continuations_and_hashes_are_parsed() {
+        let (aa, bb) = ("AA".repeat(32), "bb".repeat(32));
+        let text = format!(
+            "# 注释\n\
+            \n\
+            demo-pkg==1.0 \\\n    \
+                --hash=sha256:{aa} \\\n    \
+                --hash sha256:{bb}  # 行尾注释\n\
+            other_pkg[excel]>=2.0\t--hash=sha256:{bb}\n\
+            third-pkg @ https://example.org/third_pkg-1.0.tar.gz#sha256={bb}\n"
+        );
+        let file = RequirementsFile::parse(&text, None).unwrap();
+
+        assert_eq!(
+            requirement_lines(&file),
+            [
+                "demo-pkg ==1.0".to_string(),
+                "other_pkg[excel] >=2.0".to_string(),
+                format!("third-pkg @ https://example.org/third_pkg-1.0.tar.gz#sha256={bb}"),
+            ]
+        );
+
+        let pins = file.hash_pins();
+        let sha256 = |hexcode: &str| ("sha256".to_string(), hexcode.to_string());
+        assert_eq!(pins["demo-pkg"], [sha256(&aa.to_lowercase()), sha256(&bb)]);
+        assert_eq!(pins["other-pkg"], [sha256(&bb)]);
+        assert!(!pins.contains_key("third-pkg"));
+    }
+
+    #[test]
+    fn requirement_options_are_split_outside_quoted_markers() {
+        let text = "demo-pkg==1.0 ; platform_release != \"5.4 -generic\" --hash=sha256:aa\n";
+        let file = RequirementsFile::parse(text, None).unwrap();
+
+        assert_eq!(
+            requirement_lines(&file),
+            ["demo-pkg ==1.0 ; platform_release != '5.4 -generic'"]
+        );
+        assert_eq!(
+            file.hash_pins()["demo-pkg"],
+            [("sha256".to_string(), "aa".to_string())]
+        );
+
+        let text = "demo-pkg==1.0 --global-option=build_ext\n";
+        assert!(RequirementsFile::parse(text, None).is_err());
+    }
+
+    #[test]
+    fn included_files_and_constraints_are_merged() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+
+        std::fs::write(
+            dir.join("requirements.txt"),
+            "-r sub/more.txt\n-c constraints.txt\ndemo-pkg>=1.0\n",
+        )
+        .unwrap();
+        // 嵌套引用的相对路径从所在文件的目录算起
+        std::fs::write(dir.join("sub/more.txt"), "other-pkg\n-r ../extra.txt\n").unwrap();
+        std::fs::write(
+            dir.join("extra.txt"),
+            "third-pkg @ https://example.org/third.zip\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("constraints.txt"),
+            "demo-pkg<2 --hash=sha256:aa\nother-pkg==3.0\nthird-pkg<1\nunused-pkg==1.0\n",
+        )
+        .unwrap();
+
+        let file = RequirementsFile::read(&dir.join("requirements.txt")).unwrap();
+        assert_eq!(
+            requirement_lines(&file),
+            [
+                "other-pkg ==3.0",
+                "third-pkg @ https://example.org/third.zip",
+                "demo-pkg >=1.0, <2",
+            ]
+        );
+        // 约束文件中的--hash不作为下载时的校验
+        assert!(file.hash_pins().is_empty());
+    }
+
+    #[test]
+    fn include_errors_are_reported() {
+        // 内置的需求文本没有所在目录
+        assert!(RequirementsFile::parse("-r other.txt\n", None).is_err());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("loop.txt");
+        std::fs::write(&path, "-r loop.txt\n").unwrap();
+        let err = RequirementsFile::read(&path).unwrap_err();
+        assert!(err.to_string().contains("嵌套层数过多"), "{err}");
+    }
+
+    #[test]
+    fn selection_options_are_applied_or_rejected() {
+        let file = RequirementsFile::parse("demo-pkg\n", None).unwrap();
+        assert!(!file.allow_prereleases());
+        assert!(!file.prefer_binary());
+
+        let text = "--pre\n--only-binary=:all:\n--trusted-host example.org\ndemo-pkg\n";
+        let file = RequirementsFile::parse(text, None).unwrap();
+        assert!(file.allow_prereleases());
+        assert!(file.prefer_binary());
+
+        let file = RequirementsFile::parse("--prefer-binary\ndemo-pkg\n", None).unwrap();
+        assert!(file.prefer_binary());
+
+        for option in [
+            "--no-binary :all:",
+            "--only-binary demo-pkg",
+            "-f https://example.org/wheels/",
+            "--find-links=./wheels",
+        ] {
+            let text = format!("{option}\ndemo-pkg\n");
+            assert!(RequirementsFile::parse(&text, None).is_err(), "{option}");
+        }
+    }
+
+    #[test]
+    fn percent_encoded_file_names_are_decoded() {
+        assert_eq!(percent_decode("demo_pkg-1.0%2Blocal"), "demo_pkg-1.0+local");
+        assert_eq!(percent_decode("%E4%B8%AD"), "中");
+        assert_eq!(percent_decode("100%zz"), "100%zz");
+        assert_eq!(percent_decode("trailing%2"), "trailing%2");
+
+        let url = "https://example.org/p/demo_pkg-1.0%2Blocal-py3-none-any.whl#sha256=aa";
+        assert_eq!(
+            direct_url_file_name(url).unwrap(),
+            "demo_pkg-1.0+local-py3-none-any.whl"
+        );
+        assert_eq!(
+            direct_url_hash(url),
+            Some(("sha256".to_string(), "aa".to_string()))
+        );
+        assert!(direct_url_file_name("https://example.org/").is_err());
+    }
+}
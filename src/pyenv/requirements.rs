@@ -1,13 +1,20 @@
 use anyhow::{bail, Result};
-use pep508_rs::Requirement;
-use std::{fs::File, path::PathBuf};
+use pep508_rs::{Requirement, VersionOrUrl};
+use std::path::{Path, PathBuf};
 
-use crate::errors::DownloadingError;
+use crate::errors::{DownloadingError, InstallingError};
 
+use super::super::resources::RESOURCES;
 use super::super::status::StatusUpdate;
+use super::archive::is_tar_extension;
 use super::installer::Installer;
+use super::reqfile::{direct_url_file_name, RequirementsFile};
+use super::utils::{canonicalize_name, split_filename_extension};
 
-pub async fn prepare_requirements(installer: &Installer) -> Result<Vec<Requirement>> {
+pub async fn prepare_requirements(
+    installer: &mut Installer,
+    status_updater: &impl StatusUpdate,
+) -> Result<Vec<Requirement>> {
     let cached_packages_dir = &installer.cached_packages_dir;
     if let Err(_err) = std::fs::create_dir_all(cached_packages_dir) {
         bail!(
@@ -17,17 +24,248 @@ pub async fn prepare_requirements(installer: &Installer) -> Result<Vec<Requireme
         )
     }
 
-    let requirements_path = &get_requirements_path(installer).await?;
-
-    log::info!("程序包需求文件: {}", requirements_path.display());
+    let requirements_path = &get_requirements_path(installer);
     log::info!("程序包下载临时目录: {}", cached_packages_dir.display());
 
-    let mut requirements = extract_requirements(requirements_path).await?;
+    if let Some(bundle) = installer.bundle() {
+        status_updater.message(&format!(
+            "从离线安装包{}解出程序包",
+            bundle.path().display()
+        ));
+
+        bundle.extract_requirements(requirements_path)?;
+        bundle.extract_packages(cached_packages_dir, status_updater)?;
+
+        // 直接链接的需求按文件名对应到离线安装包中的程序包，安装时不访问原链接
+        let requirements = RequirementsFile::read(requirements_path)?.requirements()?;
+        for requirement in &requirements {
+            if let Some(VersionOrUrl::Url(url)) = &requirement.version_or_url {
+                if let Err(err) = direct_url_cached_file(installer, url.as_str()) {
+                    bail!("离线安装包不完整: {}", err);
+                }
+            }
+        }
+
+        return Ok(requirements);
+    }
+
+    // 明确指定的需求文件优先于内置锁文件和依赖解析
+    if let Some(path) = installer.requirements_file() {
+        log::info!(
+            "采用指定的需求文件{}，不使用内置锁文件，也不解析依赖",
+            path.display()
+        );
+    } else {
+        if let Some(lock) = installer.lock_file() {
+            status_updater.message(&format!("按锁文件安装{}个程序包", lock.packages().len()));
+
+            let locked = lock.requirements()?;
+            write_requirements_file(requirements_path, &locked)?;
+
+            log::info!(
+                "锁文件中的版本写入程序包需求文件: {}",
+                requirements_path.display()
+            );
+            return Ok(locked);
+        }
+
+        if installer.has_lock_file() {
+            log::warn!(
+                "内置锁文件不适用于Python {}({})，按需求文件安装",
+                installer.python_version,
+                installer.platform_tag.as_deref().unwrap_or_default()
+            );
+        }
+
+        if installer.resolve_requirements() {
+            status_updater.message("解析程序包依赖关系");
+
+            let pinned = resolve_top_level_requirements(installer, status_updater).await?;
+            write_requirements_file(requirements_path, &pinned)?;
+
+            log::info!(
+                "依赖解析结果写入程序包需求文件: {}",
+                requirements_path.display()
+            );
+            return Ok(pinned);
+        }
+    }
+
+    let requirements_file = load_requirements_file(installer)?;
+    installer.add_index_urls(
+        requirements_file.index_url(),
+        requirements_file.extra_index_urls(),
+    );
+    installer.set_hash_pins(requirements_file.hash_pins());
+    if requirements_file.allow_prereleases() {
+        installer.set_allow_prereleases(true);
+    }
+    if requirements_file.prefer_binary() {
+        installer.set_prefer_binary(true);
+    }
+
+    let mut requirements = requirements_file.requirements()?;
     requirements.append(&mut get_obligated_requirements(installer)?);
+    let requirements = applicable_requirements(installer, requirements);
+
+    // 保留直接链接需求的原链接，安装时再对应到已下载的文件
+    write_requirements_file(requirements_path, &requirements)?;
+    log::info!("程序包需求文件: {}", requirements_path.display());
 
     Ok(requirements)
 }
 
+/// 从内置的顶层需求`requirements.txt`（加上必需的程序包）解析出一致的固定版本集合
+pub async fn resolve_top_level_requirements(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+) -> Result<Vec<Requirement>> {
+    use super::resolver::Resolver;
+
+    let text = String::from_utf8_lossy(RESOURCES.get_top_level_requirements()).to_string();
+    let mut requirements = RequirementsFile::parse(&text, None)?.requirements()?;
+    requirements.append(&mut get_obligated_requirements(installer)?);
+
+    let mut resolver = Resolver::new(installer, status_updater)?;
+    resolver.resolve(&requirements).await
+}
+
+/// 内置或指定的需求文件中的需求（加上必需的程序包），不考虑锁文件
+pub fn embedded_requirements(installer: &Installer) -> Result<Vec<Requirement>> {
+    let mut requirements = load_requirements_file(installer)?.requirements()?;
+    requirements.append(&mut get_obligated_requirements(installer)?);
+
+    Ok(applicable_requirements(installer, requirements))
+}
+
+/// 按目标Python的标记环境求值各需求的PEP 508环境标记，去掉不适用的需求。
+///
+/// 未获取标记环境（未调用set_platform_info）时保留全部需求
+fn applicable_requirements(
+    installer: &Installer,
+    requirements: Vec<Requirement>,
+) -> Vec<Requirement> {
+    let Some(marker_env) = installer.marker_env.as_ref() else {
+        log::warn!("未获取目标Python的标记环境，不求值需求的环境标记");
+        return requirements;
+    };
+
+    let mut applicable = Vec::new();
+    for requirement in requirements {
+        let Some(marker) = requirement.marker.as_ref() else {
+            applicable.push(requirement);
+            continue;
+        };
+
+        if marker.evaluate(marker_env, &[]) {
+            applicable.push(requirement);
+        } else {
+            log::info!(
+                "跳过需求{}: 标记`{}`在目标环境(sys_platform={}, platform_machine={}, python_full_version={})下不成立",
+                requirement.name,
+                marker,
+                marker_env.sys_platform,
+                marker_env.platform_machine,
+                marker_env.python_full_version.string,
+            );
+        }
+    }
+
+    applicable
+}
+
+/// 每行一个`name==version`写入需求文件，供离线安装使用
+pub fn write_requirements_file(path: &Path, requirements: &[Requirement]) -> Result<()> {
+    let mut content = String::new();
+    for requirement in requirements {
+        content.push_str(&requirement.to_string());
+        content.push('\n');
+    }
+
+    if let Err(err) = std::fs::write(path, content) {
+        bail!("写入程序包需求文件{}错误: {}", path.display(), err)
+    }
+
+    Ok(())
+}
+
+/// 写入pip离线安装用的需求文件：直接链接的需求改为指向缓存目录中对应的文件，
+/// `--hash`已在下载时校验，不再写入
+fn write_install_requirements(
+    installer: &Installer,
+    path: &Path,
+    requirements: &[Requirement],
+) -> Result<()> {
+    let mut content = String::new();
+    for requirement in requirements {
+        let line = match &requirement.version_or_url {
+            Some(VersionOrUrl::Url(url)) => {
+                let cached_filename = direct_url_cached_file(installer, url.as_str())?;
+                let Ok(file_url) = url::Url::from_file_path(&cached_filename) else {
+                    bail!("无法转换为链接的路径: {}", cached_filename.display())
+                };
+
+                let extras = match &requirement.extras {
+                    Some(extras) if !extras.is_empty() => format!("[{}]", extras.join(",")),
+                    _ => String::new(),
+                };
+                format!("{}{} @ {}", requirement.name, extras, file_url)
+            }
+            _ => requirement.to_string(),
+        };
+
+        content.push_str(&line);
+        content.push('\n');
+    }
+
+    if let Err(err) = std::fs::write(path, content) {
+        bail!("写入程序包需求文件{}错误: {}", path.display(), err)
+    }
+
+    Ok(())
+}
+
+/// 直接链接的需求在缓存目录中对应的文件：链接为源码包且已构建为wheel时取构建的wheel，
+/// 否则按链接中的文件名。从离线安装包安装时缓存目录中即为包中的程序包
+pub(super) fn direct_url_cached_file(installer: &Installer, url: &str) -> Result<PathBuf> {
+    let cached_dir = &installer.cached_packages_dir;
+    let file_name = direct_url_file_name(url)?;
+
+    let (base, extension) = split_filename_extension(&file_name)?;
+    let extension = extension.to_lowercase();
+    let sdist = match base.rsplit_once('-') {
+        Some((name, version)) if extension == ".zip" || is_tar_extension(&extension) => {
+            Some((canonicalize_name(name), version))
+        }
+        _ => None,
+    };
+
+    if let Some((name, version)) = sdist {
+        for entry in std::fs::read_dir(cached_dir)? {
+            let path = entry?.path();
+            let wheel_file = path.file_name().unwrap().to_string_lossy().to_string();
+            let Some(wheel_base) = wheel_file.strip_suffix(".whl") else {
+                continue;
+            };
+
+            // wheel文件名为{name}-{version}-...
+            let mut parts = wheel_base.split('-');
+            if let (Some(wheel_name), Some(wheel_version)) = (parts.next(), parts.next()) {
+                if canonicalize_name(wheel_name) == name && wheel_version == version {
+                    return Ok(path);
+                }
+            }
+        }
+    }
+
+    let cached_filename = cached_dir.join(&file_name);
+    if !cached_filename.is_file() {
+        bail!("直接链接{}对应的文件{}不在缓存目录中", url, file_name);
+    }
+
+    Ok(cached_filename)
+}
+
 // pub async fn download_requirements(
 //     installer: &Installer,
 //     collector: &impl StatusUpdate,
@@ -43,18 +281,34 @@ pub async fn prepare_requirements(installer: &Installer) -> Result<Vec<Requireme
 //     Ok(())
 // }
 
+/// 下载满足需求的程序包，返回选定的文件名
 pub async fn retry_download_requirement(
     installer: &Installer,
     collector: &impl StatusUpdate,
     requirement: &Requirement,
-) -> Result<(), DownloadingError> {
-    use super::project::download_requirement;
+) -> Result<String, DownloadingError> {
+    use super::lockfile::download_locked_package;
+    use super::project::{download_direct_url, download_requirement};
+
+    if let Some(VersionOrUrl::Url(url)) = &requirement.version_or_url {
+        return download_direct_url(installer, collector, requirement, url.as_str()).await;
+    }
+
+    if let Some(package) = installer
+        .lock_file()
+        .and_then(|lock| lock.find(&requirement.name))
+    {
+        return download_locked_package(installer, collector, package).await;
+    }
+
+    let mirror_health = installer.mirror_health();
 
     let mut errors = Vec::new();
-    for pypi in installer.pypi_mirrors() {
+    for pypi in installer.available_pypi_mirrors() {
         match download_requirement(installer, collector, &pypi, requirement).await {
-            Ok(_) => {
-                return Ok(());
+            Ok(file_name) => {
+                mirror_health.record_success(pypi);
+                return Ok(file_name);
             }
             Err(err) => {
                 log::error!(
@@ -63,6 +317,7 @@ pub async fn retry_download_requirement(
                     requirement.name,
                     err
                 );
+                mirror_health.record_failure(pypi, &err);
                 let pypi_name = pypi.name();
                 errors.push(format!("尝试从{pypi_name}镜像下载发生错误: {err}"));
             }
@@ -90,15 +345,39 @@ pub async fn retry_download_requirement(
     // )
 }
 
+/// 从下载目录离线安装需求文件中的程序包，按配置由安装程序直接安装wheel或调用pip
 pub async fn offline_install_requirements(
     installer: &Installer,
-) -> Result<()> {
-    use super::venv::venv_python_cmd;
+    status_updater: &impl StatusUpdate,
+) -> Result<(), InstallingError> {
+    use super::config::InstallBackend;
+    use super::wheel::install_wheels;
+
+    if installer.install_backend() == InstallBackend::Native {
+        let requirements_path = get_requirements_path(installer);
+        let requirements = RequirementsFile::read(&requirements_path)
+            .and_then(|file| file.requirements())
+            .map_err(InstallingError::other)?;
+
+        log::info!(
+            "开始从本地{}安装程序需求{}",
+            installer.cached_packages_dir.display(),
+            requirements_path.display()
+        );
+        return install_wheels(installer, status_updater, &requirements).await;
+    }
+
+    let requirements = RequirementsFile::read(&get_requirements_path(installer))
+        .and_then(|file| file.requirements())
+        .map_err(InstallingError::other)?;
 
-    let requirements_path = get_requirements_path(installer)
-        .await?
-        .to_string_lossy()
-        .to_string();
+    // pip离线安装时直接链接的需求只能指向本地文件
+    let tmp_dir = tempfile::tempdir().map_err(InstallingError::other)?;
+    let requirements_path = tmp_dir.path().join("requirements.txt");
+    write_install_requirements(installer, &requirements_path, &requirements)
+        .map_err(InstallingError::other)?;
+
+    let requirements_path = requirements_path.to_string_lossy().to_string();
     let cached_packages_dir = installer.cached_packages_dir.to_string_lossy().to_string();
 
     let args = &vec![
@@ -118,73 +397,99 @@ pub async fn offline_install_requirements(
         requirements_path,
     );
 
-    let err = match venv_python_cmd(installer, args) {
-        Ok(output) => {
-            if output.status.success() {
-                return Ok(());
-            }
+    pip_install_with_progress(installer, status_updater, args, requirements.len())
+}
 
-            String::from_utf8_lossy(&output.stderr).to_string()
+/// 运行`pip install`，逐行解析其输出，按处理过的程序包数更新进度
+fn pip_install_with_progress(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    args: &[&str],
+    n_requirements: usize,
+) -> Result<(), InstallingError> {
+    use super::pipevents::{
+        classify_pip_error, parse_pip_line, read_lines_in_background, PipEvent,
+    };
+    use super::venv::venv_python_spawn;
+
+    let mut child = venv_python_spawn(installer, args).map_err(InstallingError::other)?;
+    let lines =
+        read_lines_in_background(child.stdout.take().unwrap(), child.stderr.take().unwrap());
+
+    let mut output = Vec::new();
+    let mut last_package: Option<String> = None;
+    let mut n_processed = 0;
+    let mut n_total = n_requirements;
+    status_updater.update_progress(0, 0, n_total);
+
+    // 两个读取线程结束后通道关闭
+    for line in lines {
+        match parse_pip_line(&line) {
+            Some(PipEvent::Collecting(name)) => {
+                last_package = Some(name);
+            }
+            Some(PipEvent::Processing(name)) | Some(PipEvent::AlreadySatisfied(name)) => {
+                n_processed += 1;
+                n_total = n_total.max(n_processed);
+                status_updater.message(&format!("处理{}", name));
+                status_updater.update_progress(n_processed, 0, n_total);
+                last_package = Some(name);
+            }
+            Some(PipEvent::Installing(names)) => {
+                status_updater.message(&format!("安装{}个程序包", names.len()));
+            }
+            Some(PipEvent::Installed(installed)) => {
+                log::info!("安装完成: {}", installed.join(" "));
+                status_updater.update_progress(n_total, 0, n_total);
+            }
+            Some(PipEvent::Error(err)) => {
+                log::error!("pip: {}", err);
+            }
+            None => {}
         }
-        Err(err) => err.to_string(),
+        output.push(line);
+    }
+
+    let status = match child.wait() {
+        Ok(status) => status,
+        Err(err) => return Err(InstallingError::other(format!("等待pip结束错误: {}", err))),
     };
+    log::info!(
+        "执行结果: STATUS: {}\nOUTPUT:\n{}\n",
+        status,
+        output.join("\n")
+    );
 
-    bail!("程序包本地安装发生错误: {}", err)
-}
+    if status.success() {
+        return Ok(());
+    }
 
-use super::super::resources::RESOURCES;
+    status_updater.update_progress(n_processed, 1, n_total);
+    Err(classify_pip_error(&output, last_package.as_deref()))
+}
 
-async fn get_requirements_path(installer: &Installer) -> Result<PathBuf> {
+/// 准备阶段写入的程序包需求文件，离线安装时以`pip install -r`使用
+pub(super) fn get_requirements_path(installer: &Installer) -> PathBuf {
     let filename = format!(
         "requirements-{}-{}.txt",
         installer.python_version,
         installer.platform_tag.as_ref().unwrap()
     );
 
-    let requirements_path = installer.tgba_dir().join(&filename);
-
-    let mut file = File::create(&requirements_path)?;
-
-    use std::io::Write;
-    file.write_all(RESOURCES.get_requirements_file(&installer.python_version))?;
-
-    Ok(requirements_path)
+    installer.tgba_dir().join(&filename)
 }
 
-async fn extract_requirements(requirements_path: &PathBuf) -> Result<Vec<Requirement>> {
-    let file = File::open(requirements_path).unwrap();
-
-    use std::io::{BufRead, BufReader};
-    let reader = BufReader::new(file);
-
-    let mut requirements = Vec::new();
-    let mut errors = Vec::new();
-    for (line_idx, line) in reader.lines().enumerate() {
-        let line = line.unwrap(); // Ignore errors.
-
-        use std::str::FromStr;
-        match Requirement::from_str(line.as_str()) {
-            Ok(requirement) => {
-                requirements.push(requirement);
-            }
-            Err(err) => {
-                errors.push((line_idx + 1, err));
-            }
-        };
-    }
-
-    if errors.len() > 0 {
-        let mut lines = Vec::new();
-        for (line_no, err) in errors {
-            lines.push(format!("Line {}: {}", line_no, err));
+/// 指定的需求文件，未指定时为内置的目标Python版本需求文件
+fn load_requirements_file(installer: &Installer) -> Result<RequirementsFile> {
+    match installer.requirements_file() {
+        Some(path) => RequirementsFile::read(path),
+        None => {
+            let text =
+                String::from_utf8_lossy(RESOURCES.get_requirements_file(&installer.python_version))
+                    .to_string();
+            RequirementsFile::parse(&text, None)
         }
-        bail!(
-            "errors in parsing requirements file: \n{}",
-            lines.join("\n")
-        )
     }
-
-    Ok(requirements)
 }
 
 // use super::config::OBLIGATED_PACKAGES;
@@ -204,15 +509,12 @@ fn get_obligated_requirements(installer: &Installer) -> Result<Vec<Requirement>>
         };
     }
 
-    if errors.len() > 0 {
+    if !errors.is_empty() {
         let mut lines = Vec::new();
         for (line_no, err) in errors {
-            lines.push(format!("Line {}: {}", line_no, err));
+            lines.push(format!("第{}行: {}", line_no, err));
         }
-        bail!(
-            "errors in parsing requirements file: \n{}",
-            lines.join("\n")
-        )
+        bail!("解析需求文件错误:\n{}", lines.join("\n"))
     }
 
     Ok(requirements)
@@ -0,0 +1,407 @@
+use anyhow::{bail, Result};
+use pep440_rs::{Version, VersionSpecifiers};
+use pep508_rs::{MarkerEnvironment, Requirement, VersionOrUrl};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::str::FromStr;
+
+use super::super::status::StatusUpdate;
+use super::installer::Installer;
+use super::link::PackageLink;
+use super::metadata::{fetch_dist_metadata, DistMetadata};
+use super::project::{
+    compatible_links, fetch_project_index, mentions_prerelease, pins_exact_version, ProjectIndex,
+};
+use super::utils::canonicalize_name;
+
+/// 回溯次数的上限，超过后放弃并报告最后一次冲突
+const MAX_BACKTRACKS: usize = 5000;
+
+/// 回溯式依赖解析。
+///
+/// 从顶层需求出发，按需获取各程序包的索引和核心元数据，依次为每个程序包选定满足全部约束的
+/// 最高版本，并把该版本的`Requires-Dist`（按目标环境求值标记后）加入约束；
+/// 出现冲突时撤销最近的选择，改试下一个版本。
+pub struct Resolver<'a, S: StatusUpdate> {
+    installer: &'a Installer,
+    status_updater: &'a S,
+    marker_env: &'a MarkerEnvironment,
+    indexes: HashMap<String, ProjectIndex>,
+    metadata: HashMap<String, DistMetadata>, // file_name -> metadata
+}
+
+/// 选定的版本
+#[derive(Debug, Clone)]
+struct Pin {
+    name: String,
+    version: Version,
+    requires_dist: Vec<Requirement>,
+}
+
+/// 对某个程序包版本的约束及其来源
+#[derive(Debug, Clone)]
+struct Constraint {
+    specifiers: Option<VersionSpecifiers>,
+    from: String,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ResolveState {
+    pins: BTreeMap<String, Pin>,
+    constraints: HashMap<String, Vec<Constraint>>,
+    extras: HashMap<String, BTreeSet<String>>,
+    pending: VecDeque<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Candidate {
+    version: Version,
+    link: PackageLink,
+}
+
+/// 回溯栈中的一层：为name逐个尝试candidates，before为作出选择前的状态
+struct Frame {
+    name: String,
+    candidates: Vec<Candidate>,
+    next: usize,
+    before: ResolveState,
+}
+
+impl<'a, S: StatusUpdate> Resolver<'a, S> {
+    pub fn new(installer: &'a Installer, status_updater: &'a S) -> Result<Self> {
+        let Some(marker_env) = installer.marker_env.as_ref() else {
+            bail!("未获取目标Python的标记环境，无法解析依赖")
+        };
+
+        Ok(Resolver {
+            installer,
+            status_updater,
+            marker_env,
+            indexes: HashMap::new(),
+            metadata: HashMap::new(),
+        })
+    }
+
+    /// 解析顶层需求，返回按名称排序的`name==version`固定版本列表
+    pub async fn resolve(&mut self, requirements: &[Requirement]) -> Result<Vec<Requirement>> {
+        let mut state = ResolveState::default();
+        for requirement in requirements {
+            if !self.is_applicable(requirement, &[]) {
+                log::info!("需求{}不适用于目标环境，跳过", requirement);
+                continue;
+            }
+
+            if let Err(conflict) = self.add_requirement(&mut state, requirement, "需求文件") {
+                bail!("顶层需求相互冲突: {}", conflict);
+            }
+        }
+
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut n_backtracks = 0;
+        let mut last_conflict = String::new();
+
+        while let Some(name) = next_undecided(&mut state) {
+            let candidates = self.candidates(&name, &state).await?;
+            if candidates.is_empty() {
+                last_conflict = format!("没有满足{}的版本", describe_constraints(&state, &name));
+            }
+
+            stack.push(Frame {
+                name,
+                candidates,
+                next: 0,
+                before: state.clone(),
+            });
+
+            // 为栈顶的程序包找到可行的版本，全部不可行时回溯到上一层
+            loop {
+                let Some(frame) = stack.last_mut() else {
+                    bail!("无法找到满足全部需求的版本组合: {}", last_conflict);
+                };
+
+                if frame.next >= frame.candidates.len() {
+                    log::info!("{}无可行版本，回溯", frame.name);
+                    stack.pop();
+
+                    n_backtracks += 1;
+                    if n_backtracks > MAX_BACKTRACKS {
+                        bail!("回溯超过{}次，放弃解析: {}", MAX_BACKTRACKS, last_conflict);
+                    }
+                    continue;
+                }
+
+                let name = frame.name.clone();
+                let candidate = frame.candidates[frame.next].clone();
+                let mut trial = frame.before.clone();
+                frame.next += 1;
+
+                match self.try_pin(&mut trial, &name, &candidate).await? {
+                    Ok(()) => {
+                        state = trial;
+                        break;
+                    }
+                    Err(conflict) => {
+                        log::info!("{}=={}不可行: {}", name, candidate.version, conflict);
+                        last_conflict = conflict;
+                    }
+                }
+            }
+        }
+
+        let mut pinned = Vec::new();
+        for pin in state.pins.values() {
+            let line = format!("{}=={}", pin.name, pin.version);
+            match Requirement::from_str(&line) {
+                Ok(requirement) => pinned.push(requirement),
+                Err(err) => bail!("生成固定版本需求{}错误: {}", line, err),
+            }
+        }
+
+        self.status_updater
+            .message(&format!("依赖解析完成，共{}个程序包", pinned.len()));
+
+        Ok(pinned)
+    }
+
+    fn is_applicable(&self, requirement: &Requirement, extras: &[&str]) -> bool {
+        match &requirement.marker {
+            Some(marker) => marker.evaluate(self.marker_env, extras),
+            None => true,
+        }
+    }
+
+    /// 加入一条约束。程序包已选定版本时立即检查是否相容，并补充新增extras的依赖；
+    /// 否则放入待决定队列。冲突时返回冲突说明
+    fn add_requirement(
+        &self,
+        state: &mut ResolveState,
+        requirement: &Requirement,
+        from: &str,
+    ) -> std::result::Result<(), String> {
+        let name = canonicalize_name(&requirement.name);
+
+        let specifiers = match &requirement.version_or_url {
+            Some(VersionOrUrl::VersionSpecifier(specifiers)) => Some(specifiers.clone()),
+            Some(VersionOrUrl::Url(url)) => {
+                return Err(format!("{}: 不支持直接给链接的需求{}", from, url));
+            }
+            None => None,
+        };
+
+        state
+            .constraints
+            .entry(name.clone())
+            .or_default()
+            .push(Constraint {
+                specifiers: specifiers.clone(),
+                from: from.to_string(),
+            });
+
+        let requested_extras = state.extras.entry(name.clone()).or_default();
+        let mut new_extras = Vec::new();
+        for extra in requirement.extras.iter().flatten() {
+            if requested_extras.insert(extra.to_lowercase()) {
+                new_extras.push(extra.to_lowercase());
+            }
+        }
+
+        let Some(pin) = state.pins.get(&name).cloned() else {
+            state.pending.push_back(name);
+            return Ok(());
+        };
+
+        if let Some(specifiers) = &specifiers {
+            if !specifiers.contains(&pin.version) {
+                return Err(format!(
+                    "{}需要{}，与已选定的{}=={}冲突",
+                    from, requirement, pin.name, pin.version
+                ));
+            }
+        }
+
+        let pin_from = format!("{}=={}", pin.name, pin.version);
+        for extra in &new_extras {
+            for dep in &pin.requires_dist {
+                // 只补充由该extra引入的依赖，其余依赖在选定版本时已加入
+                if self.is_applicable(dep, &[extra.as_str()]) && !self.is_applicable(dep, &[]) {
+                    self.add_requirement(state, dep, &pin_from)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 试选candidate，成功时把它的依赖加入状态
+    async fn try_pin(
+        &mut self,
+        state: &mut ResolveState,
+        name: &str,
+        candidate: &Candidate,
+    ) -> Result<std::result::Result<(), String>> {
+        let metadata = self.dist_metadata(name, &candidate.link).await?.clone();
+
+        if let Some(requires_python) = metadata.requires_python() {
+            if let Ok(specifiers) = VersionSpecifiers::from_str(requires_python) {
+                if !specifiers.contains(&self.marker_env.python_full_version.version) {
+                    return Ok(Err(format!("需要Python{}", requires_python)));
+                }
+            }
+        }
+
+        let pin = Pin {
+            name: metadata.name().to_string(),
+            version: candidate.version.clone(),
+            requires_dist: metadata.requires_dist().to_vec(),
+        };
+        let pin_from = format!("{}=={}", pin.name, pin.version);
+        state.pins.insert(name.to_string(), pin);
+
+        self.status_updater
+            .message(&format!("解析依赖: {}", pin_from));
+
+        let extras = state
+            .extras
+            .get(name)
+            .map(|extras| extras.iter().cloned().collect::<Vec<String>>())
+            .unwrap_or_default();
+        let extras = extras.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+
+        for dep in metadata.requires_dist() {
+            if !self.is_applicable(dep, &extras) {
+                continue;
+            }
+
+            if let Err(conflict) = self.add_requirement(state, dep, &pin_from) {
+                return Ok(Err(conflict));
+            }
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// 程序包满足当前全部约束的候选版本，从高到低排列，每个版本取最适合当前环境的下载项
+    async fn candidates(&mut self, name: &str, state: &ResolveState) -> Result<Vec<Candidate>> {
+        let constraints = state
+            .constraints
+            .get(name)
+            .map(|c| c.as_slice())
+            .unwrap_or_default();
+
+        // 约束中明确给出预发布版本或者配置允许时才考虑预发布版本
+        let allow_prerelease = self.installer.allow_prereleases()
+            || constraints.iter().any(|c| match &c.specifiers {
+                Some(specifiers) => mentions_prerelease(specifiers),
+                None => false,
+            });
+
+        // PEP 592：以==固定版本时才可以选择已撤回的文件
+        let allow_yanked = constraints.iter().any(|c| match &c.specifiers {
+            Some(specifiers) => pins_exact_version(specifiers),
+            None => false,
+        });
+
+        let installer = self.installer;
+        let index = self.project_index(name).await?;
+
+        let mut candidates: Vec<Candidate> = Vec::new();
+        for (version, _, link) in compatible_links(installer, index)? {
+            if candidates.last().map(|c| c.version == version) == Some(true) {
+                continue; // 同一版本只保留最适合的下载项
+            }
+
+            if let Some(reason) = link.yanked_reason() {
+                if !allow_yanked {
+                    continue;
+                }
+                log::warn!("{}已被撤回（yanked）{}", link.file_name(), reason);
+            }
+
+            if version.any_prerelease() && !allow_prerelease {
+                continue;
+            }
+
+            let satisfied = constraints.iter().all(|c| match &c.specifiers {
+                Some(specifiers) => specifiers.contains(&version),
+                None => true,
+            });
+            if !satisfied {
+                continue;
+            }
+
+            candidates.push(Candidate {
+                version,
+                link: link.clone(),
+            });
+        }
+
+        // 有wheel的版本先于只有源码包的版本尝试，各自保持从高到低
+        if self.installer.prefer_binary() {
+            candidates.sort_by_key(|candidate| !candidate.link.is_wheel());
+        }
+
+        Ok(candidates)
+    }
+
+    /// 依次从各镜像获取程序包索引，结果在本次解析中缓存
+    async fn project_index(&mut self, name: &str) -> Result<&ProjectIndex> {
+        if !self.indexes.contains_key(name) {
+            let mut errors = Vec::new();
+            let mut found = None;
+            for pypi in self.installer.available_pypi_mirrors() {
+                match fetch_project_index(self.installer, self.status_updater, pypi, name).await {
+                    Ok(index) => {
+                        found = Some(index);
+                        break;
+                    }
+                    Err(err) => errors.push(format!("{}: {}", pypi.name(), err)),
+                }
+            }
+
+            let Some(index) = found else {
+                bail!("获取{}程序包索引失败:\n{}", name, errors.join("\n"))
+            };
+            self.indexes.insert(name.to_string(), index);
+        }
+
+        Ok(&self.indexes[name])
+    }
+
+    async fn dist_metadata(&mut self, name: &str, link: &PackageLink) -> Result<&DistMetadata> {
+        if !self.metadata.contains_key(link.file_name()) {
+            let metadata =
+                fetch_dist_metadata(self.installer, self.status_updater, name, link).await?;
+            self.metadata.insert(link.file_name().to_string(), metadata);
+        }
+
+        Ok(&self.metadata[link.file_name()])
+    }
+}
+
+/// 取出下一个尚未选定版本的程序包
+fn next_undecided(state: &mut ResolveState) -> Option<String> {
+    while let Some(name) = state.pending.pop_front() {
+        if !state.pins.contains_key(&name) {
+            return Some(name);
+        }
+    }
+
+    None
+}
+
+fn describe_constraints(state: &ResolveState, name: &str) -> String {
+    let constraints = state
+        .constraints
+        .get(name)
+        .map(|c| c.as_slice())
+        .unwrap_or_default();
+
+    let descriptions = constraints
+        .iter()
+        .map(|c| match &c.specifiers {
+            Some(specifiers) => format!("{}{}({})", name, specifiers, c.from),
+            None => format!("{}({})", name, c.from),
+        })
+        .collect::<Vec<String>>();
+
+    descriptions.join("、")
+}
@@ -0,0 +1,116 @@
+use serde_derive::Deserialize;
+use std::future::Future;
+use std::time::Duration;
+
+use super::super::errors::{DownloadingError, DownloadingErrorKind};
+use super::super::status::StatusUpdate;
+
+/// 下载失败后的自动重试策略，对应config.toml中的`[retry]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// 每个镜像最多尝试的次数，包括第一次
+    attempts: u32,
+    /// 第一次重试前等待的毫秒数，之后每次加倍
+    backoff_ms: u64,
+    /// 等待时间的上限
+    backoff_max_ms: u64,
+    /// 等待时间随机抖动的比例，0.3表示在±30%内浮动
+    jitter: f64,
+    /// 可以重试的错误类别
+    retryable: Vec<DownloadingErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff_ms: 1000,
+            backoff_max_ms: 15000,
+            jitter: 0.3,
+            retryable: vec![
+                DownloadingErrorKind::Timeout,
+                DownloadingErrorKind::ServerError,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn attempts(&self) -> u32 {
+        self.attempts.max(1)
+    }
+
+    pub fn is_retryable(&self, kind: DownloadingErrorKind) -> bool {
+        self.retryable.contains(&kind)
+    }
+
+    /// 第attempt次失败后到下次尝试前的等待时间
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(16);
+        let delay = self
+            .backoff_ms
+            .saturating_mul(1u64 << exp)
+            .min(self.backoff_max_ms) as f64;
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 + jitter * (random_unit() * 2.0 - 1.0);
+
+        Duration::from_millis((delay * factor).max(0.0) as u64)
+    }
+}
+
+/// 按重试策略执行f，直到成功、遇到不可重试的错误或用完尝试次数
+pub async fn with_retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    status_updater: &impl StatusUpdate,
+    title: &str,
+    mut f: F,
+) -> Result<T, DownloadingError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, DownloadingError>>,
+{
+    let attempts = policy.attempts();
+
+    let mut attempt = 1;
+    loop {
+        let err = match f().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+
+        if !policy.is_retryable(err.kind()) {
+            log::error!("{title}失败(第{attempt}次尝试，不可重试): {err}");
+            return Err(err);
+        }
+
+        if attempt >= attempts {
+            log::error!("{title}失败(已尝试{attempt}次): {err}");
+            return Err(err);
+        }
+
+        let delay = policy.backoff(attempt);
+        log::warn!(
+            "{title}失败(第{attempt}/{attempts}次尝试): {err}，{:.1}秒后重试",
+            delay.as_secs_f64()
+        );
+        status_updater.message(&format!(
+            "{title}失败，{:.0}秒后第{}次尝试",
+            delay.as_secs_f64().ceil(),
+            attempt + 1
+        ));
+
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// [0, 1)之间的随机数，仅用于抖动等待时间
+fn random_unit() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let value = RandomState::new().build_hasher().finish();
+    (value >> 11) as f64 / (1u64 << 53) as f64
+}
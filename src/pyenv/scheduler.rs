@@ -0,0 +1,130 @@
+use pep508_rs::Requirement;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use super::super::errors::{DownloadingError, DownloadingFailures};
+use super::super::status::{DownloadingStats, StatusUpdate};
+use super::installer::Installer;
+use super::requirements::retry_download_requirement;
+
+/// 以installer.download_concurrency个下载槽并行下载全部需求。
+///
+/// 每个程序包仍按镜像顺序依次尝试，某个包下载失败不会中断其它包的下载，
+/// 全部结束后将失败的程序包一并返回。
+pub async fn download_requirements(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    requirements: &[Requirement],
+) -> Result<(), DownloadingFailures> {
+    if installer.bundle().is_some() {
+        // 程序包已在准备需求时从离线安装包解出
+        log::info!("从离线安装包安装，不下载程序包");
+        return Ok(());
+    }
+
+    let n_slots = installer
+        .download_concurrency()
+        .clamp(1, requirements.len().max(1));
+
+    log::info!(
+        "开始下载{}个程序包，并行下载数: {}",
+        requirements.len(),
+        n_slots
+    );
+
+    let batch = BatchState {
+        next_idx: AtomicUsize::new(0),
+        finished: AtomicUsize::new(0),
+        failures: Mutex::new(Vec::new()),
+    };
+
+    status_updater.update_progress(0, 0, requirements.len());
+
+    let workers = (0..n_slots).map(|slot| {
+        let slot_updater = SlotStatus {
+            slot,
+            inner: status_updater,
+        };
+        let batch = &batch;
+        async move {
+            slot_worker(installer, &slot_updater, requirements, batch).await;
+        }
+    });
+
+    futures::future::join_all(workers).await;
+
+    let mut failures = batch.failures.into_inner().unwrap();
+    if failures.is_empty() {
+        return Ok(());
+    }
+
+    // 按需求文件中的顺序报告
+    failures.sort_by_key(|(idx, _)| *idx);
+    let failures = failures
+        .into_iter()
+        .map(|(idx, err)| (requirements[idx].clone(), err))
+        .collect();
+
+    Err(DownloadingFailures::new(failures))
+}
+
+struct BatchState {
+    next_idx: AtomicUsize,
+    finished: AtomicUsize,
+    failures: Mutex<Vec<(usize, DownloadingError)>>,
+}
+
+async fn slot_worker<S: StatusUpdate>(
+    installer: &Installer,
+    slot_updater: &SlotStatus<'_, S>,
+    requirements: &[Requirement],
+    batch: &BatchState,
+) {
+    let n_total = requirements.len();
+
+    loop {
+        let idx = batch.next_idx.fetch_add(1, Ordering::SeqCst);
+        if idx >= n_total {
+            break;
+        }
+
+        let requirement = &requirements[idx];
+        match retry_download_requirement(installer, slot_updater, requirement).await {
+            Ok(file_name) => {
+                installer.record_selected_package(file_name);
+                batch.finished.fetch_add(1, Ordering::SeqCst);
+                slot_updater.message(&format!("已下载{}", requirement));
+            }
+            Err(err) => {
+                log::error!("下载{}失败: {}", requirement, err);
+                batch.failures.lock().unwrap().push((idx, err));
+            }
+        }
+
+        let n_finished = batch.finished.load(Ordering::SeqCst);
+        let n_failed = batch.failures.lock().unwrap().len();
+        slot_updater
+            .inner
+            .update_progress(n_finished, n_failed, n_total);
+    }
+}
+
+/// 将下载进度转发到对应下载槽的状态更新
+struct SlotStatus<'a, S: StatusUpdate> {
+    slot: usize,
+    inner: &'a S,
+}
+
+impl<'a, S: StatusUpdate> StatusUpdate for SlotStatus<'a, S> {
+    fn message(&self, msg: &str) {
+        self.inner.message(msg);
+    }
+
+    fn update_downloading(&self, status: &DownloadingStats) {
+        self.inner.update_slot_downloading(self.slot, status);
+    }
+
+    fn update_progress(&self, finished: usize, failed: usize, total: usize) {
+        self.inner.update_progress(finished, failed, total);
+    }
+}
@@ -5,6 +5,7 @@ use regex::Regex;
 use std::fs::File;
 
 pub fn fix_patches(installer: &Installer) -> Result<()> {
+    #[cfg(windows)]
     fix_win_activate_scripts(installer)?;
 
     fix_matplotlibrc(installer)?;
@@ -29,12 +30,15 @@ pub fn clean_cached_dir(installer: &Installer) -> Result<()> {
     Ok(())
 }
 
+#[cfg(windows)]
 static SCRIPT_PROPMT_REGEX1: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"(set PROMPT=).*(%PROMPT%)"#).unwrap());
+#[cfg(windows)]
 static SCRIPT_PROPMT_REGEX2: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"(set VIRTUAL_ENV_PROMPT=).*"#).unwrap());
 
 /// 将虚拟环境提示符改为(TGBA)
+#[cfg(windows)]
 fn fix_win_activate_scripts(installer: &Installer) -> Result<()> {
     static PROMPT: &'static str = "TGBA ";
 
@@ -93,14 +97,8 @@ static SANSFAMILY_REGEX: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"#?(font\.sans-serif:).*"#).unwrap());
 
 fn fix_matplotlibrc(installer: &Installer) -> Result<()> {
-    let mut rcfile_path = installer.venv_dir.clone();
-    rcfile_path.extend([
-        "Lib",
-        "site-packages",
-        "matplotlib",
-        "mpl-data",
-        "matplotlibrc",
-    ]);
+    let mut rcfile_path = installer.site_packages_dir();
+    rcfile_path.extend(["matplotlib", "mpl-data", "matplotlibrc"]);
 
     let rcfile = File::open(&rcfile_path).unwrap();
     use std::io::{BufRead, BufReader};
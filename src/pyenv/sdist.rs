@@ -0,0 +1,603 @@
+use anyhow::{bail, Result};
+use pep508_rs::Requirement;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::super::errors::InstallingErrorKind;
+use super::super::status::StatusUpdate;
+use super::archive::{is_tar_extension, open_tar, unpack_archive};
+use super::installer::Installer;
+use super::pipevents::classify_pip_error;
+use super::requirements::retry_download_requirement;
+use super::resolver::Resolver;
+use super::utils::{canonicalize_name, split_filename_extension};
+use super::venv::venv_python_cmd;
+
+/// 源码包未声明`[build-system]`时pip采用的构建依赖
+static DEFAULT_BUILD_REQUIRES: [&str; 2] = ["setuptools>=40.8.0", "wheel"];
+
+/// 源码包未声明`build-backend`时pip采用的构建后端
+static DEFAULT_BUILD_BACKEND: &str = "setuptools.build_meta:__legacy__";
+
+/// 在临时构建环境中调用构建后端的`get_requires_for_build_wheel`，结果以JSON写入输出文件。
+///
+/// 参数依次为构建环境目录、源码目录、构建后端、`backend-path`（JSON）和输出文件
+static GET_REQUIRES_SCRIPT: &str = r#"
+import importlib, json, os, sys
+env_dir, source_dir, backend, backend_path, output = sys.argv[1:6]
+sys.path[:0] = [os.path.join(source_dir, p) for p in json.loads(backend_path)] + [env_dir]
+os.chdir(source_dir)
+module, _, attrs = backend.partition(":")
+hooks = importlib.import_module(module.strip())
+for attr in filter(None, attrs.strip().split(".")):
+    hooks = getattr(hooks, attr)
+hook = getattr(hooks, "get_requires_for_build_wheel", None)
+requires = list(hook({})) if hook else []
+with open(output, "w", encoding="utf-8") as f:
+    json.dump(requires, f)
+"#;
+
+/// pip构建隔离环境安装失败时输出的信息
+static BUILD_ENV_FAILED_HINTS: [&str; 2] = [
+    "pip subprocess to install build dependencies did not run successfully",
+    "pip subprocess to install backend dependencies did not run successfully",
+];
+
+/// 源码包`pyproject.toml`中的`[build-system]`
+#[derive(Debug, Clone, PartialEq)]
+struct BuildSystem {
+    requires: Vec<Requirement>,
+    backend: String,
+    backend_path: Vec<String>,
+}
+
+/// 构建失败输出中表明缺少C/C++或Rust编译器的信息
+static COMPILER_MISSING_HINTS: [&str; 8] = [
+    "Microsoft Visual C++ 14.0 or greater is required",
+    "Unable to find vcvarsall.bat",
+    "command 'cl.exe' failed",
+    "error: Microsoft Visual C++",
+    "command 'gcc' failed",
+    "command 'cc' failed",
+    "gcc: not found",
+    "can't find Rust compiler",
+];
+
+/// 下载阶段结束后，把缓存中没有对应wheel的源码包在虚拟环境中预先构建为wheel。
+///
+/// 先按源码包`pyproject.toml`中的`[build-system] requires`下载构建依赖到缓存目录，
+/// 再在临时构建环境中调用构建后端的`get_requires_for_build_wheel`，下载后端额外要求的构建依赖，
+/// 最后以`pip wheel --no-index`离线构建，离线安装时pip优先采用构建出的wheel
+pub async fn build_sdist_wheels(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+) -> Result<()> {
+    let sdists = find_unbuilt_sdists(&installer.cached_packages_dir)?;
+    if sdists.is_empty() {
+        return Ok(());
+    }
+
+    log::info!("需要从源码包构建{}个wheel", sdists.len());
+
+    for sdist_path in &sdists {
+        let file_name = sdist_path.file_name().unwrap().to_string_lossy();
+        status_updater.message(&format!("准备构建{}", file_name));
+
+        let build_system = read_build_system(sdist_path)?;
+        download_build_requires(
+            installer,
+            status_updater,
+            &file_name,
+            &build_system.requires,
+        )
+        .await?;
+
+        status_updater.message(&format!("查询{}的构建后端所需的依赖", file_name));
+        let backend_requires = query_backend_requires(installer, sdist_path, &build_system)?;
+        if !backend_requires.is_empty() {
+            download_build_requires(installer, status_updater, &file_name, &backend_requires)
+                .await?;
+        }
+
+        status_updater.message(&format!("从源码包构建{}（时间较长，耐心等候）", file_name));
+        build_wheel(installer, sdist_path)?;
+    }
+
+    Ok(())
+}
+
+/// 缓存目录中尚无同名同版本wheel的源码包
+fn find_unbuilt_sdists(cached_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut wheels = Vec::new();
+    let mut sdists = Vec::new();
+
+    for entry in std::fs::read_dir(cached_dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let Ok((base, extension)) = split_filename_extension(&file_name) else {
+            continue;
+        };
+
+        match extension.to_lowercase().as_str() {
+            ".whl" => {
+                // wheel文件名为{name}-{version}-...
+                let mut parts = base.split('-');
+                if let (Some(name), Some(version)) = (parts.next(), parts.next()) {
+                    wheels.push((canonicalize_name(name), version.to_string()));
+                }
+            }
+            extension if extension == ".zip" || is_tar_extension(extension) => {
+                // 源码包文件名为{name}-{version}
+                if let Some((name, version)) = base.rsplit_once('-') {
+                    sdists.push((canonicalize_name(name), version.to_string(), path));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(sdists
+        .into_iter()
+        .filter(|(name, version, _)| {
+            !wheels
+                .iter()
+                .any(|(wheel_name, wheel_version)| wheel_name == name && wheel_version == version)
+        })
+        .map(|(_, _, path)| path)
+        .collect())
+}
+
+/// 读取源码包顶层目录中`pyproject.toml`的`[build-system]`，未声明的项采用pip的默认值
+fn read_build_system(sdist_path: &Path) -> Result<BuildSystem> {
+    let file_name = sdist_path
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let (_, extension) = split_filename_extension(&file_name)?;
+
+    let pyproject = match read_pyproject(sdist_path, &extension.to_lowercase()) {
+        Ok(pyproject) => pyproject,
+        Err(err) => bail!("读取{}中的pyproject.toml错误: {}", file_name, err),
+    };
+
+    let build_system = match pyproject {
+        Some(text) => {
+            let value: toml::Value = match toml::from_str(&text) {
+                Ok(value) => value,
+                Err(err) => bail!("{}中的pyproject.toml格式错误: {}", file_name, err),
+            };
+            value.get("build-system").cloned()
+        }
+        None => None,
+    };
+    let build_system = build_system.as_ref();
+
+    let strings = |key: &str| {
+        build_system
+            .and_then(|build_system| build_system.get(key))
+            .and_then(|value| value.as_array())
+            .map(|values| {
+                values
+                    .iter()
+                    .filter_map(|value| value.as_str())
+                    .map(|value| value.to_string())
+                    .collect::<Vec<String>>()
+            })
+    };
+
+    let requires = strings("requires")
+        .unwrap_or_else(|| DEFAULT_BUILD_REQUIRES.map(|s| s.to_string()).to_vec());
+    let mut requirements = Vec::new();
+    for require in requires {
+        match Requirement::from_str(&require) {
+            Ok(requirement) => requirements.push(requirement),
+            Err(err) => bail!("{}的构建依赖{}格式错误: {}", file_name, require, err),
+        }
+    }
+
+    let backend = build_system
+        .and_then(|build_system| build_system.get("build-backend"))
+        .and_then(|backend| backend.as_str())
+        .unwrap_or(DEFAULT_BUILD_BACKEND)
+        .to_string();
+
+    Ok(BuildSystem {
+        requires: requirements,
+        backend,
+        backend_path: strings("backend-path").unwrap_or_default(),
+    })
+}
+
+fn read_pyproject(sdist_path: &Path, extension: &str) -> Result<Option<String>> {
+    if extension == ".zip" {
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(sdist_path)?)?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            if is_top_level_pyproject(entry.name()) {
+                let mut text = String::new();
+                entry.read_to_string(&mut text)?;
+                return Ok(Some(text));
+            }
+        }
+
+        return Ok(None);
+    }
+
+    if !is_tar_extension(extension) {
+        bail!("不支持的源码包格式{}", extension);
+    }
+
+    let mut archive = tar::Archive::new(open_tar(extension, std::fs::File::open(sdist_path)?)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        if is_top_level_pyproject(&name) {
+            let mut text = String::new();
+            entry.read_to_string(&mut text)?;
+            return Ok(Some(text));
+        }
+    }
+
+    Ok(None)
+}
+
+/// 源码包的内容位于`{name}-{version}/`目录下
+fn is_top_level_pyproject(path: &str) -> bool {
+    let parts = path
+        .trim_start_matches("./")
+        .split('/')
+        .collect::<Vec<&str>>();
+    parts.len() == 2 && parts[1] == "pyproject.toml"
+}
+
+/// 下载构建依赖（解析其依赖后）到缓存目录，供离线构建时的隔离构建环境安装
+async fn download_build_requires(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    sdist_name: &str,
+    build_requires: &[Requirement],
+) -> Result<()> {
+    let requirements = if installer.marker_env.is_some() {
+        let mut resolver = Resolver::new(installer, status_updater)?;
+        match resolver.resolve(build_requires).await {
+            Ok(pinned) => pinned,
+            Err(err) => bail!("解析{}的构建依赖错误: {}", sdist_name, err),
+        }
+    } else {
+        build_requires.to_vec()
+    };
+
+    for requirement in &requirements {
+        status_updater.message(&format!("下载{}的构建依赖{}", sdist_name, requirement));
+        if let Err(err) = retry_download_requirement(installer, status_updater, requirement).await {
+            bail!("下载{}的构建依赖{}错误: {}", sdist_name, requirement, err);
+        }
+    }
+
+    Ok(())
+}
+
+/// 把静态构建依赖从缓存目录安装到临时构建环境，解压源码包后调用构建后端的
+/// `get_requires_for_build_wheel`，返回静态构建依赖之外后端额外要求的依赖
+fn query_backend_requires(
+    installer: &Installer,
+    sdist_path: &Path,
+    build_system: &BuildSystem,
+) -> Result<Vec<Requirement>> {
+    let file_name = sdist_path
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let (_, extension) = split_filename_extension(&file_name)?;
+
+    let temp_dir = tempfile::Builder::new()
+        .prefix(".build-env-")
+        .tempdir_in(&installer.cached_packages_dir)?;
+    let env_dir = temp_dir.path().join("env");
+    let source_dir = temp_dir.path().join("source");
+    let output_path = temp_dir.path().join("requires.json");
+
+    if let Err(err) = unpack_archive(extension, sdist_path, &source_dir, 1) {
+        bail!("解压源码包{}错误: {}", file_name, err);
+    }
+
+    let cached_dir = installer.cached_packages_dir.to_string_lossy().to_string();
+    let env = env_dir.to_string_lossy().to_string();
+    let requires = build_system
+        .requires
+        .iter()
+        .map(|requirement| requirement.to_string())
+        .collect::<Vec<String>>();
+
+    let mut args = vec![
+        "-m",
+        "pip",
+        "install",
+        "--no-index",
+        "--find-links",
+        &cached_dir,
+        "--target",
+        &env,
+    ];
+    args.extend(requires.iter().map(|s| s.as_str()));
+
+    let output = venv_python_cmd(installer, &args)?;
+    if !output.status.success() {
+        let lines = output_lines(&output);
+        if let Some(message) = missing_build_requirement(&file_name, &lines) {
+            bail!("{}", message);
+        }
+        bail!(
+            "安装{}的构建依赖错误: {}",
+            file_name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let source = source_dir.to_string_lossy().to_string();
+    let backend_path = serde_json::to_string(&build_system.backend_path)?;
+    let output_file = output_path.to_string_lossy().to_string();
+    let args = [
+        "-c",
+        GET_REQUIRES_SCRIPT,
+        &env,
+        &source,
+        &build_system.backend,
+        &backend_path,
+        &output_file,
+    ];
+
+    let output = venv_python_cmd(installer, &args)?;
+    if !output.status.success() {
+        bail!(
+            "调用{}的构建后端{}查询构建依赖错误: {}",
+            file_name,
+            build_system.backend,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let requires: Vec<String> = match serde_json::from_str(&std::fs::read_to_string(&output_path)?)
+    {
+        Ok(requires) => requires,
+        Err(err) => bail!("{}的构建后端返回的构建依赖格式错误: {}", file_name, err),
+    };
+
+    let mut requirements = Vec::new();
+    for require in requires {
+        let requirement = match Requirement::from_str(&require) {
+            Ok(requirement) => requirement,
+            Err(err) => bail!("{}的构建依赖{}格式错误: {}", file_name, require, err),
+        };
+
+        let name = canonicalize_name(&requirement.name);
+        let listed = build_system
+            .requires
+            .iter()
+            .any(|static_require| canonicalize_name(&static_require.name) == name);
+        if !listed {
+            requirements.push(requirement);
+        }
+    }
+
+    if !requirements.is_empty() {
+        log::info!(
+            "{}的构建后端额外要求的构建依赖: {}",
+            file_name,
+            requirements
+                .iter()
+                .map(|requirement| requirement.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+
+    Ok(requirements)
+}
+
+/// 合并pip的stdout和stderr，按行拆分
+fn output_lines(output: &std::process::Output) -> Vec<String> {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// 构建失败是因为缓存目录中缺少构建依赖时，返回指明缺少的依赖的错误信息
+fn missing_build_requirement(file_name: &str, lines: &[String]) -> Option<String> {
+    let error = classify_pip_error(lines, None);
+    if error.kind() == InstallingErrorKind::NotFound {
+        return Some(match error.package() {
+            Some(package) => format!(
+                "从源码包{}构建wheel时缺少构建依赖{}，下载目录中没有满足要求的程序包",
+                file_name, package
+            ),
+            None => format!("从源码包{}构建wheel时缺少构建依赖: {}", file_name, error),
+        });
+    }
+
+    let build_env_failed = lines.iter().any(|line| {
+        BUILD_ENV_FAILED_HINTS
+            .iter()
+            .any(|hint| line.contains(hint))
+    });
+    if build_env_failed {
+        return Some(format!(
+            "从源码包{}构建wheel时无法安装构建依赖，请查看日志中pip的输出",
+            file_name
+        ));
+    }
+
+    None
+}
+
+fn build_wheel(installer: &Installer, sdist_path: &Path) -> Result<()> {
+    let cached_dir = installer.cached_packages_dir.to_string_lossy().to_string();
+    let sdist = sdist_path.to_string_lossy().to_string();
+
+    let args = [
+        "-m",
+        "pip",
+        "wheel",
+        "--no-deps",
+        "--no-index",
+        "--find-links",
+        &cached_dir,
+        "--wheel-dir",
+        &cached_dir,
+        &sdist,
+    ];
+
+    let output = venv_python_cmd(installer, &args)?;
+    if output.status.success() {
+        log::info!("源码包{}构建完成", sdist);
+        return Ok(());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    log::error!("构建{}失败:\n{}\n{}", sdist, stdout, stderr);
+
+    let file_name = sdist_path.file_name().unwrap().to_string_lossy();
+    let compiler_missing = COMPILER_MISSING_HINTS
+        .iter()
+        .find(|hint| stdout.contains(*hint) || stderr.contains(*hint));
+
+    match compiler_missing {
+        Some(hint) => {
+            let (compiler, advice) = compiler_advice(hint, installer.target());
+            bail!(
+                "{}没有适合本机的wheel，从源码构建需要{}（{}）。\
+                {}，或在需求文件中改用有wheel的版本",
+                file_name,
+                compiler,
+                hint,
+                advice
+            )
+        }
+        None => {
+            if let Some(message) = missing_build_requirement(&file_name, &output_lines(&output)) {
+                bail!("{}", message);
+            }
+            bail!("从源码包{}构建wheel错误: {}", file_name, stderr)
+        }
+    }
+}
+
+/// 按构建输出中的提示和目标三元组给出缺少的编译器及安装建议
+fn compiler_advice(hint: &str, target: &str) -> (&'static str, &'static str) {
+    if hint.contains("Rust") {
+        ("Rust编译器", "请按https://rustup.rs安装Rust工具链后重试")
+    } else if target.contains("windows") {
+        ("C/C++编译器", "请安装Microsoft C++生成工具后重试")
+    } else if target.contains("apple") {
+        (
+            "C/C++编译器",
+            "请执行xcode-select --install安装Xcode命令行工具后重试",
+        )
+    } else {
+        (
+            "C/C++编译器",
+            "请安装gcc等编译工具（如Debian/Ubuntu的build-essential、Fedora的gcc-c++）后重试",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_sdist(dir: &Path, pyproject: Option<&str>) -> PathBuf {
+        let path = dir.join("demo-1.0.tar.gz");
+        let file = std::fs::File::create(&path).unwrap();
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+
+        let mut files = vec![(
+            "demo-1.0/setup.py",
+            "from setuptools import setup\nsetup()\n",
+        )];
+        if let Some(pyproject) = pyproject {
+            files.push(("demo-1.0/pyproject.toml", pyproject));
+        }
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        path
+    }
+
+    #[test]
+    fn build_system_defaults_to_setuptools_legacy_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let sdist = write_sdist(dir.path(), None);
+
+        let build_system = read_build_system(&sdist).unwrap();
+        let names = build_system
+            .requires
+            .iter()
+            .map(|requirement| requirement.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["setuptools", "wheel"]);
+        assert_eq!(build_system.backend, "setuptools.build_meta:__legacy__");
+        assert!(build_system.backend_path.is_empty());
+    }
+
+    #[test]
+    fn build_system_reads_declared_backend_and_backend_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let sdist = write_sdist(
+            dir.path(),
+            Some(
+                "[build-system]\n\
+                requires = [\"flit_core>=3.2\"]\n\
+                build-backend = \"backend:hooks\"\n\
+                backend-path = [\"_build\"]\n",
+            ),
+        );
+
+        let build_system = read_build_system(&sdist).unwrap();
+        assert_eq!(build_system.requires.len(), 1);
+        assert_eq!(build_system.requires[0].name, "flit_core");
+        assert_eq!(build_system.backend, "backend:hooks");
+        assert_eq!(build_system.backend_path, vec!["_build"]);
+    }
+
+    #[test]
+    fn missing_build_requirement_is_reported_by_name() {
+        let lines = include_str!("../../testdata/pip-wheel-missing-build-requires.txt")
+            .lines()
+            .map(|line| line.to_string())
+            .collect::<Vec<String>>();
+
+        let message = missing_build_requirement("demo-1.0.tar.gz", &lines).unwrap();
+        assert!(message.contains("缺少构建依赖flit_core"), "{}", message);
+    }
+
+    #[test]
+    fn compiler_errors_are_not_reported_as_missing_build_requirements() {
+        let lines = vec![
+            "  error: command 'gcc' failed: No such file or directory".to_string(),
+            "  ERROR: Failed building wheel for demo".to_string(),
+        ];
+
+        assert_eq!(missing_build_requirement("demo-1.0.tar.gz", &lines), None);
+    }
+}
@@ -0,0 +1,518 @@
+use anyhow::{bail, Result};
+
+/// 按目标Python版本和目标三元组计算的平台标签及PEP 425兼容标签
+#[derive(Debug, Clone)]
+pub struct PlatformTags {
+    /// 与`sysconfig.get_platform()`相同，`.`和`-`替换为`_`，如`win_amd64`
+    pub platform_tag: String,
+    /// 从最适合到最不适合排列的`{python}-{abi}-{platform}`，与pip的`get_supported()`顺序一致
+    pub supported: Vec<String>,
+}
+
+/// python-build-standalone的发行包在macOS上的最低系统版本
+const MACOS_X86_64_DEPLOYMENT_TARGET: (u32, u32) = (10, 9);
+const MACOS_ARM64_DEPLOYMENT_TARGET: (u32, u32) = (11, 0);
+
+/// python-build-standalone的Linux发行包要求的最低glibc版本
+const MIN_GLIBC_VERSION: (u32, u32) = (2, 17);
+
+/// 无法获取本机musl版本时假定的版本
+const DEFAULT_MUSL_VERSION: (u32, u32) = (1, 2);
+
+/// 不启动Python，由版本号（如`3.11`）和目标三元组计算CPython支持的标签
+pub fn compute_platform_tags(python_version: &str, target: &str) -> Result<PlatformTags> {
+    let (major, minor, _) = parse_python_version(python_version)?;
+    let (platform_tag, platforms) = target_platforms(target)?;
+
+    Ok(PlatformTags {
+        platform_tag,
+        supported: supported_tags(major, minor, &[], &platforms),
+    })
+}
+
+/// 与pip的`--platform`、`--python-version`和`--abi`相同，按明确给出的目标计算支持的标签，
+/// 供为其它平台或Python版本下载程序包
+#[derive(Debug, Clone)]
+pub struct CustomTarget {
+    python_version: String,
+    python_version_full: String,
+    platforms: Vec<String>,
+    abis: Vec<String>,
+}
+
+impl CustomTarget {
+    /// python_version为`3.8`或`3.8.10`，未给出micro版本时按`3.8.0`
+    pub fn new(python_version: &str, platforms: Vec<String>, abis: Vec<String>) -> Result<Self> {
+        let (major, minor, micro) = parse_python_version(python_version)?;
+        if platforms.is_empty() {
+            bail!("没有给出目标平台标签");
+        }
+
+        Ok(CustomTarget {
+            python_version: format!("{}.{}", major, minor),
+            python_version_full: format!("{}.{}.{}", major, minor, micro.unwrap_or(0)),
+            platforms,
+            abis,
+        })
+    }
+
+    /// 目标Python版本，如`3.8`
+    pub fn python_version(&self) -> &str {
+        &self.python_version
+    }
+
+    /// 目标Python的完整版本，如`3.8.10`，用于推断标记环境中的`python_full_version`
+    pub fn python_version_full(&self) -> &str {
+        &self.python_version_full
+    }
+
+    /// 第一个平台标签对应的目标三元组，用于推断标记环境
+    pub fn target_triple(&self) -> Result<&'static str> {
+        let platform = &self.platforms[0];
+        let arch = platform_arch(platform);
+
+        let triple = if platform.starts_with("win") {
+            match platform.as_str() {
+                "win_amd64" => "x86_64-pc-windows-msvc",
+                "win32" => "i686-pc-windows-msvc",
+                "win_arm64" => "aarch64-pc-windows-msvc",
+                _ => bail!("不支持的目标平台标签: {}", platform),
+            }
+        } else if platform.starts_with("macosx_") {
+            match arch {
+                "x86_64" => "x86_64-apple-darwin",
+                "arm64" => "aarch64-apple-darwin",
+                _ => bail!("不支持的目标平台标签: {}", platform),
+            }
+        } else if platform.starts_with("musllinux_") {
+            match arch {
+                "x86_64" => "x86_64-unknown-linux-musl",
+                "aarch64" => "aarch64-unknown-linux-musl",
+                _ => bail!("不支持的目标平台标签: {}", platform),
+            }
+        } else if platform.starts_with("manylinux") || platform.starts_with("linux_") {
+            match arch {
+                "x86_64" => "x86_64-unknown-linux-gnu",
+                "aarch64" => "aarch64-unknown-linux-gnu",
+                "i686" => "i686-unknown-linux-gnu",
+                _ => bail!("不支持的目标平台标签: {}", platform),
+            }
+        } else {
+            bail!("不支持的目标平台标签: {}", platform)
+        };
+
+        Ok(triple)
+    }
+
+    /// 与pip相同，manylinux、musllinux和macosx的平台标签扩展为同样兼容的较早版本
+    pub fn platform_tags(&self) -> Result<PlatformTags> {
+        let (major, minor, _) = parse_python_version(&self.python_version)?;
+
+        let mut platforms: Vec<String> = Vec::new();
+        for platform in &self.platforms {
+            for compatible in expand_platform(platform) {
+                if !platforms.contains(&compatible) {
+                    platforms.push(compatible);
+                }
+            }
+        }
+
+        Ok(PlatformTags {
+            platform_tag: self.platforms[0].clone(),
+            supported: supported_tags(major, minor, &self.abis, &platforms),
+        })
+    }
+}
+
+/// `major.minor`或`major.minor.micro`格式的Python版本号
+fn parse_python_version(python_version: &str) -> Result<(u32, u32, Option<u32>)> {
+    let parts = python_version
+        .split('.')
+        .map(|part| part.parse::<u32>())
+        .collect::<Vec<_>>();
+    let (major, minor, micro) = match parts[..] {
+        [Ok(major), Ok(minor)] => (major, minor, None),
+        [Ok(major), Ok(minor), Ok(micro)] => (major, minor, Some(micro)),
+        _ => bail!("Python版本号{}格式错误", python_version),
+    };
+    if major != 3 {
+        bail!("不支持的Python版本: {}", python_version);
+    }
+
+    Ok((major, minor, micro))
+}
+
+/// 平台标签末尾的CPU架构，如`manylinux_2_17_x86_64`中的x86_64
+fn platform_arch(platform: &str) -> &str {
+    for arch in [
+        "x86_64", "i686", "aarch64", "arm64", "armv7l", "ppc64le", "s390x",
+    ] {
+        if platform.ends_with(&format!("_{}", arch)) {
+            return arch;
+        }
+    }
+
+    platform.rsplit('_').next().unwrap_or_default()
+}
+
+/// 明确给出的平台标签及与其兼容的较早版本的平台标签
+fn expand_platform(platform: &str) -> Vec<String> {
+    let arch = platform_arch(platform);
+    let version = platform
+        .strip_suffix(arch)
+        .and_then(|s| s.strip_suffix('_'))
+        .unwrap_or(platform);
+
+    let numbers = |prefix: &str| -> Option<(u32, u32)> {
+        let (major, minor) = version.strip_prefix(prefix)?.split_once('_')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    };
+
+    let legacy_glibc_version = match version {
+        "manylinux2014" => Some((2, 17)),
+        "manylinux2010" => Some((2, 12)),
+        "manylinux1" => Some((2, 5)),
+        _ => numbers("manylinux_"),
+    };
+    if let Some(glibc_version) = legacy_glibc_version {
+        return manylinux_platforms(glibc_version, arch);
+    }
+
+    if let Some(musl_version) = numbers("musllinux_") {
+        return musllinux_platforms(musl_version, arch);
+    }
+
+    if let Some(macos_version) = numbers("macosx_") {
+        return mac_platforms(macos_version, arch);
+    }
+
+    vec![platform.to_string()]
+}
+
+/// 同packaging.tags中的cpython_tags和compatible_tags，未给出abis时为`cp3X`
+fn supported_tags(major: u32, minor: u32, abis: &[String], platforms: &[String]) -> Vec<String> {
+    let interpreter = format!("cp{}{}", major, minor);
+    let mut tags = Vec::new();
+
+    let abis = if abis.is_empty() {
+        vec![interpreter.clone()]
+    } else {
+        // abi3和none总是按下面的顺序加入
+        abis.iter()
+            .filter(|abi| *abi != "abi3" && *abi != "none")
+            .cloned()
+            .collect()
+    };
+
+    for abi in &abis {
+        for platform in platforms {
+            tags.push(format!("{interpreter}-{abi}-{platform}"));
+        }
+    }
+    for platform in platforms {
+        tags.push(format!("{interpreter}-abi3-{platform}"));
+    }
+    for platform in platforms {
+        tags.push(format!("{interpreter}-none-{platform}"));
+    }
+
+    // 为较早版本构建的abi3 wheel，abi3始于3.2
+    for older_minor in (2..minor).rev() {
+        for platform in platforms {
+            tags.push(format!("cp{}{}-abi3-{}", major, older_minor, platform));
+        }
+    }
+
+    // 纯Python的py3x、py3等
+    let mut py_versions = vec![format!("py{}{}", major, minor), format!("py{}", major)];
+    py_versions.extend((0..minor).rev().map(|m| format!("py{}{}", major, m)));
+
+    for version in &py_versions {
+        for platform in platforms {
+            tags.push(format!("{version}-none-{platform}"));
+        }
+    }
+
+    tags.push(format!("{interpreter}-none-any"));
+    for version in &py_versions {
+        tags.push(format!("{version}-none-any"));
+    }
+
+    tags
+}
+
+/// 目标三元组对应的平台标签，以及从最适合到最不适合排列的兼容平台
+fn target_platforms(target: &str) -> Result<(String, Vec<String>)> {
+    let arch = target.split('-').next().unwrap_or_default();
+
+    if target.ends_with("-windows-msvc") || target.ends_with("-windows-gnu") {
+        let platform = match arch {
+            "x86_64" => "win_amd64",
+            "i686" | "i586" | "x86" => "win32",
+            "aarch64" => "win_arm64",
+            _ => bail!("不支持的目标平台: {}", target),
+        };
+        return Ok((platform.to_string(), vec![platform.to_string()]));
+    }
+
+    if target.contains("-apple-darwin") {
+        let (arch, deployment_target) = match arch {
+            "x86_64" => ("x86_64", MACOS_X86_64_DEPLOYMENT_TARGET),
+            "aarch64" | "arm64" => ("arm64", MACOS_ARM64_DEPLOYMENT_TARGET),
+            _ => bail!("不支持的目标平台: {}", target),
+        };
+
+        // 兼容标签取决于运行的系统版本，不在本机运行时按发行包的最低版本
+        let macos_version = host_macos_version().unwrap_or(deployment_target);
+        let platform_tag = format!(
+            "macosx_{}_{}_{}",
+            deployment_target.0, deployment_target.1, arch
+        );
+        return Ok((platform_tag, mac_platforms(macos_version, arch)));
+    }
+
+    if target.contains("-linux-") {
+        let arch = match arch {
+            "i686" | "i586" => "i686",
+            "armv7" => "armv7l",
+            arch => arch,
+        };
+        let linux_platform = format!("linux_{}", arch);
+
+        let mut platforms = if target.ends_with("-musl") {
+            let musl_version = host_musl_version().unwrap_or(DEFAULT_MUSL_VERSION);
+            musllinux_platforms(musl_version, arch)
+        } else {
+            let glibc_version = host_glibc_version().unwrap_or(MIN_GLIBC_VERSION);
+            manylinux_platforms(glibc_version, arch)
+        };
+        platforms.push(linux_platform.clone());
+
+        return Ok((linux_platform, platforms));
+    }
+
+    bail!("不支持的目标平台: {}", target)
+}
+
+/// 同packaging.tags中的mac_platforms
+fn mac_platforms(version: (u32, u32), arch: &str) -> Vec<String> {
+    let mut platforms = Vec::new();
+
+    if version.0 == 10 {
+        for minor in (0..=version.1).rev() {
+            for format in mac_binary_formats((10, minor), arch) {
+                platforms.push(format!("macosx_10_{}_{}", minor, format));
+            }
+        }
+    } else {
+        for major in (11..=version.0).rev() {
+            for format in mac_binary_formats((major, 0), arch) {
+                platforms.push(format!("macosx_{}_0_{}", major, format));
+            }
+        }
+
+        // macOS 11以后仍可使用为10.x构建的wheel
+        for minor in (4..=16).rev() {
+            if arch == "x86_64" {
+                for format in mac_binary_formats((10, minor), arch) {
+                    platforms.push(format!("macosx_10_{}_{}", minor, format));
+                }
+            } else {
+                platforms.push(format!("macosx_10_{}_universal2", minor));
+            }
+        }
+    }
+
+    platforms
+}
+
+fn mac_binary_formats(version: (u32, u32), arch: &str) -> Vec<&'static str> {
+    match arch {
+        "x86_64" if version < (10, 4) => vec![],
+        "x86_64" => vec![
+            "x86_64",
+            "intel",
+            "fat64",
+            "fat32",
+            "universal2",
+            "universal",
+        ],
+        _ => vec!["arm64", "universal2"],
+    }
+}
+
+/// 同packaging中的_manylinux.platform_tags，按glibc版本从高到低，
+/// 并在对应版本后插入manylinux2014、manylinux2010和manylinux1的旧名称
+fn manylinux_platforms(glibc_version: (u32, u32), arch: &str) -> Vec<String> {
+    let mut platforms = Vec::new();
+
+    // x86_64和i686最早有glibc 2.5的manylinux1，其它架构始于manylinux2014
+    let min_minor = if arch == "x86_64" || arch == "i686" {
+        5
+    } else {
+        17
+    };
+
+    for minor in (min_minor..=glibc_version.1).rev() {
+        platforms.push(format!("manylinux_2_{}_{}", minor, arch));
+        let legacy = match minor {
+            17 => Some("manylinux2014"),
+            12 if min_minor <= 12 => Some("manylinux2010"),
+            5 if min_minor <= 5 => Some("manylinux1"),
+            _ => None,
+        };
+        if let Some(legacy) = legacy {
+            platforms.push(format!("{}_{}", legacy, arch));
+        }
+    }
+
+    platforms
+}
+
+fn musllinux_platforms(musl_version: (u32, u32), arch: &str) -> Vec<String> {
+    (0..=musl_version.1)
+        .rev()
+        .map(|minor| format!("musllinux_{}_{}_{}", musl_version.0, minor, arch))
+        .collect()
+}
+
+/// 本机的macOS版本，由`sw_vers -productVersion`获取
+#[cfg(target_os = "macos")]
+fn host_macos_version() -> Option<(u32, u32)> {
+    let output = std::process::Command::new("sw_vers")
+        .arg("-productVersion")
+        .output()
+        .ok()?;
+    parse_major_minor(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn host_macos_version() -> Option<(u32, u32)> {
+    None
+}
+
+/// 本机的glibc版本，由`ldd --version`第一行末尾的版本号获取
+#[cfg(target_os = "linux")]
+fn host_glibc_version() -> Option<(u32, u32)> {
+    let output = std::process::Command::new("ldd")
+        .arg("--version")
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stdout);
+    let first_line = text.lines().next()?;
+    if !first_line.to_lowercase().contains("glibc") && !first_line.contains("GNU libc") {
+        return None;
+    }
+    parse_major_minor(first_line.split_whitespace().last()?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn host_glibc_version() -> Option<(u32, u32)> {
+    None
+}
+
+/// 本机的musl版本，musl的ldd在stderr中输出`Version 1.2.4`
+#[cfg(target_os = "linux")]
+fn host_musl_version() -> Option<(u32, u32)> {
+    let output = std::process::Command::new("ldd").output().ok()?;
+    let text = String::from_utf8_lossy(&output.stderr);
+    let version = text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Version "))?;
+    parse_major_minor(version)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn host_musl_version() -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn parse_major_minor(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().unwrap_or("0").parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn custom_target(python_version: &str, platform: &str) -> Result<CustomTarget> {
+        CustomTarget::new(python_version, vec![platform.to_string()], vec![])
+    }
+
+    /// packaging.tags（24.1）的输出，manylinux按glibc 2.17
+    fn packaging_tags(section: &str, key: &str) -> Vec<String> {
+        let data: serde_json::Value =
+            serde_json::from_str(include_str!("../../testdata/packaging-tags.json")).unwrap();
+        serde_json::from_value(data[section][key].clone()).unwrap()
+    }
+
+    #[test]
+    fn cp311_win_amd64_tags_match_packaging() {
+        let expected = packaging_tags("supported", "cp311-win_amd64");
+
+        let tags = compute_platform_tags("3.11", "x86_64-pc-windows-msvc").unwrap();
+        assert_eq!(tags.platform_tag, "win_amd64");
+        assert_eq!(tags.supported, expected);
+
+        let target = custom_target("3.11", "win_amd64").unwrap();
+        assert_eq!(target.platform_tags().unwrap().supported, expected);
+    }
+
+    #[test]
+    fn manylinux2014_expands_like_packaging() {
+        for arch in ["x86_64", "aarch64"] {
+            let platform = format!("manylinux2014_{arch}");
+            assert_eq!(
+                expand_platform(&platform),
+                packaging_tags("platforms", &platform)
+            );
+        }
+
+        // pip的--platform只加上manylinux2010和manylinux1，这里同packaging按glibc版本扩展
+        let target = custom_target("3.8", "manylinux2014_x86_64").unwrap();
+        assert_eq!(
+            target.platform_tags().unwrap().supported,
+            packaging_tags("supported", "cp38-manylinux2014_x86_64")
+        );
+    }
+
+    #[test]
+    fn macos_11_platforms_match_packaging() {
+        for arch in ["arm64", "x86_64"] {
+            let platform = format!("macosx_11_0_{arch}");
+            let expected = packaging_tags("platforms", &platform);
+            assert_eq!(mac_platforms((11, 0), arch), expected);
+            assert_eq!(expand_platform(&platform), expected);
+        }
+    }
+
+    #[test]
+    fn custom_target_python_version_without_micro_defaults_to_zero() {
+        let target = custom_target("3.8", "manylinux2014_x86_64").unwrap();
+        assert_eq!(target.python_version(), "3.8");
+        assert_eq!(target.python_version_full(), "3.8.0");
+        assert_eq!(target.target_triple().unwrap(), "x86_64-unknown-linux-gnu");
+
+        let target = custom_target("3.12.1", "musllinux_1_2_aarch64").unwrap();
+        assert_eq!(target.python_version(), "3.12");
+        assert_eq!(target.python_version_full(), "3.12.1");
+        assert_eq!(
+            target.target_triple().unwrap(),
+            "aarch64-unknown-linux-musl"
+        );
+    }
+
+    #[test]
+    fn custom_target_rejects_malformed_python_version() {
+        for python_version in ["3", "3.x", "3.8.1.2", "2.7"] {
+            assert!(
+                custom_target(python_version, "win_amd64").is_err(),
+                "{python_version}"
+            );
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! 单元测试共用的安装参数、状态输出和本机HTTP服务
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use super::super::status::{DownloadingStats, StatusUpdate};
+use super::installer::Installer;
+use super::network::NetworkOverrides;
+
+/// 不输出任何信息的状态更新
+pub struct NoStatus;
+
+impl StatusUpdate for NoStatus {
+    fn message(&self, _msg: &str) {}
+
+    fn update_downloading(&self, _status: &DownloadingStats) {}
+}
+
+/// 安装到target_dir的安装参数，不采用环境变量和系统的代理设置
+pub fn test_installer(target_dir: &Path) -> Installer {
+    let overrides = NetworkOverrides {
+        trust_env: Some(false),
+        ..Default::default()
+    };
+
+    Installer::with_network(target_dir.to_path_buf(), None, &overrides).unwrap()
+}
+
+/// 本机HTTP服务，每个连接只应答一个请求，应答由handler按请求头生成
+pub struct TestServer {
+    base_url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub fn start(handler: impl Fn(&str) -> Vec<u8> + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    break;
+                };
+
+                let head = read_request_head(&mut stream);
+                let response = handler(&head);
+                received.lock().unwrap().push(head);
+
+                let _ = stream.write_all(&response);
+                let _ = stream.flush();
+            }
+        });
+
+        TestServer { base_url, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// 已收到的请求头，统一为小写
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// 组成HTTP应答，加上Content-Length并在应答后关闭连接
+pub fn http_response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {status}\r\n");
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str(&format!("content-length: {}\r\n", body.len()));
+    response.push_str("connection: close\r\n\r\n");
+
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+fn read_request_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut byte) {
+            Ok(1) => head.push(byte[0]),
+            _ => break,
+        }
+    }
+
+    String::from_utf8_lossy(&head).to_ascii_lowercase()
+}
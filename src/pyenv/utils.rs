@@ -2,7 +2,8 @@ use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
 use pep440_rs::Version;
 use regex::Regex;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 pub fn parse_version(version: &str) -> Result<Version> {
     use std::str::FromStr;
@@ -43,6 +44,75 @@ pub fn make_python_bin_path(pydist_dir: &PathBuf) -> PathBuf {
     pybin_path
 }
 
+/// 虚拟环境中可执行程序目录，Windows下为Scripts，其它平台为bin
+pub fn make_venv_bin_dir(venv_dir: &Path) -> PathBuf {
+    #[cfg(windows)]
+    {
+        venv_dir.join("Scripts")
+    }
+    #[cfg(not(windows))]
+    {
+        venv_dir.join("bin")
+    }
+}
+
+pub fn make_venv_python_path(venv_dir: &Path) -> PathBuf {
+    let bin_dir = make_venv_bin_dir(venv_dir);
+
+    #[cfg(windows)]
+    {
+        bin_dir.join("python.exe")
+    }
+    #[cfg(not(windows))]
+    {
+        bin_dir.join("python")
+    }
+}
+
+/// 虚拟环境中site-packages目录，其它平台下路径中包含Python版本，如lib/python3.11/site-packages
+pub fn make_venv_site_packages_dir(venv_dir: &Path, python_version: &str) -> PathBuf {
+    #[cfg(windows)]
+    {
+        let _ = python_version;
+        venv_dir.join("Lib").join("site-packages")
+    }
+    #[cfg(not(windows))]
+    {
+        venv_dir
+            .join("lib")
+            .join(format!("python{python_version}"))
+            .join("site-packages")
+    }
+}
+
+/// 在Windows下运行子进程时不弹出终端窗口
+pub fn hide_console_window(cmd: &mut Command) -> &mut Command {
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    cmd
+}
+
+/// 当前程序所运行平台的目标三元组，对应python-build-standalone发行包的命名
+pub fn current_target_triple() -> Result<&'static str> {
+    use std::env::consts::{ARCH, OS};
+
+    Ok(match (OS, ARCH) {
+        ("windows", "x86_64") => "x86_64-pc-windows-msvc",
+        ("windows", "x86") => "i686-pc-windows-msvc",
+        ("linux", "x86_64") => "x86_64-unknown-linux-gnu",
+        ("linux", "aarch64") => "aarch64-unknown-linux-gnu",
+        ("macos", "x86_64") => "x86_64-apple-darwin",
+        ("macos", "aarch64") => "aarch64-apple-darwin",
+        _ => bail!("不支持的系统平台: {}-{}", OS, ARCH),
+    })
+}
+
 pub fn split_filename_extension(file_name: &str) -> Result<(&str, &str)> {
     let Some(mut sep) = file_name.rfind('.') else {
         bail!("no extension: {}", file_name);
@@ -58,6 +128,7 @@ pub fn split_filename_extension(file_name: &str) -> Result<(&str, &str)> {
     Ok((filename_base, filename_ext))
 }
 
+#[cfg(windows)]
 pub fn get_windows_major_versoin() -> Result<u8> {
     use winreg::enums::HKEY_LOCAL_MACHINE;
     use winreg::RegKey;
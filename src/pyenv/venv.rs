@@ -1,12 +1,18 @@
 use anyhow::{bail, Result};
+use pep508_rs::MarkerEnvironment;
 use std::fs::File;
+use std::path::PathBuf;
 use std::process::{Command, Stdio};
 
 use super::super::status::StatusUpdate;
-use super::archive::{checksum, unpack_archive};
-use super::download::download;
+use super::archive::unpack_archive;
+use super::bundle::Bundle;
+use super::checksum::checksum_file;
+use super::download::download_file;
+use super::markers::{marker_env_for_target, marker_env_from_json};
+use super::retry::with_retry;
 use super::utils::make_python_bin_path;
-use super::utils::split_filename_extension;
+use super::utils::{hide_console_window, make_venv_bin_dir, split_filename_extension};
 use super::Installer;
 
 pub async fn ensure_python_venv(
@@ -22,16 +28,56 @@ pub async fn ensure_python_venv(
 
 static PLATFORM_INFO_SCRIPT: &str = r#"
 import json
-import sysconfig
-from pip._internal.utils.compatibility_tags import get_supported
+import os
+import platform
+import sys
+
+def format_full_version(info):
+    version = "{0.major}.{0.minor}.{0.micro}".format(info)
+    if info.releaselevel != "final":
+        version += info.releaselevel[0] + str(info.serial)
+    return version
 
 print(json.dumps({
-    "platform_tag": sysconfig.get_platform().replace('.', '-').replace('-', '_'),
-    "support_tags": [str(t) for t in get_supported()]
+    "markers": {
+        "implementation_name": sys.implementation.name,
+        "implementation_version": format_full_version(sys.implementation.version),
+        "os_name": os.name,
+        "platform_machine": platform.machine(),
+        "platform_python_implementation": platform.python_implementation(),
+        "platform_release": platform.release(),
+        "platform_system": platform.system(),
+        "platform_version": platform.version(),
+        "python_full_version": platform.python_version(),
+        "python_version": ".".join(platform.python_version_tuple()[:2]),
+        "sys_platform": sys.platform,
+    }
 }))
 "#;
 
+/// 设置平台标签、支持的wheel标签和PEP 508标记环境。
+///
+/// 标签由目标Python版本和目标三元组计算，不需要虚拟环境；虚拟环境已创建时由其中的Python
+/// 获取标记环境，否则按目标平台推断，因此可以在创建虚拟环境之前下载程序包
 pub fn set_platform_info(installer: &mut Installer) -> Result<()> {
+    if installer.platform_tag.is_none() || installer.support_tags_map.is_empty() {
+        installer.set_platform_tags()?;
+    }
+
+    let marker_env = if installer.venv_python_path.is_file() {
+        venv_marker_env(installer)?
+    } else {
+        log::info!("虚拟环境尚未创建，按目标平台推断PEP 508标记环境");
+        marker_env_for_target(&installer.python_version_full, installer.target())?
+    };
+
+    log::info!("PEP 508标记环境: {:?}", marker_env);
+    installer.marker_env = Some(marker_env);
+
+    Ok(())
+}
+
+fn venv_marker_env(installer: &Installer) -> Result<MarkerEnvironment> {
     let tmp_dir = tempfile::tempdir()?;
 
     let script_file = tmp_dir.path().join("platform_info.py");
@@ -45,34 +91,20 @@ pub fn set_platform_info(installer: &mut Installer) -> Result<()> {
 
     log::info!("临时获取平台信息Python程序脚本: {}", &script_file.display());
 
-    let output = Command::new(&installer.venv_python_path)
-        .creation_flags(CREATE_NO_WINDOW)
+    let output = match hide_console_window(&mut Command::new(&installer.venv_python_path))
         .arg(&script_file)
         .stdout(Stdio::piped())
         .output()
-        .expect("无法执行Python脚本");
+    {
+        Ok(output) => output,
+        Err(err) => bail!("无法执行Python脚本: {}", err),
+    };
 
     let output = String::from_utf8_lossy(&output.stdout);
 
     let json_msg: serde_json::Value = serde_json::from_str(&output)?;
 
-    installer.platform_tag = Some(json_msg["platform_tag"].as_str().unwrap().to_string());
-    log::info!("系统平台标签: {}", json_msg["platform_tag"]);
-
-    let support_tags_map = &mut installer.support_tags_map;
-    for (i, tag) in json_msg["support_tags"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|v| v.as_str().unwrap())
-        .enumerate()
-    {
-        support_tags_map.insert(tag.to_string(), i as u32);
-    }
-
-    log::info!("系统支持的平台标签: {}", json_msg["support_tags"]);
-
-    Ok(())
+    marker_env_from_json(&json_msg["markers"])
 }
 
 pub async fn ensure_python_dist(
@@ -93,37 +125,131 @@ pub async fn ensure_python_dist(
         bail!("创建目录{}失败: {}", pydist_dir.display(), _err)
     }
 
-    let cpython_source = &installer.pydist_source;
+    let cpython_source = installer.pydist_source()?;
 
-    let Ok((_file_base, file_ext)) = split_filename_extension(cpython_source.url()) else {
-        bail!("地址文件解析扩展名错误: {}", cpython_source.url())
+    let archive_path = match installer.bundle() {
+        Some(bundle) => extract_bundled_python_dist(installer, bundle, collector)?,
+        None => download_python_dist(installer, collector).await?,
     };
 
-    collector.message(&format!("下载CPython-{pyver}安装包",));
-
-    let buffer = download(
-        installer,
-        collector,
-        cpython_source.url(),
-        &format!("下载CPython-{}安装包", pyver),
-    )
-    .await?;
-
-    if !checksum("sha256", &buffer, cpython_source.checksum())? {
-        bail!("hash check of {} failed", cpython_source.url())
-    }
+    let file_name = archive_path
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let Ok((_file_base, file_ext)) = split_filename_extension(&file_name) else {
+        bail!("地址文件解析扩展名错误: {}", file_name)
+    };
 
     collector.message(format!("解压CPython-{}安装包", pyver).as_str());
-    unpack_archive(file_ext, &buffer, pydist_dir)?;
+    unpack_archive(
+        file_ext,
+        &archive_path,
+        pydist_dir,
+        cpython_source.strip_components(),
+    )?;
 
     collector.message(format!("CPython-{}安装完成", installer.python_version_full).as_str());
 
     Ok(())
 }
 
-use std::os::windows::process::CommandExt;
+/// 下载CPython安装包到程序包缓存目录并校验，已下载时直接返回其路径
+pub(super) async fn download_python_dist(
+    installer: &Installer,
+    collector: &impl StatusUpdate,
+) -> Result<PathBuf> {
+    let cpython_source = installer.pydist_source()?;
+    let pyver = installer.python_version_full.as_str();
+
+    let hexcode = match cpython_source.checksum() {
+        Some(hexcode) => hexcode.to_string(),
+        None => fetch_dist_checksum(installer, cpython_source.checksum_url()).await?,
+    };
+
+    // 安装包下载到程序包缓存目录，安装完成后与其它程序包一起删除
+    let cached_dir = &installer.cached_packages_dir;
+    if let Err(_err) = std::fs::create_dir_all(cached_dir) {
+        bail!("创建目录{}失败: {}", cached_dir.display(), _err)
+    }
+
+    let Some((_, file_name)) = cpython_source.url().rsplit_once('/') else {
+        bail!("地址文件解析文件名错误: {}", cpython_source.url())
+    };
+    let archive_path = cached_dir.join(file_name);
+
+    if archive_path.is_file() && checksum_file("sha256", &archive_path, &hexcode)? {
+        collector.message(&format!("CPython-{pyver}安装包已下载"));
+    } else {
+        collector.message(&format!("下载CPython-{pyver}安装包",));
+
+        let title = format!("下载CPython-{}安装包", pyver);
+        with_retry(installer.retry_policy(), collector, &title, || {
+            download_file(
+                installer,
+                collector,
+                cpython_source.url(),
+                &title,
+                &archive_path,
+                Some(("sha256", &hexcode)),
+            )
+        })
+        .await?;
+    }
+
+    Ok(archive_path)
+}
+
+/// 从离线安装包中解出CPython安装包到程序包缓存目录，按清单校验sha256
+fn extract_bundled_python_dist(
+    installer: &Installer,
+    bundle: &Bundle,
+    collector: &impl StatusUpdate,
+) -> Result<PathBuf> {
+    let cached_dir = &installer.cached_packages_dir;
+    if let Err(_err) = std::fs::create_dir_all(cached_dir) {
+        bail!("创建目录{}失败: {}", cached_dir.display(), _err)
+    }
+
+    collector.message(&format!(
+        "从离线安装包解出CPython-{}安装包",
+        installer.python_version_full
+    ));
+
+    let archive_path = cached_dir.join(bundle.cpython_file_name()?);
+    bundle.extract_cpython(&archive_path)?;
+
+    Ok(archive_path)
+}
+
+/// 获取发行包同名的.sha256文件，文件内容为`<hexcode>`或`<hexcode>  <filename>`
+async fn fetch_dist_checksum(installer: &Installer, checksum_url: Option<&str>) -> Result<String> {
+    let Some(checksum_url) = checksum_url else {
+        bail!("[[cpython]]配置中无checksum或checksum_url")
+    };
+
+    let read_timeout = installer.read_timeout();
+    let request = installer.client.get(checksum_url).send();
+    let resp = match tokio::time::timeout(read_timeout, request).await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(err)) => bail!("获取{}失败: {}", checksum_url, err),
+        Err(_) => bail!("获取{}超过{}秒无响应", checksum_url, read_timeout.as_secs()),
+    };
 
-const CREATE_NO_WINDOW: u32 = 0x08000000;
+    if !resp.status().is_success() {
+        bail!("获取{}失败: HTTP状态码[{}]", checksum_url, resp.status().as_u16())
+    }
+
+    let text = match tokio::time::timeout(read_timeout, resp.text()).await {
+        Ok(text) => text?,
+        Err(_) => bail!("获取{}超过{}秒无响应", checksum_url, read_timeout.as_secs()),
+    };
+    let Some(hexcode) = text.split_whitespace().next() else {
+        bail!("{}中无checksum码", checksum_url)
+    };
+
+    Ok(hexcode.to_string())
+}
 
 pub async fn ensure_venv(installer: &Installer, status_updater: &impl StatusUpdate) -> Result<()> {
     let venv_dir = &installer.venv_dir;
@@ -140,10 +266,15 @@ pub async fn ensure_venv(installer: &Installer, status_updater: &impl StatusUpda
 
     // initialize the virtualenv
     let mut venv_cmd = Command::new(&python_bin);
-    venv_cmd.creation_flags(CREATE_NO_WINDOW);
+    hide_console_window(&mut venv_cmd);
     venv_cmd.arg("-mvenv");
+    #[cfg(not(windows))]
+    {
+        // Windows下由fix_patches修改activate.bat中的提示符
+        venv_cmd.arg("--prompt");
+        venv_cmd.arg("TGBA");
+    }
     venv_cmd.arg(&venv_dir);
-    
 
     let status = match venv_cmd.status() {
         Ok(status) => status,
@@ -170,35 +301,7 @@ pub async fn ensure_venv(installer: &Installer, status_updater: &impl StatusUpda
 use super::super::utils::detect_decode;
 
 pub fn venv_python_cmd(installer: &Installer, args: &[&str]) -> Result<std::process::Output> {
-    let python_bin = &installer.venv_python_path;
-
-    // 将venv/Script目录添加到环境变量PATH中
-    use std::env;
-    let venv_script_dir = installer.venv_dir.join("Scripts");
-    let path_env = if let Some(path) = env::var_os("PATH") {
-        let mut paths = std::env::split_paths(&path).collect::<Vec<_>>();
-        paths.insert(0, venv_script_dir);
-        env::join_paths(paths)?
-    } else {
-        let paths = vec![venv_script_dir];
-        env::join_paths(paths)?
-    };
-
-    let mut cmd = Command::new(&python_bin);
-    cmd.creation_flags(CREATE_NO_WINDOW);
-    cmd.env("PATH", path_env.to_string_lossy().as_ref());
-    cmd.env("VIRTUAL_ENV", installer.venv_dir.to_string_lossy().as_ref());
-    cmd.env_remove("PYTHONHOME");
-    for arg in args {
-        cmd.arg(arg);
-    }
-
-    let args_str = cmd
-        .get_args()
-        .map(|s| s.to_string_lossy().to_string())
-        .collect::<Vec<String>>()
-        .join(" ");
-    let prog_cmd = format!("{} {}", cmd.get_program().to_string_lossy(), args_str);
+    let (mut cmd, prog_cmd) = venv_python_command(installer, args)?;
 
     let output = match cmd.output() {
         Ok(output) => output,
@@ -233,3 +336,51 @@ pub fn venv_python_cmd(installer: &Installer, args: &[&str]) -> Result<std::proc
 
     Ok(output)
 }
+
+/// 启动虚拟环境中的Python，stdout和stderr以管道输出，供逐行读取
+pub fn venv_python_spawn(installer: &Installer, args: &[&str]) -> Result<std::process::Child> {
+    let (mut cmd, prog_cmd) = venv_python_command(installer, args)?;
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    log::info!("执行: CMD {}", prog_cmd);
+
+    match cmd.spawn() {
+        Ok(child) => Ok(child),
+        Err(err) => bail!("程序({})无法执行：{}", prog_cmd, err),
+    }
+}
+
+/// 在虚拟环境中运行Python的命令，返回命令及其文字形式（用于日志）
+fn venv_python_command(installer: &Installer, args: &[&str]) -> Result<(Command, String)> {
+    let python_bin = &installer.venv_python_path;
+
+    // 将venv/Script目录添加到环境变量PATH中
+    use std::env;
+    let venv_script_dir = make_venv_bin_dir(&installer.venv_dir);
+    let path_env = if let Some(path) = env::var_os("PATH") {
+        let mut paths = std::env::split_paths(&path).collect::<Vec<_>>();
+        paths.insert(0, venv_script_dir);
+        env::join_paths(paths)?
+    } else {
+        let paths = vec![venv_script_dir];
+        env::join_paths(paths)?
+    };
+
+    let mut cmd = Command::new(python_bin);
+    hide_console_window(&mut cmd);
+    cmd.env("PATH", path_env.to_string_lossy().as_ref());
+    cmd.env("VIRTUAL_ENV", installer.venv_dir.to_string_lossy().as_ref());
+    cmd.env_remove("PYTHONHOME");
+    for arg in args {
+        cmd.arg(arg);
+    }
+
+    let args_str = cmd
+        .get_args()
+        .map(|s| s.to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join(" ");
+    let prog_cmd = format!("{} {}", cmd.get_program().to_string_lossy(), args_str);
+
+    Ok((cmd, prog_cmd))
+}
@@ -0,0 +1,1195 @@
+use anyhow::{anyhow, bail, Result};
+use pep440_rs::Version;
+use pep508_rs::{Requirement, VersionOrUrl};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use super::super::errors::{InstallingError, InstallingErrorKind};
+use super::super::status::StatusUpdate;
+use super::installer::Installer;
+use super::link::parse_build_tag;
+use super::metadata::DistMetadata;
+use super::requirements::direct_url_cached_file;
+use super::utils::{canonicalize_name, make_venv_bin_dir, split_filename_extension};
+use super::venv::venv_python_cmd;
+
+/// 写入dist-info/INSTALLER的安装工具名称
+const INSTALLER_NAME: &str = "tgba-installer";
+
+/// 缓存目录中的wheel文件
+#[derive(Debug, Clone)]
+struct CachedWheel {
+    path: PathBuf,
+    file_name: String,
+    name: String, // 规范名称
+    version: Version,
+    build: Option<(u32, String)>, // 构建标签
+    tag_rank: u32,
+}
+
+/// 计划安装的wheel
+struct PlannedWheel {
+    wheel: CachedWheel,
+    metadata: DistMetadata,
+    extras: BTreeSet<String>,
+    direct_url: Option<String>,
+    requested: bool,
+}
+
+/// 不调用pip，直接把缓存目录中的wheel安装到虚拟环境的site-packages。
+///
+/// 从需求出发按wheel中METADATA的Requires-Dist补全依赖，逐个解压并写入RECORD、INSTALLER、
+/// REQUESTED和direct_url.json，生成命令行启动程序，最后可选地编译.pyc文件
+pub async fn install_wheels(
+    installer: &Installer,
+    status_updater: &impl StatusUpdate,
+    requirements: &[Requirement],
+) -> Result<(), InstallingError> {
+    let Some(marker_env) = installer.marker_env.as_ref() else {
+        return Err(InstallingError::other(
+            "未获取目标Python的标记环境，无法安装程序包",
+        ));
+    };
+
+    let site_packages = installer.site_packages_dir();
+    let cached_wheels = scan_cached_wheels(installer).map_err(InstallingError::other)?;
+    let installed = scan_installed_dists(&site_packages).map_err(InstallingError::other)?;
+
+    // 按需求及其依赖确定要安装的wheel
+    let mut planned: Vec<PlannedWheel> = Vec::new();
+    let mut queue: VecDeque<(Requirement, bool)> = requirements
+        .iter()
+        .map(|requirement| (requirement.clone(), true))
+        .collect();
+    let mut missing = Vec::new();
+
+    while let Some((requirement, requested)) = queue.pop_front() {
+        if let Some(marker) = &requirement.marker {
+            if !marker.evaluate(marker_env, &[]) {
+                continue;
+            }
+        }
+
+        let name = canonicalize_name(&requirement.name);
+        let extras = requirement
+            .extras
+            .iter()
+            .flatten()
+            .map(|extra| extra.to_lowercase())
+            .collect::<BTreeSet<String>>();
+
+        if let Some(plan) = planned.iter_mut().find(|plan| plan.wheel.name == name) {
+            if !matches_version(&requirement, &plan.wheel.version) {
+                log::warn!(
+                    "{}需要{}，与将要安装的{}不一致",
+                    requirement,
+                    name,
+                    plan.wheel.file_name
+                );
+            }
+            plan.requested |= requested;
+
+            // 新增的extras引入的依赖
+            let new_extras = extras
+                .difference(&plan.extras)
+                .cloned()
+                .collect::<Vec<String>>();
+            warn_missing_extras(&plan.metadata, &new_extras);
+            for extra in &new_extras {
+                for dep in plan.metadata.requires_dist() {
+                    if is_extra_dependency(dep, marker_env, extra) {
+                        queue.push_back((dep.clone(), false));
+                    }
+                }
+            }
+            plan.extras.extend(new_extras);
+            continue;
+        }
+
+        // 依赖已在虚拟环境中安装且版本相符的不再安装
+        if !requested {
+            if let Some(version) = installed.get(&name) {
+                if matches_version(&requirement, version) {
+                    continue;
+                }
+            }
+        }
+
+        let direct_url = match &requirement.version_or_url {
+            Some(VersionOrUrl::Url(url)) => Some(url.to_string()),
+            _ => None,
+        };
+
+        let wheel = choose_wheel(installer, &cached_wheels, &requirement).map_err(|err| {
+            InstallingError::new(
+                InstallingErrorKind::Other,
+                Some(name.clone()),
+                err.to_string(),
+            )
+        })?;
+        let Some(wheel) = wheel else {
+            missing.push(requirement);
+            continue;
+        };
+
+        let metadata = read_wheel_metadata(&wheel.path).map_err(|err| {
+            InstallingError::new(
+                InstallingErrorKind::Other,
+                Some(name.clone()),
+                err.to_string(),
+            )
+        })?;
+        warn_missing_extras(&metadata, &extras);
+        let extra_list = extras.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+        for dep in metadata.requires_dist() {
+            let applicable = match &dep.marker {
+                Some(marker) => marker.evaluate(marker_env, &extra_list),
+                None => true,
+            };
+            if applicable {
+                queue.push_back((dep.clone(), false));
+            }
+        }
+
+        planned.push(PlannedWheel {
+            wheel,
+            metadata,
+            extras,
+            direct_url,
+            requested,
+        });
+    }
+
+    if !missing.is_empty() {
+        // 只缺一个程序包时指明该程序包
+        let package = match missing.as_slice() {
+            [requirement] => Some(requirement.name.clone()),
+            _ => None,
+        };
+        let missing = missing
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<String>>();
+        return Err(InstallingError::new(
+            InstallingErrorKind::NotFound,
+            package,
+            format!(
+                "下载目录中没有以下程序包适合本机的wheel:\n{}",
+                missing.join("\n")
+            ),
+        ));
+    }
+
+    let n_total = planned.len();
+    status_updater.update_progress(0, 0, n_total);
+
+    let mut installed_files = Vec::new();
+    for (idx, plan) in planned.iter().enumerate() {
+        if installed.get(&plan.wheel.name) == Some(&plan.wheel.version) {
+            status_updater.message(&format!("{}已安装", plan.wheel.file_name));
+        } else {
+            status_updater.message(&format!("安装{}", plan.wheel.file_name));
+            match install_wheel(installer, plan) {
+                Ok(files) => installed_files.push(files),
+                Err(err) => {
+                    status_updater.update_progress(idx, 1, n_total);
+                    let kind = if err.downcast_ref::<std::io::Error>().is_some() {
+                        InstallingErrorKind::Io
+                    } else {
+                        InstallingErrorKind::Other
+                    };
+                    return Err(InstallingError::new(
+                        kind,
+                        Some(plan.wheel.file_name.clone()),
+                        err.to_string(),
+                    ));
+                }
+            }
+        }
+
+        status_updater.update_progress(idx + 1, 0, n_total);
+    }
+
+    if installer.compile_bytecode() {
+        status_updater.message("编译.pyc文件");
+        compile_bytecode(installer, &site_packages, &installed_files);
+    }
+
+    Ok(())
+}
+
+fn matches_version(requirement: &Requirement, version: &Version) -> bool {
+    match &requirement.version_or_url {
+        Some(VersionOrUrl::VersionSpecifier(specifiers)) => specifiers.contains(version),
+        _ => true,
+    }
+}
+
+/// 依赖只在要求了该extra时才适用
+fn is_extra_dependency(
+    dep: &Requirement,
+    marker_env: &pep508_rs::MarkerEnvironment,
+    extra: &str,
+) -> bool {
+    match &dep.marker {
+        Some(marker) => marker.evaluate(marker_env, &[extra]) && !marker.evaluate(marker_env, &[]),
+        None => false,
+    }
+}
+
+/// 与pip相同，需求中的extra未在元数据的Provides-Extra中声明时只给出警告
+fn warn_missing_extras<'a>(metadata: &DistMetadata, extras: impl IntoIterator<Item = &'a String>) {
+    for extra in extras {
+        let provided = metadata
+            .provides_extra()
+            .iter()
+            .any(|provided| canonicalize_name(provided) == canonicalize_name(extra));
+        if !provided {
+            log::warn!(
+                "{} {}没有提供extra {}",
+                metadata.name(),
+                metadata.version(),
+                extra
+            );
+        }
+    }
+}
+
+fn scan_cached_wheels(installer: &Installer) -> Result<Vec<CachedWheel>> {
+    let mut wheels = Vec::new();
+    for entry in std::fs::read_dir(&installer.cached_packages_dir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let Ok((base, extension)) = split_filename_extension(&file_name) else {
+            continue;
+        };
+        if extension.to_lowercase() != ".whl" {
+            continue;
+        }
+
+        // {name}-{version}(-{build})?-{python}-{abi}-{platform}
+        let parts = base.split('-').collect::<Vec<&str>>();
+        if parts.len() != 5 && parts.len() != 6 {
+            log::warn!("wheel文件名不符合规范: {}", file_name);
+            continue;
+        }
+
+        let Ok(version) = Version::from_str(parts[1]) else {
+            log::warn!("wheel文件名中的版本不符合规范: {}", file_name);
+            continue;
+        };
+
+        let build = match parts.len() {
+            6 => match parse_build_tag(parts[2]) {
+                Ok(build) => Some(build),
+                Err(_) => {
+                    log::warn!("wheel文件名中的构建标签不符合规范: {}", file_name);
+                    continue;
+                }
+            },
+            _ => None,
+        };
+
+        let n = parts.len();
+        let mut ranks = Vec::new();
+        for python in parts[n - 3].split('.') {
+            for abi in parts[n - 2].split('.') {
+                for platform in parts[n - 1].split('.') {
+                    let tag = format!("{}-{}-{}", python, abi, platform);
+                    if let Some(rank) = installer.support_tags_map.get(&tag) {
+                        ranks.push(*rank);
+                    }
+                }
+            }
+        }
+
+        let Some(tag_rank) = ranks.into_iter().min() else {
+            continue; // 不适合本机
+        };
+
+        wheels.push(CachedWheel {
+            file_name: file_name.clone(),
+            name: canonicalize_name(parts[0]),
+            version,
+            build,
+            tag_rank,
+            path,
+        });
+    }
+
+    Ok(wheels)
+}
+
+/// 满足需求的最高版本，同一版本取最适合本机的tag，tag相同时取构建号最大的
+fn choose_wheel(
+    installer: &Installer,
+    wheels: &[CachedWheel],
+    requirement: &Requirement,
+) -> Result<Option<CachedWheel>> {
+    let name = canonicalize_name(&requirement.name);
+
+    // 直接链接为源码包时取由其构建的wheel
+    if let Some(VersionOrUrl::Url(url)) = &requirement.version_or_url {
+        let path = direct_url_cached_file(installer, url.as_str())?;
+        return Ok(wheels.iter().find(|wheel| wheel.path == path).cloned());
+    }
+
+    Ok(wheels
+        .iter()
+        .filter(|wheel| wheel.name == name && matches_version(requirement, &wheel.version))
+        .min_by(|a, b| {
+            b.version
+                .cmp(&a.version)
+                .then(a.tag_rank.cmp(&b.tag_rank))
+                .then_with(|| b.build.cmp(&a.build))
+        })
+        .cloned())
+}
+
+/// site-packages中已安装的程序包：规范名称 -> 版本
+fn scan_installed_dists(site_packages: &Path) -> Result<HashMap<String, Version>> {
+    let mut installed = HashMap::new();
+    if !site_packages.is_dir() {
+        return Ok(installed);
+    }
+
+    for entry in std::fs::read_dir(site_packages)? {
+        let file_name = entry?.file_name().to_string_lossy().to_string();
+        let Some(base) = file_name.strip_suffix(".dist-info") else {
+            continue;
+        };
+        let Some((name, version)) = base.split_once('-') else {
+            continue;
+        };
+        if let Ok(version) = Version::from_str(version) {
+            installed.insert(canonicalize_name(name), version);
+        }
+    }
+
+    Ok(installed)
+}
+
+fn find_dist_info(archive: &mut zip::ZipArchive<File>) -> Result<String> {
+    for i in 0..archive.len() {
+        let entry = archive.by_index(i)?;
+        let Some((dir, file)) = entry.name().split_once('/') else {
+            continue;
+        };
+        if dir.ends_with(".dist-info") && file == "WHEEL" {
+            return Ok(dir.to_string());
+        }
+    }
+
+    bail!("wheel中没有.dist-info/WHEEL")
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Result<Option<String>> {
+    let mut entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut text = String::new();
+    entry.read_to_string(&mut text)?;
+    Ok(Some(text))
+}
+
+fn read_wheel_metadata(path: &Path) -> Result<DistMetadata> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let dist_info = find_dist_info(&mut archive)?;
+
+    match read_entry(&mut archive, &format!("{}/METADATA", dist_info))? {
+        Some(text) => DistMetadata::parse(&text),
+        None => bail!("{}中没有METADATA", path.display()),
+    }
+}
+
+/// 安装方案中的各目标目录，见wheel规范中的`.data`目录
+struct SchemePaths {
+    purelib: PathBuf,
+    platlib: PathBuf,
+    scripts: PathBuf,
+    headers: PathBuf,
+    data: PathBuf,
+}
+
+impl SchemePaths {
+    fn new(installer: &Installer, dist_name: &str) -> Self {
+        let site_packages = installer.site_packages_dir();
+
+        #[cfg(windows)]
+        let headers = installer.venv_dir.join("Include").join(dist_name);
+        #[cfg(not(windows))]
+        let headers = installer
+            .venv_dir
+            .join("include")
+            .join("site")
+            .join(format!("python{}", installer.python_version))
+            .join(dist_name);
+
+        SchemePaths {
+            purelib: site_packages.clone(),
+            platlib: site_packages,
+            scripts: make_venv_bin_dir(&installer.venv_dir),
+            headers,
+            data: installer.venv_dir.clone(),
+        }
+    }
+
+    fn get(&self, scheme: &str) -> Option<&PathBuf> {
+        match scheme {
+            "purelib" => Some(&self.purelib),
+            "platlib" => Some(&self.platlib),
+            "scripts" => Some(&self.scripts),
+            "headers" => Some(&self.headers),
+            "data" => Some(&self.data),
+            _ => None,
+        }
+    }
+}
+
+/// 本次安装的程序包：RECORD文件和解压到site-packages的.py文件
+struct InstalledFiles {
+    record: PathBuf,
+    sources: Vec<PathBuf>,
+}
+
+/// RECORD中的一行
+struct RecordEntry {
+    path: String,
+    hash: String,
+    size: String,
+}
+
+fn install_wheel(installer: &Installer, plan: &PlannedWheel) -> Result<InstalledFiles> {
+    let site_packages = installer.site_packages_dir();
+    let mut archive = zip::ZipArchive::new(File::open(&plan.wheel.path)?)?;
+
+    let dist_info = find_dist_info(&mut archive)?;
+    let data_dir = format!("{}.data", dist_info.trim_end_matches(".dist-info"));
+
+    let wheel_text = read_entry(&mut archive, &format!("{}/WHEEL", dist_info))?.unwrap_or_default();
+    check_wheel_version(&wheel_text)?;
+    let root = if wheel_field(&wheel_text, "Root-Is-Purelib").as_deref() == Some("true") {
+        "purelib"
+    } else {
+        "platlib"
+    };
+
+    // wheel自带RECORD中的sha256，解压时逐个校验
+    let record_name = format!("{}/RECORD", dist_info);
+    let expected = match read_entry(&mut archive, &record_name)? {
+        Some(text) => parse_record(&text)
+            .into_iter()
+            .map(|entry| (entry.path, entry.hash))
+            .collect::<HashMap<String, String>>(),
+        None => bail!("wheel中没有RECORD"),
+    };
+
+    remove_installed_dist(&site_packages, &plan.wheel.name)?;
+
+    let scheme = SchemePaths::new(installer, plan.metadata.name());
+    let mut records = Vec::new();
+    let mut sources = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        let name = entry.name().to_string();
+        if name == record_name {
+            continue;
+        }
+
+        let Some(relative) = entry.enclosed_name().map(|path| path.to_path_buf()) else {
+            bail!("wheel中的路径不安全: {}", name);
+        };
+
+        // {name}.data/{scheme}/...解压到对应的目录，其余解压到根目录
+        let (dest, is_script) = match relative.strip_prefix(&data_dir) {
+            Ok(rest) => {
+                let mut components = rest.components();
+                let Some(Component::Normal(scheme_name)) = components.next() else {
+                    bail!("wheel中的路径不符合规范: {}", name);
+                };
+                let scheme_name = scheme_name.to_string_lossy();
+                let Some(base) = scheme.get(&scheme_name) else {
+                    bail!("wheel中未知的安装目录: {}", name);
+                };
+                (base.join(components.as_path()), scheme_name == "scripts")
+            }
+            Err(_) => (scheme.get(root).unwrap().join(&relative), false),
+        };
+
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let (hash, size) = if is_script {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            let hash = record_hash(&content);
+            verify_record_hash(&expected, &name, &hash)?;
+
+            let content = rewrite_script_shebang(installer, content);
+            std::fs::write(&dest, &content)?;
+            set_executable(&dest)?;
+            (record_hash(&content), content.len())
+        } else {
+            let mut writer = HashingWriter::new(File::create(&dest)?);
+            std::io::copy(&mut entry, &mut writer)?;
+            let (hash, size) = writer.finish();
+            verify_record_hash(&expected, &name, &hash)?;
+
+            #[cfg(unix)]
+            if entry.unix_mode().map(|mode| mode & 0o111 != 0) == Some(true) {
+                set_executable(&dest)?;
+            }
+            if dest.starts_with(&site_packages) && dest.extension().is_some_and(|ext| ext == "py") {
+                sources.push(dest.clone());
+            }
+            (hash, size)
+        };
+
+        records.push(RecordEntry {
+            path: record_path(&site_packages, &dest),
+            hash,
+            size: size.to_string(),
+        });
+    }
+
+    // 命令行启动程序
+    if let Some(text) = read_entry(&mut archive, &format!("{}/entry_points.txt", dist_info))? {
+        for (section, gui) in [("console_scripts", false), ("gui_scripts", true)] {
+            for (script_name, entry_point) in parse_entry_points(&text, section) {
+                let dest =
+                    write_launcher(installer, &scheme.scripts, &script_name, &entry_point, gui)?;
+                let content = std::fs::read(&dest)?;
+                records.push(RecordEntry {
+                    path: record_path(&site_packages, &dest),
+                    hash: record_hash(&content),
+                    size: content.len().to_string(),
+                });
+            }
+        }
+    }
+
+    // 安装工具写入的dist-info文件
+    let dist_info_dir = site_packages.join(&dist_info);
+    let mut extra_files = vec![("INSTALLER", format!("{}\n", INSTALLER_NAME))];
+    if plan.requested {
+        extra_files.push(("REQUESTED", String::new()));
+    }
+    if let Some(url) = &plan.direct_url {
+        let direct_url = serde_json::json!({
+            "url": url.split('#').next().unwrap_or(url),
+            "archive_info": {
+                "hashes": {"sha256": sha256_hex(&std::fs::read(&plan.wheel.path)?)},
+            },
+        });
+        extra_files.push(("direct_url.json", direct_url.to_string()));
+    }
+
+    for (file_name, content) in extra_files {
+        let dest = dist_info_dir.join(file_name);
+        std::fs::write(&dest, &content)?;
+        records.push(RecordEntry {
+            path: record_path(&site_packages, &dest),
+            hash: record_hash(content.as_bytes()),
+            size: content.len().to_string(),
+        });
+    }
+
+    records.push(RecordEntry {
+        path: record_name.clone(),
+        hash: String::new(),
+        size: String::new(),
+    });
+    let record = site_packages.join(&record_name);
+    write_record(&record, &records)?;
+
+    log::info!("{}安装完成", plan.wheel.file_name);
+
+    Ok(InstalledFiles { record, sources })
+}
+
+fn wheel_field(wheel_text: &str, name: &str) -> Option<String> {
+    wheel_text.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().to_lowercase())
+        } else {
+            None
+        }
+    })
+}
+
+fn check_wheel_version(wheel_text: &str) -> Result<()> {
+    let Some(version) = wheel_field(wheel_text, "Wheel-Version") else {
+        bail!("WHEEL中没有Wheel-Version")
+    };
+
+    match version.split('.').next() {
+        Some("1") => Ok(()),
+        _ => bail!("不支持的wheel格式版本: {}", version),
+    }
+}
+
+/// 卸载site-packages中已安装的同名程序包（其它版本），按其RECORD删除文件
+fn remove_installed_dist(site_packages: &Path, name: &str) -> Result<()> {
+    for entry in std::fs::read_dir(site_packages)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let Some(base) = file_name.strip_suffix(".dist-info") else {
+            continue;
+        };
+        let Some((dist_name, _)) = base.split_once('-') else {
+            continue;
+        };
+        if canonicalize_name(dist_name) != name {
+            continue;
+        }
+
+        log::info!("卸载已安装的{}", file_name);
+
+        let record = std::fs::read_to_string(path.join("RECORD")).unwrap_or_default();
+        let mut dirs = BTreeSet::new();
+        for entry in parse_record(&record) {
+            let file = site_packages.join(&entry.path);
+            if file.is_file() {
+                std::fs::remove_file(&file)?;
+            }
+            if let Some(parent) = file.parent() {
+                dirs.insert(parent.to_path_buf());
+            }
+        }
+
+        if path.is_dir() {
+            std::fs::remove_dir_all(&path)?;
+        }
+
+        // 由深到浅删除已空的目录
+        for dir in dirs.iter().rev() {
+            if dir.starts_with(site_packages) && dir != site_packages {
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 解析RECORD（CSV格式：路径,摘要,大小），路径中含逗号时以双引号括起
+fn parse_record(text: &str) -> Vec<RecordEntry> {
+    let mut entries = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(std::mem::take(&mut field)),
+                _ => field.push(c),
+            }
+        }
+        fields.push(field);
+
+        let mut fields = fields.into_iter();
+        entries.push(RecordEntry {
+            path: fields.next().unwrap_or_default(),
+            hash: fields.next().unwrap_or_default(),
+            size: fields.next().unwrap_or_default(),
+        });
+    }
+
+    entries
+}
+
+fn write_record(path: &Path, records: &[RecordEntry]) -> Result<()> {
+    let quote = |field: &str| {
+        if field.contains(',') || field.contains('"') {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    };
+
+    let mut content = String::new();
+    for record in records {
+        content.push_str(&format!(
+            "{},{},{}\n",
+            quote(&record.path),
+            record.hash,
+            record.size
+        ));
+    }
+
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// RECORD中的路径相对于site-packages，以`/`分隔
+fn record_path(site_packages: &Path, dest: &Path) -> String {
+    let base = site_packages.components().collect::<Vec<Component>>();
+    let target = dest.components().collect::<Vec<Component>>();
+
+    let common = base
+        .iter()
+        .zip(target.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut parts = vec!["..".to_string(); base.len() - common];
+    for component in &target[common..] {
+        parts.push(component.as_os_str().to_string_lossy().to_string());
+    }
+
+    parts.join("/")
+}
+
+fn verify_record_hash(expected: &HashMap<String, String>, name: &str, hash: &str) -> Result<()> {
+    match expected.get(name) {
+        Some(expected) if expected.starts_with("sha256=") && expected != hash => {
+            bail!("{}与RECORD中的摘要不符", name)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `sha256=`加urlsafe、无填充的base64编码，见RECORD的格式
+fn record_hash(content: &[u8]) -> String {
+    format!("sha256={}", urlsafe_base64(&Sha256::digest(content)))
+}
+
+fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+fn urlsafe_base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+        }
+    }
+
+    encoded
+}
+
+/// 写入时计算sha256和大小
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    size: usize,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn finish(self) -> (String, usize) {
+        let digest = self.hasher.finalize().to_vec();
+        (format!("sha256={}", urlsafe_base64(&digest)), self.size)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.size += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `.data/scripts`中以`#!python`开头的脚本改为虚拟环境中的Python
+fn rewrite_script_shebang(installer: &Installer, content: Vec<u8>) -> Vec<u8> {
+    let rest = if content.starts_with(b"#!pythonw") {
+        &content[9..]
+    } else if content.starts_with(b"#!python") {
+        &content[8..]
+    } else {
+        return content;
+    };
+
+    let mut rewritten = python_shebang(installer, false).into_bytes();
+    rewritten.extend_from_slice(rest);
+    rewritten
+}
+
+fn python_shebang(installer: &Installer, gui: bool) -> String {
+    let python = if gui && cfg!(windows) {
+        installer.venv_python_path.with_file_name("pythonw.exe")
+    } else {
+        installer.venv_python_path.clone()
+    };
+
+    let python = python.to_string_lossy().to_string();
+    if python.contains(' ') {
+        format!("#!\"{}\"", python)
+    } else {
+        format!("#!{}", python)
+    }
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    std::fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// entry_points.txt中某节的`名称 = 模块:对象`
+fn parse_entry_points(text: &str, section: &str) -> Vec<(String, String)> {
+    let mut entries = Vec::new();
+    let mut in_section = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            in_section = name.trim() == section;
+            continue;
+        }
+
+        if in_section {
+            if let Some((name, value)) = line.split_once('=') {
+                entries.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+    }
+
+    entries
+}
+
+/// 生成与pip相同的启动脚本，Windows下拼接pip自带的distlib启动程序成为.exe
+fn write_launcher(
+    installer: &Installer,
+    scripts_dir: &Path,
+    script_name: &str,
+    entry_point: &str,
+    gui: bool,
+) -> Result<PathBuf> {
+    // 形如`module:attr.sub [extra]`，extras对启动脚本无影响
+    let entry_point = entry_point.split('[').next().unwrap_or_default().trim();
+    let Some((module, attrs)) = entry_point.split_once(':') else {
+        bail!("启动脚本{}的入口{}格式错误", script_name, entry_point)
+    };
+    let import_name = attrs.split('.').next().unwrap_or(attrs);
+
+    let script = format!(
+        "# -*- coding: utf-8 -*-\n\
+        import re\n\
+        import sys\n\
+        from {module} import {import_name}\n\
+        if __name__ == '__main__':\n    \
+            sys.argv[0] = re.sub(r'(-script\\.pyw|\\.exe)?$', '', sys.argv[0])\n    \
+            sys.exit({attrs}())\n",
+        module = module.trim(),
+        import_name = import_name.trim(),
+        attrs = attrs.trim(),
+    );
+
+    std::fs::create_dir_all(scripts_dir)?;
+
+    if cfg!(windows) {
+        let launcher = read_distlib_launcher(installer, gui)?;
+
+        let mut zip_data = std::io::Cursor::new(Vec::new());
+        {
+            let mut writer = zip::ZipWriter::new(&mut zip_data);
+            let options = zip::write::FileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored);
+            writer.start_file("__main__.py", options)?;
+            writer.write_all(script.as_bytes())?;
+            writer.finish()?;
+        }
+
+        let mut content = launcher;
+        content.extend_from_slice(python_shebang(installer, gui).as_bytes());
+        content.extend_from_slice(b"\r\n");
+        content.extend_from_slice(zip_data.get_ref());
+
+        let dest = scripts_dir.join(format!("{}.exe", script_name));
+        std::fs::write(&dest, content)?;
+        Ok(dest)
+    } else {
+        let dest = scripts_dir.join(script_name);
+        std::fs::write(
+            &dest,
+            format!("{}\n{}", python_shebang(installer, gui), script),
+        )?;
+        set_executable(&dest)?;
+        Ok(dest)
+    }
+}
+
+/// 虚拟环境中pip附带的distlib启动程序，控制台程序为t*.exe，图形界面程序为w*.exe
+fn read_distlib_launcher(installer: &Installer, gui: bool) -> Result<Vec<u8>> {
+    let platform_tag = installer.platform_tag.as_deref().unwrap_or_default();
+    let kind = if gui { "w" } else { "t" };
+    let launcher_name = match platform_tag {
+        "win32" => format!("{}32.exe", kind),
+        "win_arm64" => format!("{}64-arm.exe", kind),
+        _ => format!("{}64.exe", kind),
+    };
+
+    let path = installer
+        .site_packages_dir()
+        .join("pip")
+        .join("_vendor")
+        .join("distlib")
+        .join(&launcher_name);
+
+    std::fs::read(&path).map_err(|err| anyhow!("读取启动程序{}错误: {}", path.display(), err))
+}
+
+/// 逐个编译.py文件，在第二个参数给出的文件中逐行写入`源文件\t.pyc文件`
+const COMPILE_SCRIPT: &str = r"import py_compile, sys
+with open(sys.argv[1], encoding='utf-8') as sources, \
+        open(sys.argv[2], 'w', encoding='utf-8') as compiled:
+    for source in sources.read().splitlines():
+        try:
+            compiled.write(source + '\t' + py_compile.compile(source, doraise=True) + '\n')
+        except Exception as err:
+            print(err, file=sys.stderr)
+";
+
+/// 与pip相同，只编译本次安装的.py文件，并把生成的.pyc文件加入各程序包的RECORD。
+/// 失败时（如个别文件有语法错误）只记录日志
+fn compile_bytecode(installer: &Installer, site_packages: &Path, installed: &[InstalledFiles]) {
+    match compile_installed_sources(installer, site_packages, installed) {
+        Ok(()) => log::info!("编译.pyc文件完成"),
+        Err(err) => log::warn!("编译.pyc文件出现错误: {}", err),
+    }
+}
+
+fn compile_installed_sources(
+    installer: &Installer,
+    site_packages: &Path,
+    installed: &[InstalledFiles],
+) -> Result<()> {
+    let sources = installed
+        .iter()
+        .flat_map(|dist| dist.sources.iter())
+        .map(|source| source.to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    if sources.is_empty() {
+        return Ok(());
+    }
+
+    // 文件很多时超出命令行长度限制，文件列表经临时文件传递
+    let tmp_dir = tempfile::tempdir()?;
+    let sources_path = tmp_dir.path().join("sources.txt");
+    let compiled_path = tmp_dir.path().join("compiled.txt");
+    std::fs::write(&sources_path, sources.join("\n"))?;
+
+    let sources_arg = sources_path.to_string_lossy().to_string();
+    let compiled_arg = compiled_path.to_string_lossy().to_string();
+    let output = venv_python_cmd(
+        installer,
+        &["-c", COMPILE_SCRIPT, &sources_arg, &compiled_arg],
+    )?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr));
+    }
+    if !output.stderr.is_empty() {
+        log::warn!(
+            "部分.py文件编译失败:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let compiled = std::fs::read_to_string(&compiled_path)?;
+    let compiled = compiled
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect::<HashMap<&str, &str>>();
+
+    for dist in installed {
+        let mut records = parse_record(&std::fs::read_to_string(&dist.record)?);
+        let n_records = records.len();
+
+        for source in &dist.sources {
+            let Some(pyc) = compiled.get(&*source.to_string_lossy()) else {
+                continue;
+            };
+
+            let pyc = PathBuf::from(*pyc);
+            let content = std::fs::read(&pyc)?;
+            records.push(RecordEntry {
+                path: record_path(site_packages, &pyc),
+                hash: record_hash(&content),
+                size: content.len().to_string(),
+            });
+        }
+
+        if records.len() > n_records {
+            write_record(&dist.record, &records)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testutil::test_installer;
+    use super::*;
+
+    #[test]
+    fn record_round_trips_quoted_paths() {
+        let text = "demo/__init__.py,sha256=WJG1tSLV3whtD_CxEPvZ0hu0_HFjrzTQgoai6Eb2vgM,6\n\
+            \"demo/data,with comma.txt\",sha256=YWJj,3\n\
+            \"demo/\"\"quoted\"\".txt\",,\n\
+            demo-1.0.dist-info/RECORD,,\n";
+
+        let records = parse_record(text);
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].path, "demo/__init__.py");
+        assert_eq!(records[0].size, "6");
+        assert_eq!(records[1].path, "demo/data,with comma.txt");
+        assert_eq!(records[1].hash, "sha256=YWJj");
+        assert_eq!(records[1].size, "3");
+        assert_eq!(records[2].path, "demo/\"quoted\".txt");
+        assert_eq!(records[2].hash, "");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("RECORD");
+        write_record(&path, &records).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, text);
+
+        let reparsed = parse_record(&written);
+        assert_eq!(reparsed.len(), records.len());
+        for (a, b) in reparsed.iter().zip(records.iter()) {
+            assert_eq!((&a.path, &a.hash, &a.size), (&b.path, &b.hash, &b.size));
+        }
+    }
+
+    #[test]
+    fn urlsafe_base64_matches_python_without_padding() {
+        // base64.urlsafe_b64encode(data).rstrip(b"=")
+        assert_eq!(urlsafe_base64(b"a"), "YQ");
+        assert_eq!(urlsafe_base64(b"ab"), "YWI");
+        assert_eq!(urlsafe_base64(b"abc"), "YWJj");
+        assert_eq!(urlsafe_base64(&[0xfb, 0xff]), "-_8");
+        assert_eq!(urlsafe_base64(&[0xfb, 0xff, 0xbf]), "-_-_");
+        assert_eq!(urlsafe_base64(b""), "");
+        assert_eq!(
+            record_hash(b"hello\n"),
+            "sha256=WJG1tSLV3whtD_CxEPvZ0hu0_HFjrzTQgoai6Eb2vgM"
+        );
+    }
+
+    #[test]
+    fn record_path_is_relative_to_site_packages() {
+        let venv = Path::new("/opt/app/venv");
+        let site_packages = venv.join("lib").join("python3.11").join("site-packages");
+
+        assert_eq!(
+            record_path(
+                &site_packages,
+                &site_packages.join("demo").join("__init__.py")
+            ),
+            "demo/__init__.py"
+        );
+        assert_eq!(
+            record_path(&site_packages, &venv.join("bin").join("demo")),
+            "../../../bin/demo"
+        );
+    }
+
+    #[test]
+    fn entry_points_are_read_from_the_requested_section() {
+        let text = "[console_scripts]\n\
+            demo = demo.cli:main\n\
+            # 注释\n\
+            demo-admin=demo.admin:run [extra]\n\
+            \n\
+            [gui_scripts]\n\
+            demo-gui = demo.gui:main\n";
+
+        assert_eq!(
+            parse_entry_points(text, "console_scripts"),
+            vec![
+                ("demo".to_string(), "demo.cli:main".to_string()),
+                (
+                    "demo-admin".to_string(),
+                    "demo.admin:run [extra]".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            parse_entry_points(text, "gui_scripts"),
+            vec![("demo-gui".to_string(), "demo.gui:main".to_string())]
+        );
+        assert!(parse_entry_points(text, "distutils.commands").is_empty());
+    }
+
+    #[test]
+    fn python_shebang_in_scripts_is_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let installer = test_installer(dir.path());
+        let shebang = python_shebang(&installer, false);
+
+        assert_eq!(
+            rewrite_script_shebang(&installer, b"#!python\nprint(1)\n".to_vec()),
+            format!("{}\nprint(1)\n", shebang).into_bytes()
+        );
+        assert_eq!(
+            rewrite_script_shebang(&installer, b"#!pythonw\nprint(1)\n".to_vec()),
+            format!("{}\nprint(1)\n", shebang).into_bytes()
+        );
+        assert_eq!(
+            rewrite_script_shebang(&installer, b"#!/bin/sh\necho 1\n".to_vec()),
+            b"#!/bin/sh\necho 1\n".to_vec()
+        );
+    }
+}
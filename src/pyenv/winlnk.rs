@@ -1,13 +1,19 @@
-use std::path::PathBuf;
+use std::path::Path;
 
 use anyhow::{bail, Result};
+#[cfg(windows)]
 use mslnk::ShellLink;
 
 use super::installer::Installer;
 
+#[cfg(windows)]
 static ICON_FILE_NAME: &str = "tgba-jupyterlab-48x48.ico";
+#[cfg(not(windows))]
+static ICON_FILE_NAME: &str = "tgba-jupyterlab.svg";
 
-pub fn create_winlnk(installer: &Installer, work_dir: &PathBuf) -> Result<()> {
+/// 在工作目录下创建TGBA Shell和JupyterLab的启动快捷方式。
+/// Windows下为.lnk，Linux下为.desktop，macOS下为可双击运行的.command脚本
+pub fn create_winlnk(installer: &Installer, work_dir: &Path) -> Result<()> {
     copy_jupyterlab_icon(installer)?;
 
     create_shell_lnk(installer, work_dir)?;
@@ -16,7 +22,8 @@ pub fn create_winlnk(installer: &Installer, work_dir: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn create_shell_lnk(installer: &Installer, work_dir: &PathBuf) -> Result<()> {
+#[cfg(windows)]
+fn create_shell_lnk(installer: &Installer, work_dir: &Path) -> Result<()> {
     let mut lnk = match ShellLink::new(r"C:\Windows\System32\cmd.exe") {
         Ok(lnk) => lnk,
         Err(err) => bail!("创建: {}", err),
@@ -48,7 +55,8 @@ fn create_shell_lnk(installer: &Installer, work_dir: &PathBuf) -> Result<()> {
     bail!("创建快捷错误: {}", err);
 }
 
-fn create_jupyterlab_lnk(installer: &Installer, work_dir: &PathBuf) -> Result<()> {
+#[cfg(windows)]
+fn create_jupyterlab_lnk(installer: &Installer, work_dir: &Path) -> Result<()> {
     let mut lnk = match ShellLink::new(installer.venv_python_path.to_string_lossy().as_ref()) {
         Ok(lnk) => lnk,
         Err(err) => bail!("创建: {}", err),
@@ -77,6 +85,91 @@ fn create_jupyterlab_lnk(installer: &Installer, work_dir: &PathBuf) -> Result<()
     bail!("创建快捷错误: {}", err);
 }
 
+#[cfg(not(windows))]
+fn create_shell_lnk(installer: &Installer, work_dir: &Path) -> Result<()> {
+    let activate_script_path = installer.venv_dir.join("bin").join("activate");
+
+    // 启动一个已激活虚拟环境的交互式bash
+    let command = format!(
+        r#"bash --rcfile <(cat ~/.bashrc 2>/dev/null; echo 'source "{}"')"#,
+        activate_script_path.to_string_lossy()
+    );
+
+    create_launcher(installer, work_dir, "TGBA Shell", "TGBAShell", &command)
+}
+
+#[cfg(not(windows))]
+fn create_jupyterlab_lnk(installer: &Installer, work_dir: &Path) -> Result<()> {
+    let command = format!(
+        r#""{}" -m jupyterlab"#,
+        installer.venv_python_path.to_string_lossy()
+    );
+
+    create_launcher(
+        installer,
+        work_dir,
+        "TGBA JupyterLab",
+        "TGBAJupyterLab",
+        &command,
+    )
+}
+
+#[cfg(target_os = "linux")]
+fn create_launcher(
+    installer: &Installer,
+    work_dir: &Path,
+    name: &str,
+    file_stem: &str,
+    command: &str,
+) -> Result<()> {
+    let command = command.replace('\\', "\\\\").replace('"', "\\\"");
+    let content = format!(
+        "[Desktop Entry]\n\
+        Type=Application\n\
+        Name={name}\n\
+        Exec=bash -c \"cd '{}' && {}\"\n\
+        Icon={}\n\
+        Path={}\n\
+        Terminal=true\n",
+        work_dir.to_string_lossy(),
+        command,
+        installer.tgba_dir().join(ICON_FILE_NAME).to_string_lossy(),
+        work_dir.to_string_lossy(),
+    );
+
+    write_executable(&work_dir.join(format!("{file_stem}.desktop")), &content)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn create_launcher(
+    _installer: &Installer,
+    work_dir: &Path,
+    _name: &str,
+    file_stem: &str,
+    command: &str,
+) -> Result<()> {
+    let content = format!(
+        "#!/bin/bash\ncd '{}'\n{}\n",
+        work_dir.to_string_lossy(),
+        command
+    );
+
+    write_executable(&work_dir.join(format!("{file_stem}.command")), &content)
+}
+
+#[cfg(unix)]
+fn write_executable(path: &Path, content: &str) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Err(err) = std::fs::write(path, content) {
+        bail!("创建快捷错误: {}: {}", path.display(), err);
+    }
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+
+    Ok(())
+}
+
 use super::super::resources::RESOURCES;
 
 fn copy_jupyterlab_icon(installer: &Installer) -> Result<()> {
@@ -85,7 +178,10 @@ fn copy_jupyterlab_icon(installer: &Installer) -> Result<()> {
     let mut icon_file = File::create(installer.tgba_dir().join(ICON_FILE_NAME))?;
 
     use std::io::Write;
+    #[cfg(windows)]
     icon_file.write_all(RESOURCES.get_app_icon())?;
+    #[cfg(not(windows))]
+    icon_file.write_all(RESOURCES.get_app_svg_icon())?;
 
     Ok(())
 }
@@ -3,18 +3,22 @@ use once_cell::sync::OnceCell;
 
 pub struct Resources {
     app_icon: OnceCell<&'static [u8]>,
+    app_svg_icon: OnceCell<&'static [u8]>,
     config_toml: OnceCell<String>,
     requirements: OnceCell<&'static [u8]>,
     requirements_legacy: OnceCell<&'static [u8]>,
+    top_level_requirements: OnceCell<&'static [u8]>,
 }
 
 impl Resources {
     pub const fn new() -> Resources {
         Resources {
             app_icon: OnceCell::new(),
+            app_svg_icon: OnceCell::new(),
             config_toml: OnceCell::new(),
             requirements: OnceCell::new(),
             requirements_legacy: OnceCell::new(),
+            top_level_requirements: OnceCell::new(),
         }
     }
 
@@ -28,6 +32,24 @@ impl Resources {
         }
     }
 
+    /// 维护者以`tgba-installer lock`生成的锁文件，构建时不存在则为None
+    pub fn get_lock_file(&self) -> Option<&str> {
+        #[cfg(tgba_lock)]
+        {
+            Some(include_str!("../requirements/tgba.lock"))
+        }
+        #[cfg(not(tgba_lock))]
+        {
+            None
+        }
+    }
+
+    /// 未固定版本的顶层需求，用于依赖解析
+    pub fn get_top_level_requirements(&self) -> &[u8] {
+        self.top_level_requirements
+            .get_or_init(|| include_bytes!("../requirements/requirements.txt"))
+    }
+
     pub fn get_app_icon(&self) -> &[u8] {
         self.app_icon.get_or_init(|| {
             let data = include_bytes!("../resources/tgba-jupyterlab-48x48.ico");
@@ -35,6 +57,13 @@ impl Resources {
         })
     }
 
+    pub fn get_app_svg_icon(&self) -> &[u8] {
+        self.app_svg_icon.get_or_init(|| {
+            let data = include_bytes!("../resources/tgba-jupyterlab.svg");
+            data
+        })
+    }
+
     pub fn get_config_toml(&self) -> &str {
         self.config_toml.get_or_init(|| {
             let data = include_bytes!("../requirements/config.toml");
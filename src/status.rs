@@ -4,13 +4,21 @@ pub trait StatusUpdate {
     fn message(&self, msg: &str);
 
     fn update_downloading(&self, status: &DownloadingStats);
+
+    /// 并行下载时第slot个下载槽的进度，缺省与单个下载相同
+    fn update_slot_downloading(&self, _slot: usize, status: &DownloadingStats) {
+        self.update_downloading(status);
+    }
+
+    /// 批量任务的总体进度：已完成数、失败数和总数
+    fn update_progress(&self, _finished: usize, _failed: usize, _total: usize) {}
 }
 
 pub struct DownloadingStats {
     title: String,
     count: u64,
     start_time: Instant,
-    total_size: u64,
+    total_size: Option<u64>, // 服务器未给出Content-Length时未知
     downloaded: u64,
     elasped: Option<Duration>,
     prev_start_time: Instant,
@@ -18,7 +26,7 @@ pub struct DownloadingStats {
 }
 
 impl DownloadingStats {
-    pub fn new(title: &str, total_size: u64) -> Self {
+    pub fn new(title: &str, total_size: Option<u64>) -> Self {
         DownloadingStats {
             // installer,
             title: title.to_string(),
@@ -32,11 +40,19 @@ impl DownloadingStats {
         }
     }
 
+    /// 断点续传时从已下载的resumed字节开始计数
+    pub fn resumed(title: &str, total_size: u64, resumed: u64) -> Self {
+        let mut stats = DownloadingStats::new(title, Some(total_size));
+        stats.downloaded = resumed;
+        stats.prev_downloaded = resumed;
+        stats
+    }
+
     pub fn title(&self) -> &str {
         &self.title
     }
 
-    pub fn total_size(&self) -> u64 {
+    pub fn total_size(&self) -> Option<u64> {
         self.total_size
     }
 
@@ -68,11 +84,12 @@ impl DownloadingStats {
         self.downloaded
     }
 
-    pub fn percentage(&self) -> f64 {
-        if self.total_size != 0 {
-            self.downloaded as f64 / (self.total_size as f64) * 100.0
-        } else {
-            0.0
+    /// 下载百分比，总大小未知时为None
+    pub fn percentage(&self) -> Option<f64> {
+        match self.total_size {
+            Some(0) => Some(0.0),
+            Some(total_size) => Some(self.downloaded as f64 / (total_size as f64) * 100.0),
+            None => None,
         }
     }
 
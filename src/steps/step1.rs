@@ -30,7 +30,21 @@ pub struct Step1Tab {
 }
 
 impl Step1Tab {
-    const DEFAUL_TARGET_DIR: &'static str = r#"C:\TGBAWork"#;
+    #[cfg(windows)]
+    fn default_target_dir() -> String {
+        r#"C:\TGBAWork"#.to_string()
+    }
+
+    #[cfg(not(windows))]
+    fn default_target_dir() -> String {
+        match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home)
+                .join("TGBAWork")
+                .to_string_lossy()
+                .to_string(),
+            None => "TGBAWork".to_string(),
+        }
+    }
 
     pub fn new(group: &mut Group, sender: Sender<Message>) -> Self {
         let mut panel = Flex::default_fill().column();
@@ -57,7 +71,7 @@ impl Step1Tab {
             input_row.fixed(&label, 110);
 
             target_dir_input = Input::default();
-            target_dir_input.set_value(Step1Tab::DEFAUL_TARGET_DIR);
+            target_dir_input.set_value(&Step1Tab::default_target_dir());
             // target_dir_input.set_text_font(style.font_bold_en);
             target_dir_input.set_text_size(16);
 
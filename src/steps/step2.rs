@@ -10,7 +10,7 @@ use std::path::PathBuf;
 
 use super::super::{
     myapp::Message,
-    pyenv::{ensure_python_dist, ensure_venv, Installer},
+    pyenv::{ensure_python_dist, probe_mirrors, Bundle, Installer},
     status::{DownloadingStats, LoadingSpinner, StatusUpdate},
     steps::utils::format_scale,
     style,
@@ -27,10 +27,12 @@ pub enum Step2Message {
     JobMessage(usize, String), // (job_idx, message)
     Job1Downloading {
         title: String,
-        total_size: u64,
-        percentage: f64,
+        total_size: Option<u64>,
+        downloaded: u64,
+        percentage: Option<f64>,
         speed: f64,
     },
+    MirrorOrder(Vec<String>), // 测速后的镜像名称顺序
     Done(Installer),
 }
 
@@ -47,7 +49,8 @@ impl Step2Tab {
     pub fn new(group: &mut Group, sender: Sender<Message>) -> Self {
         let mut panel = Flex::default_fill().column();
 
-        let job_title = ["下载安装Python", "创建Python虚拟环境"];
+        // 虚拟环境在下载程序包之后、构建源码包之前创建，见step3
+        let job_title = ["下载安装Python", "测试PyPI镜像速度"];
 
         panel.resize(group.x(), group.y(), group.w(), group.h());
         group.add(&panel);
@@ -145,8 +148,23 @@ impl Step2Tab {
     pub fn start(&mut self, target_dir: &str, python_version: Option<String>) {
         let mut collector = StatusCollector::new(self.sender.clone(), 0);
 
+        // 安装程序所在目录中有离线安装包时从其中安装，未指定Python版本时按其制作时的版本
+        let bundle = match Bundle::find_default().map(|path| Bundle::open(&path)) {
+            Some(Ok(bundle)) => Some(bundle),
+            Some(Err(err)) => {
+                collector.job_error(format!("读取离线安装包错误: {err}"));
+                return;
+            }
+            None => None,
+        };
+        let python_version = python_version.or_else(|| {
+            bundle
+                .as_ref()
+                .map(|bundle| bundle.python_version().to_string())
+        });
+
         log::info!("目标安装路径：{target_dir}");
-        let installer = match Installer::new(PathBuf::from(target_dir), python_version) {
+        let mut installer = match Installer::new(PathBuf::from(target_dir), python_version) {
             Ok(installer) => installer,
             Err(err) => {
                 collector.job_error(format!("初始化安装参数错误: {err}"));
@@ -154,6 +172,13 @@ impl Step2Tab {
             }
         };
 
+        if let Some(bundle) = bundle {
+            if let Err(err) = installer.set_bundle(bundle) {
+                collector.job_error(err.to_string());
+                return;
+            }
+        }
+
         self.installer = Some(installer.clone());
 
         collector.job_start();
@@ -186,7 +211,7 @@ impl Step2Tab {
         } else if job_idx == 1 {
             std::thread::spawn(move || {
                 // 在新线程内运行异步代码
-                handle.block_on(venv_worker(installer, collector));
+                handle.block_on(mirror_worker(installer, collector));
             });
         }
     }
@@ -217,15 +242,25 @@ impl Step2Tab {
             Step2Message::Job1Downloading {
                 title,
                 total_size,
+                downloaded,
                 percentage,
                 speed,
             } => {
-                let total_size = format_scale(total_size as f64, 1);
+                let size = match total_size {
+                    Some(total_size) => format_scale(total_size as f64, 1),
+                    None => format!("已下载{}", format_scale(downloaded as f64, 1)),
+                };
                 let speed = format_scale(speed as f64, 2);
 
-                let msg = format!("{title}, {total_size} \t {speed}/s");
+                let msg = format!("{title}, {size} \t {speed}/s");
                 self.job_messages[0].set_label(&msg);
-                self.job1_progress.set_value(percentage);
+                // 总大小未知时进度条保持空白
+                self.job1_progress.set_value(percentage.unwrap_or(0.0));
+            }
+            Step2Message::MirrorOrder(order) => {
+                if let Some(installer) = self.installer.as_mut() {
+                    installer.set_mirror_order(&order);
+                }
             }
             msg @ _ => {
                 log::error!("unimplemented {:?}", msg);
@@ -258,6 +293,10 @@ impl StatusCollector {
         self.send(Step2Message::ErrorJob(self.job_idx, err));
     }
 
+    pub fn mirror_order(&self, order: Vec<String>) {
+        self.send(Step2Message::MirrorOrder(order));
+    }
+
     pub fn done(&mut self, installer: Installer) {
         self.send(Step2Message::Done(installer));
     }
@@ -281,6 +320,7 @@ impl StatusUpdate for StatusCollector {
         let sender = self.sender.clone();
         let title = status.title().to_string();
         let total_size = status.total_size();
+        let downloaded = status.downloaded();
         let speed = status.speed();
         let percentage = status.percentage();
 
@@ -288,6 +328,7 @@ impl StatusUpdate for StatusCollector {
         sender.send(Message::Step2(Step2Message::Job1Downloading {
             title: title.clone(),
             total_size,
+            downloaded,
             percentage,
             speed,
         }));
@@ -303,11 +344,21 @@ pub async fn download_worker(mut installer: Installer, mut collecter: StatusColl
     collecter.job_success();
 }
 
-pub async fn venv_worker(mut installer: Installer, mut collecter: StatusCollector) {
-    if let Err(err) = ensure_venv(&mut installer, &collecter).await {
-        collecter.job_error(format!("创建Python虚拟环境发生错误: {err}"));
+pub async fn mirror_worker(mut installer: Installer, mut collecter: StatusCollector) {
+    if installer.bundle().is_some() {
+        collecter.message("从离线安装包安装，跳过镜像测速");
+        collecter.job_success();
         return;
-    };
+    }
+
+    let probes = probe_mirrors(&mut installer, &collecter).await;
+
+    // 测速只影响镜像顺序，全部失败时仍按配置顺序继续
+    let order = probes
+        .iter()
+        .map(|probe| probe.name().to_string())
+        .collect();
+    collecter.mirror_order(order);
 
     collecter.job_success();
 }
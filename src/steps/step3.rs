@@ -25,14 +25,21 @@ pub enum Step3Message {
     // JobProgress(u32, u32),
     JobError(String),
     Downloading {
+        slot: usize,
         title: String,
-        total_size: u64,
-        percentage: f64,
+        total_size: Option<u64>,
+        downloaded: u64,
+        percentage: Option<f64>,
         speed: f64,
     },
-    DownloadingStart(Installer, Vec<Requirement>, usize),
-    DownloadingError(Installer, Vec<Requirement>, usize, String),
-    DownloadingDone(Installer, Vec<Requirement>, usize),
+    DownloadingProgress {
+        finished: usize,
+        failed: usize,
+        total: usize,
+    },
+    DownloadingStart(Installer, Vec<Requirement>),
+    DownloadingError(Installer, Vec<Requirement>, String), // (installer, 下载失败的需求, errmsg)
+    DownloadingDone(Installer),
     Done(Installer),
 }
 
@@ -45,13 +52,77 @@ pub struct Step3Tab {
     job_progress: Progress,
     job_message: Frame,
     job_percent: Frame,
-    downloading_message: Frame,
-    downloading_speed: Frame,
-    downloading_progress: Progress,
+    slot_rows: Vec<SlotRow>,
     job_spinner: LoadingSpinner,
 }
 
+/// 并行下载中一个下载槽的显示行
+struct SlotRow {
+    message: Frame,
+    speed: Frame,
+    progress: Progress,
+}
+
+impl SlotRow {
+    fn new(panel: &mut Flex) -> Self {
+        let message: Frame;
+        let speed: Frame;
+        let progress: Progress;
+
+        let mut job_flex = Flex::default_fill().row();
+        panel.fixed(&job_flex, 20);
+        {
+            job_flex.fixed(&Frame::default(), 36);
+
+            let mut flex = Flex::default_fill().column();
+            {
+                let mut msg_flex = Flex::default_fill().row();
+                {
+                    let mut frame = Frame::default()
+                        .with_label("")
+                        .with_align(Align::Inside | Align::Left);
+                    frame.set_label_size(12);
+                    frame.set_label_color(style::COLOR_GREY);
+                    message = frame;
+
+                    let mut frame = Frame::default()
+                        .with_label("")
+                        .with_align(Align::Inside | Align::Right);
+                    frame.set_label_size(12);
+                    frame.set_label_color(style::COLOR_MESSAGE);
+                    msg_flex.fixed(&frame, 80);
+                    speed = frame;
+
+                    msg_flex.end();
+                }
+
+                let mut bar = Progress::default();
+                bar.set_color(style::COLOR_GREY);
+                bar.set_frame(fltk::enums::FrameType::FlatBox);
+                bar.set_minimum(0.0);
+                bar.set_maximum(100.0);
+                bar.set_selection_color(style::COLOR_TGU);
+                flex.fixed(&bar, 3);
+                progress = bar;
+
+                flex.end();
+            }
+
+            job_flex.end();
+        }
+
+        SlotRow {
+            message,
+            speed,
+            progress,
+        }
+    }
+}
+
 impl Step3Tab {
+    /// 界面上最多显示的下载槽行数，并行数超过时多个下载槽共用一行
+    const MAX_SLOT_ROWS: usize = 4;
+
     pub fn new(
         main_win: DoubleWindow,
         group: &mut Group,
@@ -68,9 +139,6 @@ impl Step3Tab {
         let mut job_progress: Progress;
         let mut job_message: Frame;
         let mut job_percent: Frame;
-        let mut downloading_message: Frame;
-        let mut downloading_speed: Frame;
-        let mut downloading_progress: Progress;
 
         // ---------------- Job0 ------------------------------------------
         let mut job_flex = Flex::default_fill().row();
@@ -119,49 +187,11 @@ impl Step3Tab {
 
         panel.fixed(&mut Frame::default(), 10);
 
-        let mut job_flex = Flex::default_fill().row();
-        panel.fixed(&job_flex, 24);
-        {
-            job_flex.fixed(&Frame::default(), 36);
-
-            let mut flex = Flex::default_fill().column();
-            {
-                let mut msg_flex = Flex::default_fill().row();
-                {
-                    downloading_message = Frame::default()
-                        .with_label("")
-                        .with_align(Align::Inside | Align::Left);
-                    downloading_message.set_label_size(12);
-                    downloading_message.set_label_color(style::COLOR_GREY);
-
-                    downloading_speed = Frame::default()
-                        .with_label("")
-                        .with_align(Align::Inside | Align::Right);
-                    downloading_speed.set_label_size(12);
-                    downloading_speed.set_label_color(style::COLOR_MESSAGE);
-                    msg_flex.fixed(&downloading_speed, 80);
-
-                    msg_flex.end();
-                }
-
-                downloading_progress = Progress::default();
-                downloading_progress.set_color(style::COLOR_GREY);
-                downloading_progress.set_frame(fltk::enums::FrameType::FlatBox);
-                downloading_progress.set_minimum(0.0);
-                downloading_progress.set_maximum(100.0);
-                downloading_progress.set_selection_color(style::COLOR_TGU);
-
-                flex.fixed(&downloading_progress, 3);
-
-                flex.end();
-            }
-
-            job_flex.end();
+        let mut slot_rows = Vec::new();
+        for _ in 0..Self::MAX_SLOT_ROWS {
+            slot_rows.push(SlotRow::new(&mut panel));
         }
 
-        let frame = Frame::default();
-        panel.fixed(&frame, 30);
-
         Frame::default();
 
         panel.end();
@@ -176,9 +206,7 @@ impl Step3Tab {
             job_progress,
             job_message,
             job_percent,
-            downloading_message,
-            downloading_speed,
-            downloading_progress,
+            slot_rows,
         }
     }
 
@@ -198,65 +226,46 @@ impl Step3Tab {
         });
     }
 
-    pub fn on_downloading_start(
-        &mut self,
-        installer: Installer,
-        requirements: Vec<Requirement>,
-        requirement_idx: usize,
-    ) {
+    pub fn on_downloading_start(&mut self, installer: Installer, requirements: Vec<Requirement>) {
         let handle = tokio::runtime::Handle::current();
         let collector = Step3Collector::new(self.sender.clone());
 
         std::thread::spawn(move || {
             // 在新线程内运行异步代码
-            handle.block_on(download_worker(
-                installer,
-                collector,
-                requirements,
-                requirement_idx,
-            ));
+            handle.block_on(download_worker(installer, collector, requirements));
         });
     }
 
-    fn on_downloading_done(
-        &mut self,
-        installer: Installer,
-        requirements: Vec<Requirement>,
-        requirement_idx: usize,
-    ) {
-        let n_requirement = requirements.len();
-        let next_idx = requirement_idx + 1;
-
-        {
-            // 显示进度比例
-            let percent = next_idx as f64 / n_requirement as f64 * 100.0;
+    fn on_downloading_progress(&mut self, finished: usize, failed: usize, total: usize) {
+        // 显示进度比例
+        let percent = if total > 0 {
+            (finished + failed) as f64 / total as f64 * 100.0
+        } else {
+            100.0
+        };
 
-            self.job_percent
-                .set_label(&format!("{next_idx}/{n_requirement}"));
-            self.job_progress.set_value(percent);
-            self.job_progress.redraw();
-        }
+        self.job_percent.set_label(&format!("{finished}/{total}"));
+        self.job_progress.set_value(percent);
+        self.job_progress.redraw();
+    }
 
-        if next_idx < n_requirement {
-            self.sender
-                .send(Message::Step3(Step3Message::DownloadingStart(
-                    installer,
-                    requirements,
-                    requirement_idx + 1,
-                )));
-        } else {
-            self.job_spinner.success(); // 最后一个下载结束
+    fn on_downloading_done(&mut self, installer: Installer) {
+        self.job_spinner.success(); // 全部下载结束
 
-            self.sender
-                .send(Message::Step3(Step3Message::Done(installer)));
+        for row in &mut self.slot_rows {
+            row.message.set_label("");
+            row.speed.set_label("");
+            row.progress.set_value(0.0);
         }
+
+        self.sender
+            .send(Message::Step3(Step3Message::Done(installer)));
     }
 
     fn on_downloading_error(
-        &self,
+        &mut self,
         installer: Installer,
-        requirements: Vec<Requirement>,
-        requirement_idx: usize,
+        failed_requirements: Vec<Requirement>,
         errmsg: String,
     ) {
         log::error!("下载中遇到错误: \n{errmsg}");
@@ -264,33 +273,44 @@ impl Step3Tab {
 
         let result = error_confirm(&self.main_win, "下载失败", &errmsg);
         if result == 1 {
-            log::info!("用户选择再次尝试下载{}", requirements[requirement_idx]);
+            log::info!(
+                "用户选择再次尝试下载{}个程序包",
+                failed_requirements.len()
+            );
             self.sender
                 .send(Message::Step3(Step3Message::DownloadingStart(
                     installer,
-                    requirements,
-                    requirement_idx,
+                    failed_requirements,
                 )));
         } else {
+            self.job_spinner.error();
             log::info!("用户选择放弃安装，退出程序！");
         }
     }
 
     fn on_downloading_status(
         &mut self,
+        slot: usize,
         title: String,
-        total_size: u64,
-        percentage: f64,
+        total_size: Option<u64>,
+        downloaded: u64,
+        percentage: Option<f64>,
         speed: f64,
     ) {
-        let total_size = format_scale(total_size as f64, 1);
+        let size = match total_size {
+            Some(total_size) => format_scale(total_size as f64, 1),
+            None => format!("已下载{}", format_scale(downloaded as f64, 1)),
+        };
         let speed = format_scale(speed as f64, 2);
 
-        let msg = format!("{title}, {total_size}");
-        self.downloading_message.set_label(&msg);
-        self.downloading_message.set_label_color(style::COLOR_MESSAGE);
-        self.downloading_speed.set_label(&format!("{speed}/s"));
-        self.downloading_progress.set_value(percentage);
+        let row = &mut self.slot_rows[slot % Self::MAX_SLOT_ROWS];
+
+        let msg = format!("{title}, {size}");
+        row.message.set_label(&msg);
+        row.message.set_label_color(style::COLOR_MESSAGE);
+        row.speed.set_label(&format!("{speed}/s"));
+        // 总大小未知时进度条保持空白
+        row.progress.set_value(percentage.unwrap_or(0.0));
     }
 
     pub fn handle_message(&mut self, msg: Step3Message) {
@@ -298,22 +318,31 @@ impl Step3Tab {
             Step3Message::JobMessage(msg) => {
                 self.job_message.set_label(&msg);
             }
-            Step3Message::DownloadingStart(installer, requirements, requirement_idx) => {
-                self.on_downloading_start(installer, requirements, requirement_idx)
+            Step3Message::JobError(err) => {
+                self.job_spinner.error();
+                fltk::dialog::alert_default(&err);
             }
-            Step3Message::DownloadingDone(installer, requirements, requirement_idx) => {
-                self.on_downloading_done(installer, requirements, requirement_idx)
+            Step3Message::DownloadingStart(installer, requirements) => {
+                self.on_downloading_start(installer, requirements)
             }
-            Step3Message::DownloadingError(installer, requirements, requirement_idx, errmsg) => {
-                self.on_downloading_error(installer, requirements, requirement_idx, errmsg)
+            Step3Message::DownloadingProgress {
+                finished,
+                failed,
+                total,
+            } => self.on_downloading_progress(finished, failed, total),
+            Step3Message::DownloadingError(installer, failed_requirements, errmsg) => {
+                self.on_downloading_error(installer, failed_requirements, errmsg)
             }
+            Step3Message::DownloadingDone(installer) => self.on_downloading_done(installer),
             Step3Message::Downloading {
+                slot,
                 title,
                 total_size,
+                downloaded,
                 percentage,
                 speed,
             } => {
-                self.on_downloading_status(title, total_size, percentage, speed);
+                self.on_downloading_status(slot, title, total_size, downloaded, percentage, speed);
             }
             _ => {
                 unimplemented!("unknow msg: {:?}", msg)
@@ -335,8 +364,8 @@ impl Step3Collector {
         self.send(Step3Message::JobError(err));
     }
 
-    fn start_downloading(&self, installer: Installer, requirements: Vec<Requirement>, idx: usize) {
-        self.send(Step3Message::DownloadingStart(installer, requirements, idx));
+    fn start_downloading(&self, installer: Installer, requirements: Vec<Requirement>) {
+        self.send(Step3Message::DownloadingStart(installer, requirements));
     }
 
     fn send(&self, msg: Step3Message) {
@@ -350,13 +379,27 @@ impl StatusUpdate for Step3Collector {
     }
 
     fn update_downloading(&self, status: &DownloadingStats) {
+        self.update_slot_downloading(0, status);
+    }
+
+    fn update_slot_downloading(&self, slot: usize, status: &DownloadingStats) {
         self.send(Step3Message::Downloading {
+            slot,
             title: status.title().to_string(),
             total_size: status.total_size(),
+            downloaded: status.downloaded(),
             percentage: status.percentage(),
             speed: status.speed(),
         });
     }
+
+    fn update_progress(&self, finished: usize, failed: usize, total: usize) {
+        self.send(Step3Message::DownloadingProgress {
+            finished,
+            failed,
+            total,
+        });
+    }
 }
 
 pub async fn prepare_downloading(mut installer: Installer, mut collector: Step3Collector) {
@@ -367,40 +410,50 @@ pub async fn prepare_downloading(mut installer: Installer, mut collector: Step3C
         return;
     }
 
-    let requirements: Vec<pep508_rs::Requirement> = match prepare_requirements(&installer).await {
-        Ok(requirements) => requirements,
-        Err(err) => {
-            collector.job_error(format!("下载安装软件包中发生错误: {err}"));
-            return;
-        }
-    };
+    let requirements: Vec<pep508_rs::Requirement> =
+        match prepare_requirements(&mut installer, &collector).await {
+            Ok(requirements) => requirements,
+            Err(err) => {
+                collector.job_error(format!("下载安装软件包中发生错误: {err}"));
+                return;
+            }
+        };
 
-    collector.start_downloading(installer, requirements, 0);
+    let mirrors = installer
+        .pypi_mirrors()
+        .iter()
+        .map(|mirror| mirror.name())
+        .collect::<Vec<&str>>()
+        .join(" > ");
+    collector.message(&format!("依次从以下镜像下载: {mirrors}"));
+
+    collector.start_downloading(installer, requirements);
 }
 
 pub async fn download_worker(
     installer: Installer,
     collector: Step3Collector,
     requirements: Vec<Requirement>,
-    requirement_idx: usize,
 ) {
-    use super::super::pyenv::retry_download_requirement;
-
-    let requirement = &requirements[requirement_idx];
+    use super::super::pyenv::{build_sdist_wheels, download_requirements, ensure_venv};
 
-    if let Err(err) = retry_download_requirement(&installer, &collector, requirement).await {
+    if let Err(failures) = download_requirements(&installer, &collector, &requirements).await {
+        let errmsg = failures.to_string();
         collector.send(Step3Message::DownloadingError(
             installer,
-            requirements,
-            requirement_idx,
-            err.to_string(),
+            failures.requirements(),
+            errmsg,
         ));
-        return;
+    } else if let Err(err) = ensure_venv(&installer, &collector).await {
+        // 全部下载成功后才创建虚拟环境，构建源码包需要在虚拟环境中进行
+        collector.send(Step3Message::JobError(format!(
+            "创建Python虚拟环境发生错误: {err}"
+        )));
+    } else if let Err(err) = build_sdist_wheels(&installer, &collector).await {
+        collector.send(Step3Message::JobError(format!(
+            "从源码包构建程序包发生错误: {err}"
+        )));
     } else {
-        collector.send(Step3Message::DownloadingDone(
-            installer,
-            requirements,
-            requirement_idx,
-        ));
+        collector.send(Step3Message::DownloadingDone(installer));
     }
 }
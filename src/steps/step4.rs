@@ -10,16 +10,18 @@ use fltk::{
     enums::Align,
     frame::Frame,
     group::{Flex, Group},
+    misc::Progress,
     prelude::{GroupExt, WidgetBase, WidgetExt},
 };
 
 #[derive(Debug)]
 pub enum Step4Message {
     Enter(Installer),
-    JobStart(usize),           // (job_idx)
-    JobSuccess(usize),         // (job_idx)
-    JobMessage(usize, String), // (job_idx, message)
-    JobError(usize, String),   // (job_idx, errmsg)
+    JobStart(usize),                         // (job_idx)
+    JobSuccess(usize),                       // (job_idx)
+    JobMessage(usize, String),               // (job_idx, message)
+    JobProgress(usize, usize, usize, usize), // (job_idx, finished, failed, total)
+    JobError(usize, String),                 // (job_idx, errmsg)
     Done(Installer),
 }
 
@@ -28,14 +30,19 @@ pub struct Step4Tab {
     sender: Sender<Message>,
     job_messages: Vec<Frame>,
     job_spinners: Vec<LoadingSpinner>,
+    job_percents: Vec<Frame>,
+    job_progresses: Vec<Progress>,
     installer: Option<Installer>,
 }
 
+/// 任务的进度条在收到进度之前隐藏
 fn render_job_status(
     title: &str,
     panel: &mut Flex,
     job_spinners: &mut Vec<LoadingSpinner>,
     job_messages: &mut Vec<Frame>,
+    job_percents: &mut Vec<Frame>,
+    job_progresses: &mut Vec<Progress>,
 ) {
     let mut job_flex = Flex::default_fill().row();
     panel.fixed(&job_flex, 32);
@@ -48,14 +55,35 @@ fn render_job_status(
         flex.set_margins(0, 0, 0, 0);
         flex.set_spacing(0);
         {
-            let mut message = Frame::default()
-                .with_label(title)
-                .with_align(Align::Inside | Align::Left);
-            message.set_label_size(16);
-            message.set_label_color(style::COLOR_GREY);
-            job_messages.push(message);
+            let mut msg_flex = Flex::default_fill().row();
+            {
+                let mut message = Frame::default()
+                    .with_label(title)
+                    .with_align(Align::Inside | Align::Left);
+                message.set_label_size(16);
+                message.set_label_color(style::COLOR_GREY);
+                job_messages.push(message);
+
+                let mut percent = Frame::default()
+                    .with_label("")
+                    .with_align(Align::Inside | Align::Right);
+                percent.set_label_size(12);
+                percent.set_label_color(style::COLOR_MESSAGE);
+                msg_flex.fixed(&percent, 60);
+                job_percents.push(percent);
+
+                msg_flex.end();
+            }
 
-            flex.fixed(&Frame::default(), 4);
+            let mut progress = Progress::default();
+            progress.set_color(style::COLOR_GREY);
+            progress.set_frame(fltk::enums::FrameType::FlatBox);
+            progress.set_minimum(0.0);
+            progress.set_maximum(100.0);
+            progress.set_selection_color(style::COLOR_TGU);
+            progress.hide();
+            flex.fixed(&progress, 4);
+            job_progresses.push(progress);
 
             flex.end();
         }
@@ -76,12 +104,16 @@ impl Step4Tab {
 
         let mut job_spinners: Vec<LoadingSpinner> = Vec::new();
         let mut job_messages: Vec<Frame> = Vec::new();
+        let mut job_percents: Vec<Frame> = Vec::new();
+        let mut job_progresses: Vec<Progress> = Vec::new();
 
         render_job_status(
             "安装Python本地程序包（时间较长，耐心等候）",
             &mut panel,
             &mut job_spinners,
             &mut job_messages,
+            &mut job_percents,
+            &mut job_progresses,
         );
 
         render_job_status(
@@ -89,6 +121,8 @@ impl Step4Tab {
             &mut panel,
             &mut job_spinners,
             &mut job_messages,
+            &mut job_percents,
+            &mut job_progresses,
         );
 
         render_job_status(
@@ -96,6 +130,8 @@ impl Step4Tab {
             &mut panel,
             &mut job_spinners,
             &mut job_messages,
+            &mut job_percents,
+            &mut job_progresses,
         );
 
         // let mut job1_progress: Progress;
@@ -113,6 +149,8 @@ impl Step4Tab {
             sender,
             job_spinners,
             job_messages,
+            job_percents,
+            job_progresses,
             installer: None,
         }
     }
@@ -151,12 +189,31 @@ impl Step4Tab {
             Step4Message::JobMessage(job_idx, message) => {
                 self.job_messages[job_idx].set_label(&message);
             }
+            Step4Message::JobProgress(job_idx, finished, failed, total) => {
+                self.on_job_progress(job_idx, finished, failed, total);
+            }
             msg @ _ => {
                 unimplemented!("unimplemented {msg:?}")
             }
         }
     }
 
+    fn on_job_progress(&mut self, job_idx: usize, finished: usize, failed: usize, total: usize) {
+        // 显示进度比例
+        let percent = if total > 0 {
+            (finished + failed) as f64 / total as f64 * 100.0
+        } else {
+            100.0
+        };
+
+        self.job_percents[job_idx].set_label(&format!("{finished}/{total}"));
+
+        let progress = &mut self.job_progresses[job_idx];
+        progress.show();
+        progress.set_value(percent);
+        progress.redraw();
+    }
+
     pub fn take_installer(&mut self) -> Installer {
         match self.installer.take() {
             Some(installer) => installer,
@@ -218,6 +275,15 @@ impl StatusUpdate for Step4Collector {
     fn update_downloading(&self, _status: &DownloadingStats) {
         unimplemented!()
     }
+
+    fn update_progress(&self, finished: usize, failed: usize, total: usize) {
+        self.send(Step4Message::JobProgress(
+            self.job_idx,
+            finished,
+            failed,
+            total,
+        ));
+    }
 }
 
 pub async fn step4_run(mut installer: Installer, mut collector: Step4Collector) {
@@ -226,7 +292,7 @@ pub async fn step4_run(mut installer: Installer, mut collector: Step4Collector)
         set_platform_info,
     };
 
-    if installer.platform_tag.is_none() {
+    if installer.marker_env.is_none() {
         if let Err(err) = set_platform_info(&mut installer) {
             collector.job_error(format!("获取系统平台信息发生错误: {err}"));
             return;
@@ -234,7 +300,7 @@ pub async fn step4_run(mut installer: Installer, mut collector: Step4Collector)
     }
 
     collector.job_start();
-    if let Err(err) = offline_install_requirements(&installer).await {
+    if let Err(err) = offline_install_requirements(&installer, &collector).await {
         collector.job_error(format!("本地安装程序包发生错误: {err}"));
         return;
     };